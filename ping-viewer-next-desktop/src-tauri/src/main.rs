@@ -10,31 +10,61 @@ async fn main() {
 
     logger::manager::init();
 
-    let (manager, handler) = device::manager::DeviceManager::new(10);
+    let (mut manager, handler) = device::manager::DeviceManager::new(10);
+
+    if cli::manager::is_reset() {
+        device::manager::registry::reset(&device::manager::registry::get_registry_path());
+    }
+    manager.restore_registry().await;
 
     tokio::spawn(async move { manager.run().await });
 
     run_tauri_app(handler).await;
 }
 
+// The window loads the API over plain HTTP without a token, so it is only served to this computer
+fn local_server_address() -> String {
+    let address = cli::manager::server_address();
+    let port = address.rsplit_once(':').map_or("8080", |(_, port)| port);
+    format!("127.0.0.1:{port}")
+}
+
 async fn run_tauri_app(handler: device::manager::ManagerActorHandler) {
+    let server_handler = handler.clone();
+    let server_address = local_server_address();
     tauri::Builder::default()
-        .setup(|app: &mut tauri::App| {
+        .setup(move |app: &mut tauri::App| {
             let window = app.get_webview_window("main").unwrap();
 
+            let address = server_address.clone();
             std::thread::spawn(move || {
-                run_from_tauri(&cli::manager::server_address(), handler).unwrap();
+                run_from_tauri(&address, server_handler).unwrap();
             });
 
             std::thread::spawn(move || {
                 std::thread::sleep(std::time::Duration::from_secs(6));
-                window.eval("window.location.replace('http://127.0.0.1:8080')").unwrap();
+                window
+                    .eval(&format!(
+                        "window.location.replace('http://{server_address}')"
+                    ))
+                    .unwrap();
             });
 
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(move |_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // The app runtime is blocking this thread, so devices are shut down from a new one
+                let handler = handler.clone();
+                std::thread::spawn(move || {
+                    tauri::async_runtime::block_on(server::manager::shutdown(&handler))
+                })
+                .join()
+                .unwrap();
+            }
+        });
 }
 
 #[actix_web::main]
@@ -42,5 +72,12 @@ pub async fn run_from_tauri(
     server_address: &str,
     handler: device::manager::ManagerActorHandler,
 ) -> std::io::Result<()> {
-    server::manager::run(server_address, handler).await
+    // Bound to the loopback interface, the API needs no token
+    server::manager::run(
+        server_address,
+        handler,
+        server::auth::ApiTokens::default(),
+        None,
+    )
+    .await
 }
@@ -0,0 +1,62 @@
+/**
+ * plugins/apiToken.js
+ *
+ * Sends the API token to the server when it is started with tokens.
+ * The token is taken once from the page address, e.g. /?token=..., and kept for the next visits.
+ */
+
+const STORAGE_KEY = 'ping-viewer-next-token';
+
+// Only the server API gets the token, other services like MAVLink websockets never see it
+const isApiPath = (pathname) =>
+  /^(\/v1)?\/(device_manager\/|ws$|metrics$)/.test(pathname) || pathname.startsWith('/v2/');
+
+const readToken = () => {
+  const params = new URLSearchParams(window.location.search);
+  const token = params.get('token');
+  if (token) {
+    window.localStorage.setItem(STORAGE_KEY, token);
+    return token;
+  }
+  return window.localStorage.getItem(STORAGE_KEY);
+};
+
+const apiUrl = (input) => {
+  try {
+    const url = new URL(input instanceof Request ? input.url : input, window.location.href);
+    return isApiPath(url.pathname) ? url : null;
+  } catch {
+    return null;
+  }
+};
+
+export function installApiToken() {
+  const token = readToken();
+  if (!token) {
+    return;
+  }
+
+  const fetch = window.fetch.bind(window);
+  window.fetch = (input, init = {}) => {
+    if (!apiUrl(input)) {
+      return fetch(input, init);
+    }
+    const headers = new Headers(init.headers ?? (input instanceof Request ? input.headers : {}));
+    headers.set('Authorization', `Bearer ${token}`);
+    return fetch(input, { ...init, headers });
+  };
+
+  // Browsers can't set headers on websockets, the server also accepts the token as ?token=
+  const WebSocket = window.WebSocket;
+  window.WebSocket = class extends WebSocket {
+    constructor(address, protocols) {
+      const url = apiUrl(address);
+      if (url) {
+        url.searchParams.set('token', token);
+        super(url.toString(), protocols);
+      } else {
+        super(address, protocols);
+      }
+    }
+  };
+}
@@ -5,9 +5,11 @@
  */
 
 import router from '@/router';
+import { installApiToken } from './apiToken';
 // Plugins
 import vuetify from './vuetify';
 
 export function registerPlugins(app) {
+  installApiToken();
   app.use(vuetify).use(router);
 }
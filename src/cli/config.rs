@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::manager::{discovery_service::DiscoveryConfig, registry::ConfiguredDevice};
+
+// Every command line flag can be set here, command line and environment variables take precedence
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub enable_auto_create: Option<bool>,
+    /// Deletes the settings file on every start while set
+    pub reset: Option<bool>,
+    pub settings_path: Option<String>,
+    pub verbose: Option<bool>,
+    pub log_path: Option<String>,
+    pub recording_path: Option<String>,
+    pub enable_tracing_level_log_file: Option<bool>,
+    pub log_include_all_dependencies: Option<bool>,
+    pub enable_tracy: Option<bool>,
+    pub vehicle_pose: Option<String>,
+    pub server: ServerConfig,
+    pub discovery: DiscoveryConfig,
+    pub devices: Vec<ConfiguredDevice>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Same as --rest-server
+    pub address: Option<String>,
+    pub auth_tokens: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_self_signed: Option<bool>,
+}
+
+// e.g. ~/.config/ping-viewer-next/config.toml on Linux, falling back to the working directory
+pub fn default_path() -> PathBuf {
+    dirs::config_dir()
+        .map(|directory| directory.join("ping-viewer-next"))
+        .unwrap_or_default()
+        .join("config.toml")
+}
+
+pub fn load(path: &Path) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read config file {}: {err}", path.display()))?;
+    toml::from_str(&content)
+        .map_err(|err| format!("Failed to parse config file {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::manager::{DeviceSelection, SourceSelection};
+
+    #[test]
+    fn test_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+            verbose = true
+            log_path = "/var/log/ping-viewer-next"
+
+            [server]
+            address = "0.0.0.0:6060"
+            tls_self_signed = true
+
+            [discovery]
+            interval = 60
+            serial = false
+
+            [[devices]]
+            source = { SerialStream = { path = "/dev/ttyUSB0", baudrate = 115200 } }
+            device_selection = "Ping1D"
+            name = "Altimeter"
+            nmea_output = { udp_address = "192.168.2.1:10110" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.verbose, Some(true));
+        assert_eq!(config.enable_tracy, None);
+        assert_eq!(config.server.address.as_deref(), Some("0.0.0.0:6060"));
+        assert_eq!(config.discovery.interval, 60);
+        assert!(!config.discovery.serial);
+        assert!(config.discovery.network);
+
+        let device = &config.devices[0];
+        assert!(matches!(device.source, SourceSelection::SerialStream(_)));
+        assert_eq!(device.device_selection, DeviceSelection::Ping1D);
+        let nmea = device.nmea_output.as_ref().unwrap();
+        assert_eq!(nmea.udp_address.as_deref(), Some("192.168.2.1:10110"));
+        assert_eq!(nmea.tcp_address, None);
+
+        assert!(toml::from_str::<Config>("rest_server = \"0.0.0.0:80\"").is_err());
+    }
+}
@@ -1,57 +1,235 @@
 use clap;
-use clap::Parser;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
 use lazy_static::lazy_static;
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
+
+use super::config::{self, Config};
 
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = env!("CARGO_PKG_DESCRIPTION"))]
 struct Args {
+    /// Specifies the path of the TOML configuration file, command line and environment variables take precedence over it.
+    #[arg(long, value_name = "PATH", env = "PING_VIEWER_NEXT_CONFIG")]
+    config: Option<String>,
+
     /// Call AutoCreate on DeviceManager during application startup.
-    #[arg(long, default_value = "false")]
+    #[arg(
+        long,
+        default_value = "false",
+        env = "PING_VIEWER_NEXT_ENABLE_AUTO_CREATE"
+    )]
     enable_auto_create: bool,
 
     /// Deletes settings file before starting.
-    #[arg(long)]
+    #[arg(long, env = "PING_VIEWER_NEXT_RESET")]
     reset: bool,
 
+    /// Specifies the path of the settings file in which the created devices will be stored.
+    #[arg(
+        long,
+        default_value = "./settings.json",
+        env = "PING_VIEWER_NEXT_SETTINGS_PATH"
+    )]
+    settings_path: Option<String>,
+
     /// Sets the address for the REST API server
-    #[arg(long, value_name = "IP>:<PORT", default_value = "0.0.0.0:8080")]
+    #[arg(
+        long,
+        value_name = "IP>:<PORT",
+        default_value = "0.0.0.0:8080",
+        env = "PING_VIEWER_NEXT_REST_SERVER"
+    )]
     rest_server: String,
 
     /// Turns all log categories up to Debug, for more information check RUST_LOG env variable.
-    #[arg(short, long)]
+    #[arg(short, long, env = "PING_VIEWER_NEXT_VERBOSE")]
     verbose: bool,
 
-    /// Specifies the path in witch the logs will be stored.
-    #[arg(long, default_value = "./logs")]
+    /// Specifies the path in which the logs will be stored.
+    #[arg(long, default_value = "./logs", env = "PING_VIEWER_NEXT_LOG_PATH")]
     log_path: Option<String>,
 
+    /// Specifies the path in which the device recordings will be stored.
+    #[arg(
+        long,
+        default_value = "./recordings",
+        env = "PING_VIEWER_NEXT_RECORDING_PATH"
+    )]
+    recording_path: Option<String>,
+
     /// Turns all log categories up to Trace to the log file, for more information check RUST_LOG env variable.
-    #[arg(long)]
+    #[arg(long, env = "PING_VIEWER_NEXT_ENABLE_TRACING_LEVEL_LOG_FILE")]
     enable_tracing_level_log_file: bool,
 
     /// Filter to show only own crate related logs
-    #[arg(long, default_value = "false")]
+    #[arg(
+        long,
+        default_value = "false",
+        env = "PING_VIEWER_NEXT_LOG_INCLUDE_ALL_DEPENDENCIES"
+    )]
     log_include_all_dependencies: bool,
 
     /// Turns on the Tracy tool integration.
-    #[arg(long)]
+    #[arg(long, env = "PING_VIEWER_NEXT_ENABLE_TRACY")]
     enable_tracy: bool,
+
+    /// Source of the vehicle heading used to stabilize Ping360 data, e.g. udpin:0.0.0.0:14550 or ws://blueos.local:6040/ws/mavlink?filter=ATTITUDE
+    #[arg(long, value_name = "SOURCE", env = "PING_VIEWER_NEXT_VEHICLE_POSE")]
+    vehicle_pose: Option<String>,
+
+    /// Specifies the path of a JSON file with the API tokens, e.g. [{"name": "cockpit", "token": "...", "role": "ReadOnly"}], roles are ReadOnly or Operator. The web interface is opened once with ?token=... to use one.
+    #[arg(long, value_name = "PATH", env = "PING_VIEWER_NEXT_AUTH_TOKENS")]
+    auth_tokens: Option<String>,
+
+    /// Specifies the path of the PEM certificate chain, enables HTTPS and WSS together with --tls-key.
+    #[arg(long, value_name = "PATH", env = "PING_VIEWER_NEXT_TLS_CERT")]
+    tls_cert: Option<String>,
+
+    /// Specifies the path of the PEM private key of the certificate.
+    #[arg(long, value_name = "PATH", env = "PING_VIEWER_NEXT_TLS_KEY")]
+    tls_key: Option<String>,
+
+    /// Generates a self-signed certificate when the certificate files don't exist, by default at ./tls/cert.pem and ./tls/key.pem.
+    #[arg(long, env = "PING_VIEWER_NEXT_TLS_SELF_SIGNED")]
+    tls_self_signed: bool,
 }
 
 #[derive(Debug)]
 struct Manager {
     clap_matches: Args,
+    config: Config,
 }
 
 lazy_static! {
     static ref MANAGER: Arc<Manager> = Arc::new(Manager::new());
 }
 
+// File values only replace the ones neither given on the command line nor by environment variables
+macro_rules! fill_from_file {
+    ($matches:expr, $arg:expr, $id:literal, $value:expr) => {
+        if let Some(value) = $value {
+            if !matches!(
+                $matches.value_source($id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            ) {
+                $arg = value;
+            }
+        }
+    };
+}
+
+impl Args {
+    fn fill_from_file(&mut self, config: &Config, matches: &ArgMatches) {
+        let server = &config.server;
+        fill_from_file!(
+            matches,
+            self.enable_auto_create,
+            "enable_auto_create",
+            config.enable_auto_create
+        );
+        fill_from_file!(matches, self.reset, "reset", config.reset);
+        fill_from_file!(
+            matches,
+            self.settings_path,
+            "settings_path",
+            config.settings_path.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.rest_server,
+            "rest_server",
+            server.address.clone()
+        );
+        fill_from_file!(matches, self.verbose, "verbose", config.verbose);
+        fill_from_file!(
+            matches,
+            self.log_path,
+            "log_path",
+            config.log_path.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.recording_path,
+            "recording_path",
+            config.recording_path.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.enable_tracing_level_log_file,
+            "enable_tracing_level_log_file",
+            config.enable_tracing_level_log_file
+        );
+        fill_from_file!(
+            matches,
+            self.log_include_all_dependencies,
+            "log_include_all_dependencies",
+            config.log_include_all_dependencies
+        );
+        fill_from_file!(
+            matches,
+            self.enable_tracy,
+            "enable_tracy",
+            config.enable_tracy
+        );
+        fill_from_file!(
+            matches,
+            self.vehicle_pose,
+            "vehicle_pose",
+            config.vehicle_pose.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.auth_tokens,
+            "auth_tokens",
+            server.auth_tokens.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.tls_cert,
+            "tls_cert",
+            server.tls_cert.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.tls_key,
+            "tls_key",
+            server.tls_key.clone().map(Some)
+        );
+        fill_from_file!(
+            matches,
+            self.tls_self_signed,
+            "tls_self_signed",
+            server.tls_self_signed
+        );
+    }
+}
+
 impl Manager {
     fn new() -> Self {
+        let matches = Args::command().get_matches();
+        let mut clap_matches = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+        // A missing file is only an error when its path was given, logs are not available yet
+        let config = match &clap_matches.config {
+            Some(path) => Some(PathBuf::from(
+                shellexpand::full(path)
+                    .expect("Failed to expand path")
+                    .to_string(),
+            )),
+            None => Some(config::default_path()).filter(|path| path.exists()),
+        }
+        .map(|path| {
+            config::load(&path).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or_default();
+
+        clap_matches.fill_from_file(&config, &matches);
         Self {
-            clap_matches: Args::parse(),
+            clap_matches,
+            config,
         }
     }
 }
@@ -82,6 +260,41 @@ pub fn is_enable_auto_create() -> bool {
     MANAGER.clap_matches.enable_auto_create
 }
 
+pub fn is_reset() -> bool {
+    MANAGER.clap_matches.reset
+}
+
+pub fn vehicle_pose() -> Option<String> {
+    MANAGER.clap_matches.vehicle_pose.clone()
+}
+
+pub fn auth_tokens_path() -> Option<String> {
+    MANAGER.clap_matches.auth_tokens.as_ref().map(|path| {
+        shellexpand::full(path)
+            .expect("Failed to expand path")
+            .to_string()
+    })
+}
+
+// TLS is enabled by the certificate paths or by asking for a self-signed certificate
+pub fn tls() -> Option<crate::server::tls::TlsConfig> {
+    let args = &MANAGER.clap_matches;
+    if args.tls_cert.is_none() && !args.tls_self_signed {
+        return None;
+    }
+
+    let expand = |path: &Option<String>, default: &str| {
+        shellexpand::full(path.as_deref().unwrap_or(default))
+            .expect("Failed to expand path")
+            .to_string()
+    };
+    Some(crate::server::tls::TlsConfig {
+        cert_path: expand(&args.tls_cert, "./tls/cert.pem"),
+        key_path: expand(&args.tls_key, "./tls/key.pem"),
+        self_signed: args.tls_self_signed,
+    })
+}
+
 pub fn log_path() -> String {
     let log_path =
         MANAGER.clap_matches.log_path.clone().expect(
@@ -93,6 +306,35 @@ pub fn log_path() -> String {
         .to_string()
 }
 
+pub fn recording_path() -> String {
+    let recording_path = MANAGER.clap_matches.recording_path.clone().expect(
+        "Clap arg \"recording-path\" should always be \"Some(_)\" because of the default value.",
+    );
+
+    shellexpand::full(&recording_path)
+        .expect("Failed to expand path")
+        .to_string()
+}
+
+pub fn settings_path() -> String {
+    let settings_path = MANAGER.clap_matches.settings_path.clone().expect(
+        "Clap arg \"settings-path\" should always be \"Some(_)\" because of the default value.",
+    );
+
+    shellexpand::full(&settings_path)
+        .expect("Failed to expand path")
+        .to_string()
+}
+
+pub fn discovery() -> crate::device::manager::discovery_service::DiscoveryConfig {
+    MANAGER.config.discovery.clone()
+}
+
+// Devices declared on the configuration file, created at startup
+pub fn configured_devices() -> Vec<crate::device::manager::registry::ConfiguredDevice> {
+    MANAGER.config.devices.clone()
+}
+
 // Return the desired address for the REST API
 pub fn server_address() -> String {
     MANAGER.clap_matches.rest_server.clone()
@@ -116,4 +358,54 @@ mod tests {
     fn default_arguments() {
         assert!(!is_verbose());
     }
+
+    #[test]
+    fn test_fill_from_file_precedence() {
+        let config: Config = toml::from_str(
+            r#"
+            log_path = "/file/logs"
+            recording_path = "/file/recordings"
+            settings_path = "/file/settings.json"
+            verbose = true
+
+            [server]
+            address = "0.0.0.0:6060"
+            "#,
+        )
+        .unwrap();
+        let matches = Args::command()
+            .try_get_matches_from([
+                "ping-viewer-next",
+                "--settings-path",
+                "/cli/settings.json",
+                "--log-path",
+                "/cli/logs",
+            ])
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.fill_from_file(&config, &matches);
+
+        // Command line over environment and file
+        assert_eq!(args.settings_path.as_deref(), Some("/cli/settings.json"));
+        assert_eq!(args.log_path.as_deref(), Some("/cli/logs"));
+        // File over defaults
+        assert_eq!(args.recording_path.as_deref(), Some("/file/recordings"));
+        assert_eq!(args.rest_server, "0.0.0.0:6060");
+        assert!(args.verbose);
+        // Defaults when not set anywhere
+        assert_eq!(args.tls_cert, None);
+        assert!(!args.enable_tracy);
+
+        // Tests share the process environment with MANAGER, so the log path reads PATH instead of a variable set here
+        let matches = Args::command()
+            .mut_arg("log_path", |arg| arg.env("PATH"))
+            .try_get_matches_from(["ping-viewer-next"])
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.fill_from_file(&config, &matches);
+
+        // Environment over file
+        assert_eq!(args.log_path, std::env::var("PATH").ok());
+        assert_eq!(args.settings_path.as_deref(), Some("/file/settings.json"));
+    }
 }
@@ -1,3 +1,5 @@
+use std::{sync::Arc, time::Instant};
+
 use bluerobotics_ping::device::PingDevice;
 use paperclip::actix::Apiv2Schema;
 use serde::{Deserialize, Serialize};
@@ -173,7 +175,7 @@ impl DeviceActor {
                         return Err(DeviceError::PingError(e));
                     }
                 };
-                if device_type_check == 1 {
+                if device_type_check == 100 {
                     return Ok(PingAnswer::UpgradeResult(UpgradeResult::Tsr1000));
                 };
                 device_type_check
@@ -226,12 +228,21 @@ impl DeviceActor {
     }
 
     pub fn new(device: DeviceType, size: usize) -> (Self, DeviceActorHandler) {
+        Self::with_health(device, size, Arc::default())
+    }
+
+    // Same as new, sharing the link statistics already collected by the device stream
+    pub fn with_health(
+        device: DeviceType,
+        size: usize,
+        health: Arc<super::health::LinkHealth>,
+    ) -> (Self, DeviceActorHandler) {
         let (sender, receiver) = mpsc::channel(size);
         let actor = DeviceActor {
             receiver,
             device_type: device,
         };
-        let actor_handler = DeviceActorHandler { sender };
+        let actor_handler = DeviceActorHandler { sender, health };
 
         trace!("Device and handler successfully created: Success");
         (actor, actor_handler)
@@ -241,9 +252,37 @@ impl DeviceActor {
 #[derive(Clone, Debug)]
 pub struct DeviceActorHandler {
     pub sender: mpsc::Sender<DeviceActorRequest>,
+    pub health: Arc<super::health::LinkHealth>,
 }
 impl DeviceActorHandler {
     pub async fn send(&self, device_request: PingRequest) -> Result<PingAnswer, DeviceError> {
+        // Only requests that reach the device are accounted in the link statistics
+        let accounted = matches!(
+            device_request,
+            PingRequest::Ping1D(_)
+                | PingRequest::Ping360(_)
+                | PingRequest::Tsr1000(_)
+                | PingRequest::Common(_)
+        );
+        let start = Instant::now();
+
+        let answer = self.send_inner(device_request).await;
+
+        if accounted {
+            let timeout = matches!(
+                answer,
+                Err(DeviceError::PingError(
+                    bluerobotics_ping::error::PingError::TimeoutError
+                ))
+            );
+            self.health
+                .record_request(start.elapsed(), answer.is_err(), timeout);
+        }
+
+        answer
+    }
+
+    async fn send_inner(&self, device_request: PingRequest) -> Result<PingAnswer, DeviceError> {
         let (result_sender, result_receiver) = oneshot::channel();
 
         let device_request = DeviceActorRequest {
@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bluerobotics_ping::{
+    decoder::{Decoder, DecoderResult, ParseError},
+    message::ProtocolMessage,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::broadcast,
+};
+
+// Message rates are computed over windows of this length
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+// Weight of the newest sample in the average latency
+const LATENCY_SMOOTHING: f32 = 0.1;
+// Frames kept for subscribers that fall behind
+const FRAME_CHANNEL_SIZE: usize = 100;
+
+// Shared by every device, so monotonic timestamps of different devices can be compared
+static MONOTONIC_ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+// Time a frame was read from the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameTimestamp {
+    /// Microseconds since the UNIX epoch
+    pub utc_us: i64,
+    /// Microseconds since the server started, not affected by system clock changes
+    pub monotonic_us: u64,
+}
+
+impl FrameTimestamp {
+    pub fn now() -> Self {
+        let origin = *MONOTONIC_ORIGIN.get_or_init(Instant::now);
+        Self {
+            utc_us: chrono::Utc::now().timestamp_micros(),
+            monotonic_us: origin.elapsed().as_micros() as u64,
+        }
+    }
+
+    pub fn instant(&self) -> Instant {
+        *MONOTONIC_ORIGIN.get_or_init(Instant::now) + Duration::from_micros(self.monotonic_us)
+    }
+}
+
+// A message as read from the link, stamped when its last byte arrived
+#[derive(Debug, Clone)]
+pub struct ReceivedFrame {
+    pub timestamp: FrameTimestamp,
+    /// Vehicle heading in degrees when the frame was read, the recorded one on replays
+    pub heading: Option<f32>,
+    pub message: ProtocolMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessageStatistics {
+    pub message_id: u16,
+    pub count: u64,
+    pub rate_hz: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LatencyStatistics {
+    pub last_ms: f32,
+    pub average_ms: f32,
+    pub max_ms: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SensorReadings {
+    /// Device 5V rail, in volts
+    pub voltage_5: Option<f32>,
+    /// Processor temperature, in degrees Celsius
+    pub processor_temperature: Option<f32>,
+    /// PCB temperature, in degrees Celsius
+    pub pcb_temperature: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LinkStatistics {
+    pub messages: Vec<MessageStatistics>,
+    pub total_messages: u64,
+    pub parser_errors: u64,
+    pub checksum_errors: u64,
+    pub requests: u64,
+    pub request_errors: u64,
+    pub request_timeouts: u64,
+    pub latency: Option<LatencyStatistics>,
+    pub last_seen: Option<String>,
+    pub seconds_since_last_seen: Option<f32>,
+    pub sensors: SensorReadings,
+}
+
+#[derive(Debug)]
+struct MessageCounter {
+    count: u64,
+    window_start: Instant,
+    window_count: u32,
+    rate_hz: f32,
+}
+
+impl MessageCounter {
+    fn new(now: Instant) -> Self {
+        Self {
+            count: 0,
+            window_start: now,
+            window_count: 0,
+            rate_hz: 0.0,
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.roll(now);
+        self.count += 1;
+        self.window_count += 1;
+    }
+
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= RATE_WINDOW {
+            self.rate_hz = self.window_count as f32 / elapsed.as_secs_f32();
+            self.window_start = now;
+            self.window_count = 0;
+        }
+    }
+
+    fn rate(&self, now: Instant) -> f32 {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= RATE_WINDOW {
+            self.window_count as f32 / elapsed.as_secs_f32()
+        } else {
+            self.rate_hz
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LinkHealthState {
+    messages: HashMap<u16, MessageCounter>,
+    parser_errors: u64,
+    checksum_errors: u64,
+    requests: u64,
+    request_errors: u64,
+    request_timeouts: u64,
+    latency: Option<LatencyStatistics>,
+    last_seen: Option<(Instant, chrono::DateTime<chrono::Utc>)>,
+    sensors: SensorReadings,
+}
+
+// Counters shared between the device stream, the device handler and the manager
+#[derive(Debug)]
+pub struct LinkHealth {
+    state: Mutex<LinkHealthState>,
+    sensor_poll: AtomicBool,
+    frames: broadcast::Sender<ReceivedFrame>,
+}
+
+impl Default for LinkHealth {
+    fn default() -> Self {
+        Self {
+            state: Mutex::default(),
+            sensor_poll: AtomicBool::default(),
+            frames: broadcast::channel(FRAME_CHANNEL_SIZE).0,
+        }
+    }
+}
+
+impl LinkHealth {
+    // Every message read from the link, with the time it was read
+    pub fn subscribe(&self) -> broadcast::Receiver<ReceivedFrame> {
+        self.frames.subscribe()
+    }
+
+    // Returns false if a sensor poll is already running, so slow devices don't pile requests up
+    pub fn begin_sensor_poll(&self) -> bool {
+        !self.sensor_poll.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn end_sensor_poll(&self) {
+        self.sensor_poll.store(false, Ordering::Release);
+    }
+
+    pub fn record_message(&self, message: ProtocolMessage) {
+        let timestamp = FrameTimestamp::now();
+        self.record_frame(ReceivedFrame {
+            timestamp,
+            heading: crate::mavlink::pose::heading_at(timestamp.instant()),
+            message,
+        });
+    }
+
+    pub fn record_frame(&self, frame: ReceivedFrame) {
+        let now = Instant::now();
+        if let Ok(mut state) = self.state.lock() {
+            state
+                .messages
+                .entry(frame.message.message_id)
+                .or_insert_with(|| MessageCounter::new(now))
+                .record(now);
+            state.last_seen = Some((now, chrono::Utc::now()));
+        }
+        // No subscribers is not an error, the frame is only counted
+        let _ = self.frames.send(frame);
+    }
+
+    pub fn record_parse_error(&self, error: &ParseError) {
+        if let Ok(mut state) = self.state.lock() {
+            match error {
+                ParseError::ChecksumError(_) => state.checksum_errors += 1,
+                _ => state.parser_errors += 1,
+            }
+        }
+    }
+
+    pub fn record_request(&self, latency: Duration, error: bool, timeout: bool) {
+        let latency_ms = latency.as_secs_f32() * 1000.0;
+        if let Ok(mut state) = self.state.lock() {
+            state.requests += 1;
+            if timeout {
+                state.request_timeouts += 1;
+                return;
+            }
+            if error {
+                state.request_errors += 1;
+                return;
+            }
+            state.latency = Some(match state.latency.take() {
+                Some(latency) => LatencyStatistics {
+                    last_ms: latency_ms,
+                    average_ms: latency.average_ms
+                        + LATENCY_SMOOTHING * (latency_ms - latency.average_ms),
+                    max_ms: latency.max_ms.max(latency_ms),
+                },
+                None => LatencyStatistics {
+                    last_ms: latency_ms,
+                    average_ms: latency_ms,
+                    max_ms: latency_ms,
+                },
+            });
+        }
+    }
+
+    pub fn update_sensors(&self, update: impl FnOnce(&mut SensorReadings)) {
+        if let Ok(mut state) = self.state.lock() {
+            update(&mut state.sensors);
+        }
+    }
+
+    pub fn statistics(&self) -> Option<LinkStatistics> {
+        let now = Instant::now();
+        let state = self.state.lock().ok()?;
+
+        let mut messages: Vec<MessageStatistics> = state
+            .messages
+            .iter()
+            .map(|(message_id, counter)| MessageStatistics {
+                message_id: *message_id,
+                count: counter.count,
+                rate_hz: counter.rate(now),
+            })
+            .collect();
+        messages.sort_by_key(|message| message.message_id);
+
+        Some(LinkStatistics {
+            total_messages: messages.iter().map(|message| message.count).sum(),
+            messages,
+            parser_errors: state.parser_errors,
+            checksum_errors: state.checksum_errors,
+            requests: state.requests,
+            request_errors: state.request_errors,
+            request_timeouts: state.request_timeouts,
+            latency: state.latency.clone(),
+            last_seen: state.last_seen.map(|(_, timestamp)| timestamp.to_rfc3339()),
+            seconds_since_last_seen: state
+                .last_seen
+                .map(|(instant, _)| now.duration_since(instant).as_secs_f32()),
+            sensors: state.sensors.clone(),
+        })
+    }
+}
+
+// Wraps a device stream, decoding a copy of the incoming bytes to account messages and errors
+pub struct MonitoredStream<S> {
+    inner: S,
+    decoder: Decoder,
+    health: Arc<LinkHealth>,
+}
+
+impl<S> MonitoredStream<S> {
+    pub fn new(inner: S, health: Arc<LinkHealth>) -> Self {
+        Self {
+            inner,
+            decoder: Decoder::new(),
+            health,
+        }
+    }
+
+    fn inspect(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            match self.decoder.parse_byte(*byte) {
+                DecoderResult::Success(message) => self.health.record_message(message),
+                DecoderResult::Error(error) => self.health.record_parse_error(&error),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MonitoredStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &result {
+            self.inspect(&buf.filled()[filled..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MonitoredStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_monitored_stream_statistics() {
+        let health = Arc::new(LinkHealth::default());
+        let (remote, local) = tokio::io::duplex(1024);
+        let mut local = MonitoredStream::new(local, health.clone());
+        let mut remote = remote;
+        let mut frames = health.subscribe();
+
+        // ProtocolVersion GeneralRequest, followed by the same frame with a broken checksum
+        let frame = [b'B', b'R', 2, 0, 6, 0, 0, 0, 5, 0, 0xa1, 0x00];
+        let mut broken = frame;
+        broken[10] = 0;
+        remote.write_all(&frame).await.unwrap();
+        remote.write_all(&broken).await.unwrap();
+
+        let mut buffer = [0u8; 24];
+        local.read_exact(&mut buffer).await.unwrap();
+
+        let statistics = health.statistics().unwrap();
+        assert_eq!(statistics.total_messages, 1);
+        assert_eq!(statistics.messages[0].message_id, 6);
+        assert_eq!(statistics.checksum_errors, 1);
+        assert!(statistics.last_seen.is_some());
+
+        let received = frames.try_recv().unwrap();
+        assert_eq!(received.message.message_id, 6);
+        assert_eq!(received.message.serialized(), frame.to_vec());
+        assert!(received.timestamp.instant() <= Instant::now());
+        assert!(frames.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_request_statistics() {
+        let health = LinkHealth::default();
+        health.record_request(Duration::from_millis(10), false, false);
+        health.record_request(Duration::from_millis(30), false, false);
+        health.record_request(Duration::from_secs(1), true, true);
+
+        let statistics = health.statistics().unwrap();
+        assert_eq!(statistics.requests, 3);
+        assert_eq!(statistics.request_timeouts, 1);
+        let latency = statistics.latency.unwrap();
+        assert_eq!(latency.last_ms, 30.0);
+        assert_eq!(latency.max_ms, 30.0);
+        assert!((latency.average_ms - 12.0).abs() < 0.01);
+    }
+}
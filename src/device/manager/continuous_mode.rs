@@ -4,8 +4,10 @@ use uuid::Uuid;
 
 use crate::device::{
     devices::DeviceActorHandler,
+    health::{FrameTimestamp, ReceivedFrame},
     manager::{Answer, DeviceAnswer, DeviceManager, DeviceSelection, ManagerError},
 };
+use crate::server::protocols::v1::subscription::Topic;
 
 use super::{DeviceProperties, Ping360Properties};
 
@@ -13,9 +15,7 @@ impl DeviceManager {
     // Call the helpers specifically for each device type
     pub async fn continuous_mode_start(
         &mut self,
-        mut subscriber: tokio::sync::broadcast::Receiver<
-            bluerobotics_ping::message::ProtocolMessage,
-        >,
+        mut subscriber: tokio::sync::broadcast::Receiver<ReceivedFrame>,
         device_id: Uuid,
         device_type: DeviceSelection,
     ) -> Option<tokio::task::JoinHandle<()>> {
@@ -39,8 +39,12 @@ impl DeviceManager {
             DeviceSelection::Ping1D => Some(tokio::spawn(async move {
                 loop {
                     match subscriber.recv().await {
-                        Ok(msg) => {
-                            Self::ping1d_continuous_mode_helper(msg, device_id);
+                        Ok(frame) => {
+                            Self::ping1d_continuous_mode_helper(
+                                frame.message,
+                                device_id,
+                                frame.timestamp,
+                            );
                         }
                         Err(err) => {
                             Self::handle_error_continuous_mode(err, device_id);
@@ -52,8 +56,12 @@ impl DeviceManager {
             DeviceSelection::Tsr1000 => Some(tokio::spawn(async move {
                 loop {
                     match subscriber.recv().await {
-                        Ok(msg) => {
-                            Self::ping1d_continuous_mode_helper(msg, device_id);
+                        Ok(frame) => {
+                            Self::tsr1000_continuous_mode_helper(
+                                frame.message,
+                                device_id,
+                                frame.timestamp,
+                            );
                         }
                         Err(err) => {
                             Self::handle_error_continuous_mode(err, device_id);
@@ -97,19 +105,36 @@ impl DeviceManager {
         device_id: Uuid,
         device_type: DeviceSelection,
     ) -> Result<(), ManagerError> {
-        if device_type == DeviceSelection::Ping1D {
-            let handler_request = self.get_device_handler(device_id).await?;
-            let handler = self.extract_handler(handler_request)?;
+        match device_type {
+            DeviceSelection::Ping1D => {
+                let handler_request = self.get_device_handler(device_id).await?;
+                let handler = self.extract_handler(handler_request)?;
 
-            let id = <bluerobotics_ping::ping1d::ProfileStruct as bluerobotics_ping::message::MessageInfo>::id();
-            let _ = handler
-                .send(crate::device::devices::PingRequest::Ping1D(
-                    crate::device::devices::Ping1DRequest::ContinuousStart(
-                        bluerobotics_ping::ping1d::ContinuousStartStruct { id },
-                    ),
-                ))
-                .await
-                .map_err(|err| {trace!("Something went wrong while executing continuous_mode_startup, details: {err:?}"); ManagerError::DeviceError(err)})?;
+                let id = <bluerobotics_ping::ping1d::ProfileStruct as bluerobotics_ping::message::MessageInfo>::id();
+                let _ = handler
+                    .send(crate::device::devices::PingRequest::Ping1D(
+                        crate::device::devices::Ping1DRequest::ContinuousStart(
+                            bluerobotics_ping::ping1d::ContinuousStartStruct { id },
+                        ),
+                    ))
+                    .await
+                    .map_err(|err| {trace!("Something went wrong while executing continuous_mode_startup, details: {err:?}"); ManagerError::DeviceError(err)})?;
+            }
+            DeviceSelection::Tsr1000 => {
+                let handler_request = self.get_device_handler(device_id).await?;
+                let handler = self.extract_handler(handler_request)?;
+
+                let id = <bluerobotics_ping::tsr1000::ProfileStruct as bluerobotics_ping::message::MessageInfo>::id();
+                let _ = handler
+                    .send(crate::device::devices::PingRequest::Tsr1000(
+                        crate::device::devices::Tsr1000Request::ContinuousStart(
+                            bluerobotics_ping::tsr1000::ContinuousStartStruct { id },
+                        ),
+                    ))
+                    .await
+                    .map_err(|err| {trace!("Something went wrong while executing continuous_mode_startup, details: {err:?}"); ManagerError::DeviceError(err)})?;
+            }
+            _ => {}
         }
         Ok(())
     }
@@ -137,6 +162,19 @@ impl DeviceManager {
                     error!("Something went wrong while executing continuous_mode_shutdown_routine, details: {err:?}, device: {device_id}");
                 }
             }
+            DeviceSelection::Tsr1000 => {
+                let id = <bluerobotics_ping::tsr1000::ProfileStruct as bluerobotics_ping::message::MessageInfo>::id();
+                if let Err(err) = handler
+                    .send(crate::device::devices::PingRequest::Tsr1000(
+                        crate::device::devices::Tsr1000Request::ContinuousStop(
+                            bluerobotics_ping::tsr1000::ContinuousStopStruct { id },
+                        ),
+                    ))
+                    .await
+                {
+                    error!("Something went wrong while executing continuous_mode_shutdown_routine, details: {err:?}, device: {device_id}");
+                }
+            }
             DeviceSelection::Ping360 => {
                 if let Err(err) = handler
                     .send(crate::device::devices::PingRequest::Ping360(
@@ -157,6 +195,7 @@ impl DeviceManager {
     pub fn ping1d_continuous_mode_helper(
         msg: bluerobotics_ping::message::ProtocolMessage,
         device_id: Uuid,
+        timestamp: FrameTimestamp,
     ) {
         if msg.message_id == <bluerobotics_ping::ping1d::ProfileStruct as bluerobotics_ping::message::MessageInfo>::id() {
             if let Ok(bluerobotics_ping::Messages::Ping1D(bluerobotics_ping::ping1d::Messages::Profile(_answer))) = bluerobotics_ping::Messages::try_from(&msg) {
@@ -170,17 +209,50 @@ impl DeviceManager {
                         }
                     ),
                     device_id,
+                    heading: None,
+                    timestamp: Some(timestamp),
                 });
-                crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Some(device_id));
+                crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Topic::device_message(device_id, msg.message_id));
             }
         }
     }
 
-    // An inner helper focused on Ping360 on AutoTransmit mode, which uses AutoDeviceData.
-    pub fn ping360_continuous_mode_helper_auto(
+    // An inner helper focused on TSR1000, which uses its own Profile message to plot graphs
+    pub fn tsr1000_continuous_mode_helper(
         msg: bluerobotics_ping::message::ProtocolMessage,
         device_id: Uuid,
+        timestamp: FrameTimestamp,
     ) {
+        if msg.message_id
+            != <bluerobotics_ping::tsr1000::ProfileStruct as bluerobotics_ping::message::MessageInfo>::id()
+        {
+            return;
+        }
+        match bluerobotics_ping::Messages::try_from(&msg) {
+            Ok(
+                message @ bluerobotics_ping::Messages::Tsr1000(
+                    bluerobotics_ping::tsr1000::Messages::Profile(_),
+                ),
+            ) => {
+                let answer = Answer::DeviceMessage(DeviceAnswer {
+                    answer: crate::device::devices::PingAnswer::PingMessage(message),
+                    device_id,
+                    heading: None,
+                    timestamp: Some(timestamp),
+                });
+                crate::server::protocols::v1::websocket::send_to_websockets(
+                    json!(answer),
+                    Topic::device_message(device_id, msg.message_id),
+                );
+            }
+            Ok(_) => {}
+            Err(err) => error!("Unexpected message during scan: {err:?}, device: {device_id}"),
+        }
+    }
+
+    // An inner helper focused on Ping360 on AutoTransmit mode, which uses AutoDeviceData.
+    pub fn ping360_continuous_mode_helper_auto(frame: ReceivedFrame, device_id: Uuid) {
+        let msg = frame.message;
         if msg.message_id == <bluerobotics_ping::ping360::AutoDeviceDataStruct as bluerobotics_ping::message::MessageInfo>::id() {
                 if let Ok(bluerobotics_ping::Messages::Ping360(bluerobotics_ping::ping360::Messages::AutoDeviceData(_answer))) = bluerobotics_ping::Messages::try_from(&msg) {
                     let answer = Answer::DeviceMessage(DeviceAnswer {
@@ -193,19 +265,33 @@ impl DeviceManager {
                             }
                         ),
                         device_id,
+                        heading: frame.heading,
+                        timestamp: Some(frame.timestamp),
                     });
-                    crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Some(device_id));
+                    crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Topic::device_message(device_id, msg.message_id));
                 }
             }
     }
 
     // An inner helper focused on Ping360, which uses DeviceData message to plot graphs
-    pub fn ping360_continuous_mode_helper(msg: bluerobotics_ping::Messages, device_id: Uuid) {
+    pub fn ping360_continuous_mode_helper(
+        msg: bluerobotics_ping::Messages,
+        device_id: Uuid,
+        frame: Option<ReceivedFrame>,
+    ) {
         let answer = Answer::DeviceMessage(DeviceAnswer {
             answer: crate::device::devices::PingAnswer::PingMessage(msg),
             device_id,
+            heading: frame.as_ref().and_then(|frame| frame.heading),
+            timestamp: frame.map(|frame| frame.timestamp),
         });
-        crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Some(device_id));
+        crate::server::protocols::v1::websocket::send_to_websockets(
+            json!(answer),
+            Topic::device_message(
+                device_id,
+                <bluerobotics_ping::ping360::DeviceDataStruct as bluerobotics_ping::message::MessageInfo>::id(),
+            ),
+        );
     }
 
     // An inner helper that returns error to requester
@@ -216,16 +302,17 @@ impl DeviceManager {
         let error = ManagerError::DeviceError(crate::device::devices::DeviceError::PingError(
             bluerobotics_ping::error::PingError::TokioBroadcastError(error.to_string()),
         ));
-        crate::server::protocols::v1::websocket::send_to_websockets(json!(error), Some(device_id));
+        crate::server::protocols::v1::websocket::send_to_websockets(
+            json!(error),
+            Topic::error(Some(device_id)),
+        );
     }
 
     fn start_ping360_firmware_mode(
         handler: DeviceActorHandler,
         device_id: Uuid,
         properties: Ping360Properties,
-        mut subscriber: tokio::sync::broadcast::Receiver<
-            bluerobotics_ping::message::ProtocolMessage,
-        >,
+        mut subscriber: tokio::sync::broadcast::Receiver<ReceivedFrame>,
     ) -> tokio::task::JoinHandle<()> {
         tokio::spawn(async move {
             loop {
@@ -286,7 +373,7 @@ impl DeviceManager {
                     }
 
                     match subscriber.recv().await {
-                        Ok(msg) => Self::ping360_continuous_mode_helper_auto(msg, device_id),
+                        Ok(frame) => Self::ping360_continuous_mode_helper_auto(frame, device_id),
                         Err(err) => {
                             Self::handle_error_continuous_mode(err, device_id);
                             return;
@@ -303,6 +390,8 @@ impl DeviceManager {
         properties: Ping360Properties,
     ) -> tokio::task::JoinHandle<()> {
         tokio::spawn(async move {
+            // The replies are handed out already parsed, their read time and heading come from the link
+            let mut frames = handler.health.subscribe();
             loop {
                 let config = properties.continuous_mode_settings.clone();
                 let initial_settings = match config.read() {
@@ -351,7 +440,12 @@ impl DeviceManager {
                     {
                         Ok(answer) => match answer {
                             crate::device::devices::PingAnswer::PingMessage(msg) => {
-                                Self::ping360_continuous_mode_helper(msg, device_id)
+                                // Only one transducer request is in flight, so the latest reply read is this one
+                                let frame = Self::latest_frame(
+                                    &mut frames,
+                                    <bluerobotics_ping::ping360::DeviceDataStruct as bluerobotics_ping::message::MessageInfo>::id(),
+                                );
+                                Self::ping360_continuous_mode_helper(msg, device_id, frame)
                             }
                             msg => {
                                 error!("Unexpected message during scan: {msg:?}");
@@ -377,6 +471,21 @@ impl DeviceManager {
         })
     }
 
+    // Drains the frames read so far, returning the last one with this id
+    fn latest_frame(
+        frames: &mut tokio::sync::broadcast::Receiver<ReceivedFrame>,
+        message_id: u16,
+    ) -> Option<ReceivedFrame> {
+        let mut latest = None;
+        loop {
+            match frames.try_recv() {
+                Ok(frame) if frame.message.message_id == message_id => latest = Some(frame),
+                Ok(_) | Err(tokio::sync::broadcast::error::TryRecvError::Lagged(_)) => {}
+                Err(_) => return latest,
+            }
+        }
+    }
+
     fn calculate_next_angle(
         current_angle: u16,
         step_size: u16,
@@ -1,8 +1,11 @@
-use tracing::{error, trace, warn};
+use std::sync::Arc;
+
+use tracing::{error, trace};
 use uuid::Uuid;
 
 use crate::device::{
-    devices::{self, DeviceActorHandler},
+    devices::DeviceActorHandler,
+    health::{LinkHealth, ReceivedFrame},
     manager::{
         Answer, Device, DeviceManager, DeviceSelection, DeviceStatus, ManagerError, SourceSelection,
     },
@@ -92,29 +95,24 @@ impl DeviceManager {
         }
     }
 
+    // Link of the running device, used to find when its messages were read, stopped devices have an empty one
+    pub fn get_link_health(&self, device_id: Uuid) -> Result<Arc<LinkHealth>, ManagerError> {
+        Ok(self
+            .get_device(device_id)?
+            .handler
+            .as_ref()
+            .map(|handler| handler.health.clone())
+            .unwrap_or_default())
+    }
+
+    // Messages read from the device link, stamped as they arrive
     pub async fn get_subscriber(
         &self,
         device_id: Uuid,
-    ) -> Result<
-        tokio::sync::broadcast::Receiver<bluerobotics_ping::message::ProtocolMessage>,
-        ManagerError,
-    > {
+    ) -> Result<tokio::sync::broadcast::Receiver<ReceivedFrame>, ManagerError> {
         let handler_request = self.get_device_handler(device_id).await?;
         let handler = self.extract_handler(handler_request)?;
 
-        let subscriber = handler
-            .send(devices::PingRequest::GetSubscriber)
-            .await
-            .map_err(|err| {
-                warn!("Something went wrong while executing get_subscriber, details: {err:?}");
-                ManagerError::DeviceError(err)
-            })?;
-
-        match subscriber {
-            devices::PingAnswer::Subscriber(subscriber) => Ok(subscriber),
-            _ => Err(ManagerError::Other(
-                "Unreachable: get_subscriber helper".to_string(),
-            )),
-        }
+        Ok(handler.health.subscribe())
     }
 }
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::trace;
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{DeviceActorHandler, Ping1DRequest, PingAnswer, PingRequest, Tsr1000Request},
+    health::{LinkStatistics, SensorReadings},
+    manager::{Answer, DeviceManager, DeviceSelection, DeviceStatus, ManagerError},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceHealth {
+    pub device_id: Uuid,
+    pub device_type: DeviceSelection,
+    pub status: DeviceStatus,
+    /// Only available while the device is running
+    pub link: Option<LinkStatistics>,
+}
+
+impl DeviceManager {
+    pub fn health(&self, device_id: Uuid) -> Result<Answer, ManagerError> {
+        let device = self.get_device(device_id)?;
+
+        Ok(Answer::DeviceHealth(DeviceHealth {
+            device_id,
+            device_type: device.device_type.clone(),
+            status: device.status.clone(),
+            link: device
+                .handler
+                .as_ref()
+                .and_then(|handler| handler.health.statistics()),
+        }))
+    }
+
+    // Health of every device, including the ones not running, for the metrics exporter
+    pub fn metrics(&self) -> Answer {
+        let mut devices: Vec<DeviceHealth> = self
+            .device
+            .keys()
+            .filter_map(|device_id| match self.health(*device_id) {
+                Ok(Answer::DeviceHealth(health)) => Some(health),
+                _ => None,
+            })
+            .collect();
+        devices.sort_by_key(|health| health.device_id);
+        Answer::Metrics(devices)
+    }
+
+    // Publish the health of running devices to websocket clients and refresh their sensor readings
+    pub fn publish_devices_health(&self) {
+        for device in self.device.values() {
+            if !matches!(
+                device.status,
+                DeviceStatus::Running | DeviceStatus::ContinuousMode
+            ) {
+                continue;
+            }
+            let Some(handler) = &device.handler else {
+                continue;
+            };
+
+            if let Ok(answer) = self.health(device.id) {
+                crate::server::protocols::v1::websocket::send_to_websockets(
+                    json!(answer),
+                    crate::server::protocols::v1::subscription::Topic::event(Some(device.id)),
+                );
+            }
+
+            if matches!(
+                device.device_type,
+                DeviceSelection::Ping1D | DeviceSelection::Tsr1000
+            ) && handler.health.begin_sensor_poll()
+            {
+                let handler = handler.clone();
+                let device_type = device.device_type.clone();
+                let device_id = device.id;
+                tokio::spawn(async move {
+                    Self::poll_device_sensors(&handler, &device_type, device_id).await;
+                    handler.health.end_sensor_poll();
+                });
+            }
+        }
+    }
+
+    async fn poll_device_sensors(
+        handler: &DeviceActorHandler,
+        device_type: &DeviceSelection,
+        device_id: Uuid,
+    ) {
+        let requests = match device_type {
+            DeviceSelection::Ping1D => [
+                PingRequest::Ping1D(Ping1DRequest::Voltage5),
+                PingRequest::Ping1D(Ping1DRequest::ProcessorTemperature),
+                PingRequest::Ping1D(Ping1DRequest::PcbTemperature),
+            ],
+            DeviceSelection::Tsr1000 => [
+                PingRequest::Tsr1000(Tsr1000Request::Voltage5),
+                PingRequest::Tsr1000(Tsr1000Request::ProcessorTemperature),
+                PingRequest::Tsr1000(Tsr1000Request::PcbTemperature),
+            ],
+            _ => return,
+        };
+
+        for request in requests {
+            match handler.send(request).await {
+                Ok(PingAnswer::PingMessage(message)) => {
+                    handler
+                        .health
+                        .update_sensors(|sensors| Self::update_sensor_reading(sensors, message));
+                }
+                Ok(answer) => {
+                    trace!(
+                        "Unexpected answer while polling sensors: {answer:?}, device: {device_id}"
+                    );
+                }
+                Err(err) => {
+                    trace!("Failed to poll sensors: {err:?}, device: {device_id}");
+                }
+            }
+        }
+    }
+
+    // Voltage is reported in mV and temperatures in cdeg C
+    fn update_sensor_reading(sensors: &mut SensorReadings, message: bluerobotics_ping::Messages) {
+        use bluerobotics_ping::{ping1d, tsr1000, Messages};
+
+        match message {
+            Messages::Ping1D(ping1d::Messages::Voltage5(msg)) => {
+                sensors.voltage_5 = Some(msg.voltage_5 as f32 / 1000.0)
+            }
+            Messages::Ping1D(ping1d::Messages::ProcessorTemperature(msg)) => {
+                sensors.processor_temperature = Some(msg.processor_temperature as f32 / 100.0)
+            }
+            Messages::Ping1D(ping1d::Messages::PcbTemperature(msg)) => {
+                sensors.pcb_temperature = Some(msg.pcb_temperature as f32 / 100.0)
+            }
+            Messages::Tsr1000(tsr1000::Messages::Voltage5(msg)) => {
+                sensors.voltage_5 = Some(msg.voltage_5 as f32 / 1000.0)
+            }
+            Messages::Tsr1000(tsr1000::Messages::ProcessorTemperature(msg)) => {
+                sensors.processor_temperature = Some(msg.processor_temperature as f32 / 100.0)
+            }
+            Messages::Tsr1000(tsr1000::Messages::PcbTemperature(msg)) => {
+                sensors.pcb_temperature = Some(msg.pcb_temperature as f32 / 100.0)
+            }
+            _ => {}
+        }
+    }
+}
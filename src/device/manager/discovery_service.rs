@@ -1,26 +1,50 @@
 use std::collections::HashSet;
 use std::net::SocketAddrV4;
+use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
 use bluerobotics_ping::ping1d::Device as Ping1D;
 use bluerobotics_ping::ping360::Device as Ping360;
 use bluerobotics_ping::tsr1000::Device as Tsr1000;
-use tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinSet,
+};
 use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
 use tracing::{error, info, trace, warn};
 use udp_stream::UdpStream;
-use uuid::Uuid;
 
 use crate::device::devices::{DeviceActor, DeviceType, PingAnswer, UpgradeResult};
 use crate::device::manager::ManagerError;
 
-use super::{
-    device_discovery, DeviceInfo, DeviceSelection, DeviceStatus, SourceSelection, SourceType,
-};
+use super::{device_discovery, Answer, DeviceInfo, DeviceSelection, DeviceStatus, SourceSelection};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Look for new devices on background, Search requests work regardless
+    pub enabled: bool,
+    /// Seconds between background discovery runs
+    pub interval: u64,
+    pub network: bool,
+    pub serial: bool,
+    /// Devices bridged by BlueOS, only with the blueos-extension feature
+    pub blueos: bool,
+}
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 30,
+            network: true,
+            serial: true,
+            blueos: true,
+        }
+    }
+}
 
 pub struct DeviceFactory;
 
@@ -29,52 +53,53 @@ impl DeviceFactory {
         source: SourceSelection,
         mut device_type: DeviceSelection,
     ) -> Result<DeviceInfo, ManagerError> {
-        let port = match &source {
+        let device = match &source {
             SourceSelection::UdpStream(source_udp_struct) => {
                 let socket_addr = SocketAddrV4::new(source_udp_struct.ip, source_udp_struct.port);
 
-                let udp_stream = UdpStream::connect(socket_addr.into())
+                let udp_port = UdpStream::connect(socket_addr.into())
                     .await
                     .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
-                SourceType::Udp(udp_stream)
+                match device_type {
+                    DeviceSelection::Common | DeviceSelection::Auto => {
+                        DeviceType::Common(bluerobotics_ping::common::Device::new(udp_port))
+                    }
+                    DeviceSelection::Ping1D => DeviceType::Ping1D(Ping1D::new(udp_port)),
+                    DeviceSelection::Ping360 => DeviceType::Ping360(Ping360::new(udp_port)),
+                    DeviceSelection::Tsr1000 => DeviceType::Tsr1000(Tsr1000::new(udp_port)),
+                }
             }
             SourceSelection::SerialStream(source_serial_struct) => {
-                let mut serial_stream: SerialStream =
+                let mut serial_port: SerialStream =
                     tokio_serial::new(&source_serial_struct.path, source_serial_struct.baudrate)
                         .open_native_async()
                         .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
 
                 device_discovery::set_baudrate_pre_routine(
-                    &mut serial_stream,
+                    &mut serial_port,
                     source_serial_struct.baudrate,
                 )
                 .await?;
 
-                serial_stream
+                serial_port
                     .clear(tokio_serial::ClearBuffer::All)
                     .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
 
-                SourceType::Serial(serial_stream)
-            }
-        };
-
-        let device = match port {
-            SourceType::Udp(udp_port) => match device_type {
-                DeviceSelection::Common | DeviceSelection::Auto => {
-                    DeviceType::Common(bluerobotics_ping::common::Device::new(udp_port))
-                }
-                DeviceSelection::Ping1D => DeviceType::Ping1D(Ping1D::new(udp_port)),
-                DeviceSelection::Ping360 => DeviceType::Ping360(Ping360::new(udp_port)),
-                DeviceSelection::Tsr1000 => DeviceType::Tsr1000(Tsr1000::new(udp_port)),
-            },
-            SourceType::Serial(serial_port) => match device_type {
-                DeviceSelection::Common | DeviceSelection::Auto => {
-                    DeviceType::Common(bluerobotics_ping::common::Device::new(serial_port))
+                match device_type {
+                    DeviceSelection::Common | DeviceSelection::Auto => {
+                        DeviceType::Common(bluerobotics_ping::common::Device::new(serial_port))
+                    }
+                    DeviceSelection::Ping1D => DeviceType::Ping1D(Ping1D::new(serial_port)),
+                    DeviceSelection::Ping360 => DeviceType::Ping360(Ping360::new(serial_port)),
+                    DeviceSelection::Tsr1000 => DeviceType::Tsr1000(Tsr1000::new(serial_port)),
                 }
-                DeviceSelection::Ping1D => DeviceType::Ping1D(Ping1D::new(serial_port)),
-                DeviceSelection::Ping360 => DeviceType::Ping360(Ping360::new(serial_port)),
-                DeviceSelection::Tsr1000 => DeviceType::Tsr1000(Tsr1000::new(serial_port)),
-            },
+            }
+            // Discovery only finds serial and network devices, recordings and simulations are created directly
+            SourceSelection::File(_) | SourceSelection::Simulated(_) => {
+                return Err(ManagerError::DeviceSourceError(format!(
+                    "Discovery can't probe this source: {source:?}"
+                )))
+            }
         };
 
         let (mut device, _handler) = DeviceActor::new(device, 1);
@@ -126,16 +151,18 @@ impl DeviceFactory {
             }
         }
 
-        let mut hasher = DefaultHasher::new();
-        source.hash(&mut hasher);
-        let id = Uuid::from_u128(hasher.finish() as u128);
+        let id = source.device_id();
 
         let device = DeviceInfo {
             id,
+            name: None,
             source,
             status: DeviceStatus::Available,
             device_type,
             properties: None,
+            recording: None,
+            mavlink: None,
+            nmea: None,
         };
 
         Ok(device)
@@ -146,6 +173,9 @@ pub struct DeviceDiscoveryManager {
     tx: broadcast::Sender<DeviceInfo>,
     handle: Option<tokio::task::JoinHandle<()>>,
     known_devices_rx: broadcast::Receiver<Vec<DeviceInfo>>,
+    config: DiscoveryConfig,
+    // Held while probing, so background runs and Search requests don't open the same ports together
+    probing: Arc<Mutex<()>>,
 }
 
 impl DeviceDiscoveryManager {
@@ -158,6 +188,8 @@ impl DeviceDiscoveryManager {
                 tx,
                 handle: None,
                 known_devices_rx,
+                config: DiscoveryConfig::default(),
+                probing: Arc::default(),
             },
             rx,
         )
@@ -166,6 +198,8 @@ impl DeviceDiscoveryManager {
     pub fn start_discovery(&mut self) {
         let tx = self.tx.clone();
         let mut known_devices_rx = self.known_devices_rx.resubscribe();
+        let config = self.config.clone();
+        let probing = self.probing.clone();
 
         let handle = tokio::spawn(async move {
             let mut known_devices = Vec::new();
@@ -188,47 +222,9 @@ impl DeviceDiscoveryManager {
                     }
                 }
 
-                let mut available_sources = Vec::new();
-
-                #[cfg(feature = "blueos-extension")]
-                if let Some(discovery_result) = device_discovery::blueos_ping_discovery().await {
-                    for source in discovery_result.sources {
-                        let key = get_device_key(&source);
-                        if !device_keys.contains(&key) {
-                            available_sources.push(source);
-                        }
-                    }
-                }
-
-                if let Some(result) = device_discovery::network_discovery() {
-                    for source in result {
-                        let key = get_device_key(&source);
-                        if !device_keys.contains(&key) {
-                            available_sources.push(source);
-                        }
-                    }
-                }
-
-                let used_ports: Vec<String> = known_devices
-                    .iter()
-                    .filter_map(|device| {
-                        if let SourceSelection::SerialStream(serial) = &device.source {
-                            Some(serial.path.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-
-                // Add serial devices, skipping used ports
-                if let Some(result) = device_discovery::serial_discovery(Some(&used_ports)).await {
-                    for source in result {
-                        let key = get_device_key(&source);
-                        if !device_keys.contains(&key) {
-                            available_sources.push(source);
-                        }
-                    }
-                }
+                let probing = probing.lock().await;
+                let available_sources =
+                    discover_sources(&known_devices, &device_keys, &config).await;
 
                 // Process discovered sources
                 for source in available_sources {
@@ -246,8 +242,9 @@ impl DeviceDiscoveryManager {
                         }
                     }
                 }
+                drop(probing);
 
-                tokio::time::sleep(Duration::from_secs(30)).await;
+                tokio::time::sleep(Duration::from_secs(config.interval)).await;
             }
         });
 
@@ -261,10 +258,110 @@ impl DeviceDiscoveryManager {
     }
 }
 
+// Look for sources which are not known yet, serial ports in use by known devices or bridged by BlueOS are left untouched
+pub async fn discover_sources(
+    known_devices: &[DeviceInfo],
+    device_keys: &HashSet<String>,
+    config: &DiscoveryConfig,
+) -> Vec<SourceSelection> {
+    let mut available_sources = Vec::new();
+
+    #[cfg_attr(not(feature = "blueos-extension"), allow(unused_mut))]
+    let mut used_ports: Vec<String> = known_devices
+        .iter()
+        .filter_map(|device| {
+            if let SourceSelection::SerialStream(serial) = &device.source {
+                Some(serial.path.clone())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    #[cfg(feature = "blueos-extension")]
+    if config.blueos {
+        if let Some(discovery_result) = device_discovery::blueos_ping_discovery().await {
+            for source in discovery_result.sources {
+                let key = get_device_key(&source);
+                if !device_keys.contains(&key) {
+                    available_sources.push(source);
+                }
+            }
+            used_ports.extend(discovery_result.used_ports);
+        }
+    }
+
+    // Network discovery waits on a blocking socket
+    if config.network {
+        match tokio::task::spawn_blocking(device_discovery::network_discovery).await {
+            Ok(Some(result)) => {
+                for source in result {
+                    let key = get_device_key(&source);
+                    if !device_keys.contains(&key) {
+                        available_sources.push(source);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!("Network discovery task failed: {err:?}"),
+        }
+    }
+
+    // Add serial devices, skipping used ports
+    if config.serial {
+        if let Some(result) = device_discovery::serial_discovery(Some(&used_ports)).await {
+            for source in result {
+                let key = get_device_key(&source);
+                if !device_keys.contains(&key) {
+                    available_sources.push(source);
+                }
+            }
+        }
+    }
+
+    available_sources
+}
+
+// Probe the sources that are not known yet and return them with their detected type, without registering them
+pub async fn search(
+    known_devices: Vec<DeviceInfo>,
+    config: DiscoveryConfig,
+    probing: Arc<Mutex<()>>,
+) -> Result<Answer, ManagerError> {
+    let _probing = probing.lock().await;
+
+    let device_keys: HashSet<String> = known_devices
+        .iter()
+        .map(|device| get_device_key(&device.source))
+        .collect();
+
+    let available_sources = discover_sources(&known_devices, &device_keys, &config).await;
+    trace!("Search: Probing sources: {available_sources:?}");
+
+    let mut set = JoinSet::new();
+    for source in available_sources {
+        set.spawn(DeviceFactory::create_device(source, DeviceSelection::Auto));
+    }
+
+    let mut candidates = Vec::new();
+    while let Some(result) = set.join_next().await {
+        match result {
+            Ok(Ok(device_info)) => candidates.push(device_info),
+            Ok(Err(err)) => warn!("Search: Failed to probe source: {err:?}"),
+            Err(err) => error!("Search: Probe task error: {err:?}"),
+        }
+    }
+
+    info!("Search: Found {} candidate devices", candidates.len());
+    Ok(Answer::SearchResult(candidates))
+}
+
 fn get_device_key(source: &SourceSelection) -> String {
     match source {
         SourceSelection::SerialStream(serial) => serial.path.clone(),
         SourceSelection::UdpStream(udp) => format!("{}:{}", udp.ip, udp.port),
+        SourceSelection::File(file) => file.path.clone(),
+        SourceSelection::Simulated(simulated) => format!("{simulated:?}"),
     }
 }
 
@@ -292,7 +389,24 @@ impl DiscoveryComponent {
         }
     }
 
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.manager.config
+    }
+
+    pub fn probing(&self) -> Arc<Mutex<()>> {
+        self.manager.probing.clone()
+    }
+
+    // Takes effect on the next start of the background discovery
+    pub fn set_config(&mut self, config: DiscoveryConfig) {
+        self.manager.config = config;
+    }
+
     pub fn start_discovery(&mut self) {
+        if !self.manager.config.enabled {
+            info!("DeviceDiscovery service is disabled");
+            return;
+        }
         self.manager.start_discovery();
         info!("DeviceDiscovery service is running");
     }
@@ -310,3 +424,58 @@ impl DiscoveryComponent {
         self.rx.resubscribe()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::manager::{simulation::SimulatedDevice, SourceSimulatedStruct};
+
+    fn simulated() -> SourceSelection {
+        SourceSelection::Simulated(SourceSimulatedStruct {
+            device: SimulatedDevice::Ping1D,
+            scene: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_search_waits_for_discovery() {
+        let known_devices = vec![DeviceInfo {
+            id: simulated().device_id(),
+            name: None,
+            source: simulated(),
+            status: DeviceStatus::ContinuousMode,
+            device_type: DeviceSelection::Ping1D,
+            properties: None,
+            recording: None,
+            mavlink: None,
+            nmea: None,
+        }];
+        let config = DiscoveryConfig {
+            network: false,
+            serial: false,
+            blueos: false,
+            ..Default::default()
+        };
+
+        // A background run is probing
+        let probing: Arc<Mutex<()>> = Arc::default();
+        let running = probing.clone().lock_owned().await;
+        let search = tokio::spawn(search(known_devices, config, probing));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!search.is_finished());
+
+        drop(running);
+        let Ok(Answer::SearchResult(candidates)) = search.await.unwrap() else {
+            panic!("Unexpected search answer");
+        };
+        assert!(candidates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_discovery_skips_created_sources() {
+        assert!(matches!(
+            DeviceFactory::create_device(simulated(), DeviceSelection::Auto).await,
+            Err(ManagerError::DeviceSourceError(_))
+        ));
+    }
+}
@@ -0,0 +1,432 @@
+use bluerobotics_ping::{ping1d, tsr1000};
+use uuid::Uuid;
+
+use super::{
+    Answer, DeviceManager, DeviceProperties, DeviceSelection, DeviceStatus, EchosounderConfig,
+    ManagerError, ModifyDeviceResult,
+};
+use crate::device::devices::{
+    DeviceActorHandler, Ping1DRequest, PingAnswer, PingRequest, Tsr1000Request,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EchosounderSetting {
+    ModeAuto,
+    Range,
+    GainSetting,
+    PingInterval,
+    SpeedOfSound,
+    PingEnable,
+}
+
+// Mode goes last, setting range or gain switches the device to manual mode
+const APPLY_ORDER: [EchosounderSetting; 6] = [
+    EchosounderSetting::PingEnable,
+    EchosounderSetting::SpeedOfSound,
+    EchosounderSetting::PingInterval,
+    EchosounderSetting::Range,
+    EchosounderSetting::GainSetting,
+    EchosounderSetting::ModeAuto,
+];
+
+// Ping1D and TSR1000 share their settings messages, each one on its own protocol namespace
+pub trait EchosounderRequest: Sized {
+    const DEVICE_TYPE: DeviceSelection;
+
+    fn get(setting: EchosounderSetting) -> Self;
+    fn set(setting: EchosounderSetting, config: &EchosounderConfig) -> Self;
+    fn into_ping_request(self) -> PingRequest;
+    // Fills the setting carried by the answer, false when it carries none
+    fn read_answer(
+        answer: &PingAnswer,
+        config: &mut EchosounderConfig,
+    ) -> Result<bool, ManagerError>;
+    fn config_result(config: EchosounderConfig) -> ModifyDeviceResult;
+    fn stored_config(properties: &mut DeviceProperties) -> Option<&mut Option<EchosounderConfig>>;
+}
+
+// Gain is reported as u32 but set as u8, anything larger is an index the manager can't apply back
+fn gain_setting(value: u32) -> Result<u8, ManagerError> {
+    u8::try_from(value)
+        .map_err(|_| ManagerError::Other(format!("Gain setting out of range: {value}")))
+}
+
+impl EchosounderRequest for Ping1DRequest {
+    const DEVICE_TYPE: DeviceSelection = DeviceSelection::Ping1D;
+
+    fn get(setting: EchosounderSetting) -> Self {
+        match setting {
+            EchosounderSetting::ModeAuto => Self::ModeAuto,
+            EchosounderSetting::Range => Self::Range,
+            EchosounderSetting::GainSetting => Self::GainSetting,
+            EchosounderSetting::PingInterval => Self::PingInterval,
+            EchosounderSetting::SpeedOfSound => Self::SpeedOfSound,
+            EchosounderSetting::PingEnable => Self::PingEnable,
+        }
+    }
+
+    fn set(setting: EchosounderSetting, config: &EchosounderConfig) -> Self {
+        match setting {
+            EchosounderSetting::ModeAuto => Self::SetModeAuto(ping1d::SetModeAutoStruct {
+                mode_auto: config.mode_auto,
+            }),
+            EchosounderSetting::Range => Self::SetRange(ping1d::SetRangeStruct {
+                scan_start: config.scan_start,
+                scan_length: config.scan_length,
+            }),
+            EchosounderSetting::GainSetting => Self::SetGainSetting(ping1d::SetGainSettingStruct {
+                gain_setting: config.gain_setting,
+            }),
+            EchosounderSetting::PingInterval => {
+                Self::SetPingInterval(ping1d::SetPingIntervalStruct {
+                    ping_interval: config.ping_interval,
+                })
+            }
+            EchosounderSetting::SpeedOfSound => {
+                Self::SetSpeedOfSound(ping1d::SetSpeedOfSoundStruct {
+                    speed_of_sound: config.speed_of_sound,
+                })
+            }
+            EchosounderSetting::PingEnable => Self::SetPingEnable(ping1d::SetPingEnableStruct {
+                ping_enabled: config.ping_enabled,
+            }),
+        }
+    }
+
+    fn into_ping_request(self) -> PingRequest {
+        PingRequest::Ping1D(self)
+    }
+
+    fn read_answer(
+        answer: &PingAnswer,
+        config: &mut EchosounderConfig,
+    ) -> Result<bool, ManagerError> {
+        let PingAnswer::PingMessage(bluerobotics_ping::Messages::Ping1D(message)) = answer else {
+            return Ok(false);
+        };
+        match message {
+            ping1d::Messages::ModeAuto(msg) => config.mode_auto = msg.mode_auto,
+            ping1d::Messages::Range(msg) => {
+                config.scan_start = msg.scan_start;
+                config.scan_length = msg.scan_length;
+            }
+            ping1d::Messages::GainSetting(msg) => {
+                config.gain_setting = gain_setting(msg.gain_setting)?
+            }
+            ping1d::Messages::PingInterval(msg) => config.ping_interval = msg.ping_interval,
+            ping1d::Messages::SpeedOfSound(msg) => config.speed_of_sound = msg.speed_of_sound,
+            ping1d::Messages::PingEnable(msg) => config.ping_enabled = msg.ping_enabled,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn config_result(config: EchosounderConfig) -> ModifyDeviceResult {
+        ModifyDeviceResult::Ping1DConfig(config)
+    }
+
+    fn stored_config(properties: &mut DeviceProperties) -> Option<&mut Option<EchosounderConfig>> {
+        match properties {
+            DeviceProperties::Ping1D(properties) => Some(&mut properties.config),
+            _ => None,
+        }
+    }
+}
+
+impl EchosounderRequest for Tsr1000Request {
+    const DEVICE_TYPE: DeviceSelection = DeviceSelection::Tsr1000;
+
+    fn get(setting: EchosounderSetting) -> Self {
+        match setting {
+            EchosounderSetting::ModeAuto => Self::ModeAuto,
+            EchosounderSetting::Range => Self::Range,
+            EchosounderSetting::GainSetting => Self::GainSetting,
+            EchosounderSetting::PingInterval => Self::PingInterval,
+            EchosounderSetting::SpeedOfSound => Self::SpeedOfSound,
+            EchosounderSetting::PingEnable => Self::PingEnable,
+        }
+    }
+
+    fn set(setting: EchosounderSetting, config: &EchosounderConfig) -> Self {
+        match setting {
+            EchosounderSetting::ModeAuto => Self::SetModeAuto(tsr1000::SetModeAutoStruct {
+                mode_auto: config.mode_auto,
+            }),
+            EchosounderSetting::Range => Self::SetRange(tsr1000::SetRangeStruct {
+                scan_start: config.scan_start,
+                scan_length: config.scan_length,
+            }),
+            EchosounderSetting::GainSetting => {
+                Self::SetGainSetting(tsr1000::SetGainSettingStruct {
+                    gain_setting: config.gain_setting,
+                })
+            }
+            EchosounderSetting::PingInterval => {
+                Self::SetPingInterval(tsr1000::SetPingIntervalStruct {
+                    ping_interval: config.ping_interval,
+                })
+            }
+            EchosounderSetting::SpeedOfSound => {
+                Self::SetSpeedOfSound(tsr1000::SetSpeedOfSoundStruct {
+                    speed_of_sound: config.speed_of_sound,
+                })
+            }
+            EchosounderSetting::PingEnable => Self::SetPingEnable(tsr1000::SetPingEnableStruct {
+                ping_enabled: config.ping_enabled,
+            }),
+        }
+    }
+
+    fn into_ping_request(self) -> PingRequest {
+        PingRequest::Tsr1000(self)
+    }
+
+    fn read_answer(
+        answer: &PingAnswer,
+        config: &mut EchosounderConfig,
+    ) -> Result<bool, ManagerError> {
+        let PingAnswer::PingMessage(bluerobotics_ping::Messages::Tsr1000(message)) = answer else {
+            return Ok(false);
+        };
+        match message {
+            tsr1000::Messages::ModeAuto(msg) => config.mode_auto = msg.mode_auto,
+            tsr1000::Messages::Range(msg) => {
+                config.scan_start = msg.scan_start;
+                config.scan_length = msg.scan_length;
+            }
+            tsr1000::Messages::GainSetting(msg) => {
+                config.gain_setting = gain_setting(msg.gain_setting)?
+            }
+            tsr1000::Messages::PingInterval(msg) => config.ping_interval = msg.ping_interval,
+            tsr1000::Messages::SpeedOfSound(msg) => config.speed_of_sound = msg.speed_of_sound,
+            tsr1000::Messages::PingEnable(msg) => config.ping_enabled = msg.ping_enabled,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn config_result(config: EchosounderConfig) -> ModifyDeviceResult {
+        ModifyDeviceResult::Tsr1000Config(config)
+    }
+
+    fn stored_config(properties: &mut DeviceProperties) -> Option<&mut Option<EchosounderConfig>> {
+        match properties {
+            DeviceProperties::Tsr1000(properties) => Some(&mut properties.config),
+            _ => None,
+        }
+    }
+}
+
+impl DeviceManager {
+    pub async fn read_echosounder_config<R: EchosounderRequest>(
+        handler: &DeviceActorHandler,
+        device_id: Uuid,
+    ) -> Result<EchosounderConfig, ManagerError> {
+        let mut config = EchosounderConfig::default();
+        for setting in APPLY_ORDER {
+            let answer = handler
+                .send(R::get(setting).into_ping_request())
+                .await
+                .map_err(ManagerError::DeviceError)?;
+            if !R::read_answer(&answer, &mut config)? {
+                return Err(ManagerError::Other(format!(
+                    "read_echosounder_config: Unexpected answer from {:?} device: {answer:?}, device: {device_id}",
+                    R::DEVICE_TYPE
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    fn store_echosounder_config<R: EchosounderRequest>(
+        &mut self,
+        device_id: Uuid,
+        config: EchosounderConfig,
+    ) {
+        if let Ok(device) = self.get_mut_device(device_id) {
+            if let Some(stored) = device.properties.as_mut().and_then(R::stored_config) {
+                *stored = Some(config);
+            }
+        }
+    }
+
+    async fn echosounder_handler<R: EchosounderRequest>(
+        &self,
+        device_id: Uuid,
+    ) -> Result<DeviceActorHandler, ManagerError> {
+        let device_type = &self.get_device(device_id)?.device_type;
+        if *device_type != R::DEVICE_TYPE {
+            return Err(ManagerError::DeviceSourceError(format!(
+                "{:?} settings are not available for {device_type:?}, device: {device_id}",
+                R::DEVICE_TYPE
+            )));
+        }
+        self.check_device_status(
+            device_id,
+            &[DeviceStatus::Running, DeviceStatus::ContinuousMode],
+        )?;
+        self.extract_handler(self.get_device_handler(device_id).await?)
+    }
+
+    // Range and gain are left to the device while in automatic mode, so they are only applied and checked in manual mode
+    pub async fn set_echosounder_config<R: EchosounderRequest>(
+        &mut self,
+        device_id: Uuid,
+        new_config: EchosounderConfig,
+    ) -> Result<Answer, ManagerError> {
+        let handler = self.echosounder_handler::<R>(device_id).await?;
+
+        let manual = new_config.mode_auto == 0;
+        for setting in APPLY_ORDER {
+            if !manual
+                && matches!(
+                    setting,
+                    EchosounderSetting::Range | EchosounderSetting::GainSetting
+                )
+            {
+                continue;
+            }
+            handler
+                .send(R::set(setting, &new_config).into_ping_request())
+                .await
+                .map_err(ManagerError::DeviceError)?;
+        }
+
+        let config = Self::read_echosounder_config::<R>(&handler, device_id).await?;
+        self.store_echosounder_config::<R>(device_id, config);
+
+        let mut rejected = Vec::new();
+        if config.mode_auto != new_config.mode_auto {
+            rejected.push("mode_auto");
+        }
+        if manual && config.scan_start != new_config.scan_start {
+            rejected.push("scan_start");
+        }
+        if manual && config.scan_length != new_config.scan_length {
+            rejected.push("scan_length");
+        }
+        if manual && config.gain_setting != new_config.gain_setting {
+            rejected.push("gain_setting");
+        }
+        if config.ping_interval != new_config.ping_interval {
+            rejected.push("ping_interval");
+        }
+        if config.speed_of_sound != new_config.speed_of_sound {
+            rejected.push("speed_of_sound");
+        }
+        if config.ping_enabled != new_config.ping_enabled {
+            rejected.push("ping_enabled");
+        }
+        if !rejected.is_empty() {
+            return Err(ManagerError::Other(format!(
+                "set_echosounder_config: Device did not apply {rejected:?}, current: {config:?}, device: {device_id}"
+            )));
+        }
+
+        Ok(Answer::DeviceConfig(R::config_result(config)))
+    }
+
+    pub async fn get_echosounder_config<R: EchosounderRequest>(
+        &mut self,
+        device_id: Uuid,
+    ) -> Result<Answer, ManagerError> {
+        let handler = self.echosounder_handler::<R>(device_id).await?;
+
+        let config = Self::read_echosounder_config::<R>(&handler, device_id).await?;
+        self.store_echosounder_config::<R>(device_id, config);
+
+        Ok(Answer::DeviceConfig(R::config_result(config)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::manager::{
+        simulation::{SimulatedDevice, SimulatedScene},
+        SourceSelection, SourceSimulatedStruct,
+    };
+
+    async fn config_roundtrip<R: EchosounderRequest>(device: SimulatedDevice) {
+        let (mut manager, _handler) = DeviceManager::new(10);
+        let Ok(Answer::DeviceInfo(info)) = manager
+            .create(
+                SourceSelection::Simulated(SourceSimulatedStruct {
+                    device,
+                    scene: SimulatedScene::default(),
+                }),
+                R::DEVICE_TYPE,
+            )
+            .await
+        else {
+            panic!("Failed to create simulated {:?}", R::DEVICE_TYPE);
+        };
+        let device_id = info[0].id;
+
+        let config = EchosounderConfig {
+            mode_auto: 0,
+            scan_start: 500,
+            scan_length: 20_000,
+            gain_setting: 3,
+            ping_interval: 200,
+            speed_of_sound: 1_480_000,
+            ping_enabled: 1,
+        };
+        let answer = manager
+            .set_echosounder_config::<R>(device_id, config)
+            .await
+            .unwrap();
+        assert!(matches!(answer, Answer::DeviceConfig(_)));
+
+        // Read back from the device, and from the properties where the last read is kept
+        let handler = manager
+            .extract_handler(manager.get_device_handler(device_id).await.unwrap())
+            .unwrap();
+        let read = DeviceManager::read_echosounder_config::<R>(&handler, device_id)
+            .await
+            .unwrap();
+        assert_eq!(read, config);
+        let stored = manager
+            .get_mut_device(device_id)
+            .unwrap()
+            .properties
+            .as_mut()
+            .and_then(R::stored_config)
+            .map(|stored| *stored);
+        assert_eq!(stored, Some(Some(config)));
+
+        let answer = manager
+            .get_echosounder_config::<R>(device_id)
+            .await
+            .unwrap();
+        assert!(matches!(answer, Answer::DeviceConfig(_)));
+    }
+
+    #[tokio::test]
+    async fn test_ping1d_config_roundtrip() {
+        config_roundtrip::<Ping1DRequest>(SimulatedDevice::Ping1D).await;
+    }
+
+    #[tokio::test]
+    async fn test_tsr1000_config_roundtrip() {
+        config_roundtrip::<Tsr1000Request>(SimulatedDevice::Tsr1000).await;
+    }
+
+    #[test]
+    fn test_gain_setting_out_of_range() {
+        let answer = |gain_setting| {
+            PingAnswer::PingMessage(bluerobotics_ping::Messages::Ping1D(
+                ping1d::Messages::GainSetting(ping1d::GainSettingStruct { gain_setting }),
+            ))
+        };
+        let mut config = EchosounderConfig::default();
+
+        assert!(matches!(
+            Ping1DRequest::read_answer(&answer(6), &mut config),
+            Ok(true)
+        ));
+        assert_eq!(config.gain_setting, 6);
+        assert!(Ping1DRequest::read_answer(&answer(256), &mut config).is_err());
+        assert_eq!(config.gain_setting, 6);
+    }
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{DeviceInfo, DeviceManager, DeviceStatus, ModifyDeviceCommand};
+use crate::server::protocols::v1::subscription::Topic;
+
+// Published to websocket clients as {"DeviceEvent": {"StatusChanged": {..}}}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DeviceEvent {
+    /// Found by the background discovery, it is registered right after
+    Discovered(DeviceInfo),
+    Registered(DeviceInfo),
+    Deleted(DeviceInfo),
+    StatusChanged(StatusChange),
+    PropertiesUpdated(DeviceInfo),
+    ConfigChanged(ConfigChange),
+    ContinuousModeStarted(DeviceInfo),
+    ContinuousModeStopped(DeviceInfo),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub device_id: Uuid,
+    pub previous: DeviceStatus,
+    pub status: DeviceStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub device_id: Uuid,
+    /// The applied command, as sent on ModifyDevice
+    pub change: ModifyDeviceCommand,
+}
+
+impl DeviceEvent {
+    pub fn device_id(&self) -> Uuid {
+        match self {
+            Self::Discovered(info)
+            | Self::Registered(info)
+            | Self::Deleted(info)
+            | Self::PropertiesUpdated(info)
+            | Self::ContinuousModeStarted(info)
+            | Self::ContinuousModeStopped(info) => info.id,
+            Self::StatusChanged(change) => change.device_id,
+            Self::ConfigChanged(change) => change.device_id,
+        }
+    }
+}
+
+pub fn publish(event: DeviceEvent) {
+    crate::server::protocols::v1::websocket::send_to_websockets(
+        json!({ "DeviceEvent": event }),
+        Topic::device_event(event.device_id()),
+    );
+}
+
+impl DeviceManager {
+    // Status is set from many places, so changes are found by comparing with the last published one.
+    // Called once per manager loop iteration, intermediate states within an iteration are not published.
+    pub fn publish_status_changes(&mut self) {
+        for device in self.device.values() {
+            match self
+                .published_status
+                .insert(device.id, device.status.clone())
+            {
+                Some(previous) if previous != device.status => {
+                    publish(DeviceEvent::StatusChanged(StatusChange {
+                        device_id: device.id,
+                        previous,
+                        status: device.status.clone(),
+                    }))
+                }
+                _ => {}
+            }
+        }
+        self.published_status
+            .retain(|device_id, _| self.device.contains_key(device_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_format() {
+        let device_id = Uuid::from_u128(1);
+        let event = DeviceEvent::StatusChanged(StatusChange {
+            device_id,
+            previous: DeviceStatus::Running,
+            status: DeviceStatus::Stopped,
+        });
+        assert_eq!(event.device_id(), device_id);
+        assert_eq!(
+            json!({ "DeviceEvent": event }),
+            json!({ "DeviceEvent": { "StatusChanged": {
+                "device_id": device_id,
+                "previous": "Running",
+                "status": "Stopped",
+            }}})
+        );
+    }
+}
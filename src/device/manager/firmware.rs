@@ -0,0 +1,693 @@
+use std::{sync::Arc, time::Duration};
+
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+    time::{timeout, Instant},
+};
+use tokio_serial::SerialPortBuilderExt;
+use tracing::{error, info, trace, warn};
+use uuid::Uuid;
+
+use super::{
+    supervisor::Recovery, Answer, DeviceManager, DeviceSelection, DeviceStatus, ManagerError,
+    SourceSelection,
+};
+use crate::device::devices::{Ping1DRequest, PingRequest};
+
+/// Largest firmware image accepted over REST.
+pub const FIRMWARE_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+// STM32 system memory bootloader, used by Ping1D, described in ST's AN3155
+const STM32_FLASH_BASE: u32 = 0x0800_0000;
+const STM32_BOOTLOADER_BAUDRATE: u32 = 115200;
+const STM32_SYNC: u8 = 0x7F;
+const STM32_ACK: u8 = 0x79;
+const STM32_NACK: u8 = 0x1F;
+const STM32_CMD_GET: u8 = 0x00;
+const STM32_CMD_READ_MEMORY: u8 = 0x11;
+const STM32_CMD_GO: u8 = 0x21;
+const STM32_CMD_WRITE_MEMORY: u8 = 0x31;
+const STM32_CMD_ERASE: u8 = 0x43;
+const STM32_CMD_EXTENDED_ERASE: u8 = 0x44;
+const STM32_MAX_CHUNK: usize = 256;
+const STM32_ACK_TIMEOUT: Duration = Duration::from_secs(1);
+const STM32_ERASE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct FirmwareUpdateStruct {
+    pub uuid: Uuid,
+    /// Only filled by the REST upload route, JSON requests can't carry an image
+    #[serde(skip)]
+    pub image: FirmwareUpload,
+}
+
+/// Uploaded firmware image, Intel HEX or raw binary starting at the beginning of the flash.
+/// Shared instead of copied, and only its size is printed when requests are logged.
+#[derive(Clone, Default)]
+pub struct FirmwareUpload(Arc<Vec<u8>>);
+
+impl FirmwareUpload {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self(Arc::new(image))
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for FirmwareUpload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FirmwareUpload({} bytes)", self.0.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FirmwareStage {
+    Preparing,
+    Erasing,
+    Writing,
+    Verifying,
+    Restarting,
+    Finished,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirmwareUpdateStatus {
+    pub device_id: Uuid,
+    pub stage: FirmwareStage,
+    /// Progress of the current stage, from 0.0 to 1.0
+    pub progress: f32,
+}
+
+// Sent back to the manager once the flashing task is over, so the device can be recreated
+#[derive(Debug)]
+pub struct FirmwareUpdateDone {
+    pub device_id: Uuid,
+    pub continuous_mode: bool,
+    pub result: Result<(), ManagerError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirmwareSegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirmwareImage {
+    pub segments: Vec<FirmwareSegment>,
+}
+
+impl FirmwareImage {
+    pub fn parse(image: &[u8]) -> Result<Self, ManagerError> {
+        let segments = if image.first() == Some(&b':') {
+            let text = std::str::from_utf8(image).map_err(|err| {
+                ManagerError::InvalidRequest(format!("Firmware: Invalid HEX file: {err}"))
+            })?;
+            Self::parse_intel_hex(text)?
+        } else {
+            vec![FirmwareSegment {
+                address: STM32_FLASH_BASE,
+                data: image.to_vec(),
+            }]
+        };
+
+        if segments.iter().all(|segment| segment.data.is_empty()) {
+            return Err(ManagerError::InvalidRequest(
+                "Firmware: Image is empty".to_string(),
+            ));
+        }
+        Ok(Self { segments })
+    }
+
+    fn parse_intel_hex(text: &str) -> Result<Vec<FirmwareSegment>, ManagerError> {
+        let mut segments: Vec<FirmwareSegment> = Vec::new();
+        let mut base_address = 0u32;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                ManagerError::InvalidRequest(format!(
+                    "Firmware: Invalid HEX record at line {}: {reason}",
+                    number + 1
+                ))
+            };
+
+            let record = line
+                .strip_prefix(':')
+                .ok_or_else(|| invalid("missing start code"))?;
+            if record.len() % 2 != 0 {
+                return Err(invalid("odd number of digits"));
+            }
+            let bytes = (0..record.len())
+                .step_by(2)
+                .map(|index| u8::from_str_radix(&record[index..index + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| invalid("invalid digit"))?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(invalid("wrong length"));
+            }
+            if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err(invalid("wrong checksum"));
+            }
+
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+            let data = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                0x00 => {
+                    let address = base_address.wrapping_add(offset);
+                    match segments.last_mut() {
+                        Some(segment) if segment.address + segment.data.len() as u32 == address => {
+                            segment.data.extend_from_slice(data)
+                        }
+                        _ => segments.push(FirmwareSegment {
+                            address,
+                            data: data.to_vec(),
+                        }),
+                    }
+                }
+                0x01 => break,
+                0x02 if data.len() == 2 => {
+                    base_address = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4
+                }
+                0x04 if data.len() == 2 => {
+                    base_address = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16
+                }
+                // Start address records are not needed, the application starts from the flash base
+                0x03 | 0x05 => {}
+                _ => return Err(invalid("unsupported record type")),
+            }
+        }
+
+        Ok(segments)
+    }
+
+    pub fn size(&self) -> usize {
+        self.segments.iter().map(|segment| segment.data.len()).sum()
+    }
+
+    pub fn start_address(&self) -> u32 {
+        self.segments
+            .iter()
+            .map(|segment| segment.address)
+            .min()
+            .unwrap_or(STM32_FLASH_BASE)
+    }
+}
+
+pub struct Stm32Bootloader<S> {
+    stream: S,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stm32Bootloader<S> {
+    pub async fn connect(stream: S) -> Result<Self, ManagerError> {
+        let mut bootloader = Self { stream };
+        bootloader.write(&[STM32_SYNC]).await?;
+        // A NACK means the bootloader already detected the baudrate on a previous attempt
+        match bootloader.read_byte(STM32_ACK_TIMEOUT).await? {
+            STM32_ACK | STM32_NACK => Ok(bootloader),
+            byte => Err(ManagerError::Other(format!(
+                "Firmware: Unexpected answer to bootloader sync: {byte:#04x}"
+            ))),
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), ManagerError> {
+        self.stream
+            .write_all(bytes)
+            .await
+            .map_err(|err| ManagerError::Other(format!("Firmware: Write failed: {err}")))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|err| ManagerError::Other(format!("Firmware: Flush failed: {err}")))
+    }
+
+    async fn read_byte(&mut self, wait: Duration) -> Result<u8, ManagerError> {
+        timeout(wait, self.stream.read_u8())
+            .await
+            .map_err(|_| ManagerError::Other("Firmware: Bootloader timed out".to_string()))?
+            .map_err(|err| ManagerError::Other(format!("Firmware: Read failed: {err}")))
+    }
+
+    async fn read_ack(&mut self, wait: Duration) -> Result<(), ManagerError> {
+        match self.read_byte(wait).await? {
+            STM32_ACK => Ok(()),
+            STM32_NACK => Err(ManagerError::Other(
+                "Firmware: Bootloader refused the request".to_string(),
+            )),
+            byte => Err(ManagerError::Other(format!(
+                "Firmware: Unexpected bootloader answer: {byte:#04x}"
+            ))),
+        }
+    }
+
+    async fn command(&mut self, command: u8) -> Result<(), ManagerError> {
+        self.write(&[command, !command]).await?;
+        self.read_ack(STM32_ACK_TIMEOUT).await
+    }
+
+    async fn send_address(&mut self, address: u32) -> Result<(), ManagerError> {
+        let bytes = address.to_be_bytes();
+        let checksum = bytes.iter().fold(0u8, |sum, byte| sum ^ byte);
+        self.write(&[bytes[0], bytes[1], bytes[2], bytes[3], checksum])
+            .await?;
+        self.read_ack(STM32_ACK_TIMEOUT).await
+    }
+
+    pub async fn supported_commands(&mut self) -> Result<Vec<u8>, ManagerError> {
+        self.command(STM32_CMD_GET).await?;
+        let count = self.read_byte(STM32_ACK_TIMEOUT).await? as usize + 1;
+        let mut answer = vec![0u8; count];
+        for byte in answer.iter_mut() {
+            *byte = self.read_byte(STM32_ACK_TIMEOUT).await?;
+        }
+        self.read_ack(STM32_ACK_TIMEOUT).await?;
+        // The first byte is the bootloader version
+        Ok(answer.split_off(1))
+    }
+
+    pub async fn erase_all(&mut self, extended: bool) -> Result<(), ManagerError> {
+        if extended {
+            self.command(STM32_CMD_EXTENDED_ERASE).await?;
+            self.write(&[0xFF, 0xFF, 0x00]).await?;
+        } else {
+            self.command(STM32_CMD_ERASE).await?;
+            self.write(&[0xFF, 0x00]).await?;
+        }
+        self.read_ack(STM32_ERASE_TIMEOUT).await
+    }
+
+    pub async fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), ManagerError> {
+        if data.is_empty() || data.len() > STM32_MAX_CHUNK {
+            return Err(ManagerError::Other(format!(
+                "Firmware: Invalid write size: {}",
+                data.len()
+            )));
+        }
+        self.command(STM32_CMD_WRITE_MEMORY).await?;
+        self.send_address(address).await?;
+
+        let mut frame = Vec::with_capacity(data.len() + 2);
+        frame.push((data.len() - 1) as u8);
+        frame.extend_from_slice(data);
+        frame.push(frame.iter().fold(0u8, |sum, byte| sum ^ byte));
+        self.write(&frame).await?;
+        self.read_ack(STM32_ACK_TIMEOUT).await
+    }
+
+    pub async fn read_memory(
+        &mut self,
+        address: u32,
+        size: usize,
+    ) -> Result<Vec<u8>, ManagerError> {
+        if size == 0 || size > STM32_MAX_CHUNK {
+            return Err(ManagerError::Other(format!(
+                "Firmware: Invalid read size: {size}"
+            )));
+        }
+        self.command(STM32_CMD_READ_MEMORY).await?;
+        self.send_address(address).await?;
+        let length = (size - 1) as u8;
+        self.write(&[length, !length]).await?;
+        self.read_ack(STM32_ACK_TIMEOUT).await?;
+
+        let mut data = vec![0u8; size];
+        for byte in data.iter_mut() {
+            *byte = self.read_byte(STM32_ACK_TIMEOUT).await?;
+        }
+        Ok(data)
+    }
+
+    pub async fn go(&mut self, address: u32) -> Result<(), ManagerError> {
+        self.command(STM32_CMD_GO).await?;
+        self.send_address(address).await
+    }
+
+    // Erase, write and verify the whole image, then start the application
+    pub async fn flash(
+        &mut self,
+        image: &FirmwareImage,
+        mut progress: impl FnMut(FirmwareStage, f32),
+    ) -> Result<(), ManagerError> {
+        let commands = self.supported_commands().await?;
+        let extended = commands.contains(&STM32_CMD_EXTENDED_ERASE);
+        if !extended && !commands.contains(&STM32_CMD_ERASE) {
+            return Err(ManagerError::Other(
+                "Firmware: Bootloader has no erase command".to_string(),
+            ));
+        }
+
+        progress(FirmwareStage::Erasing, 0.0);
+        self.erase_all(extended).await?;
+
+        // Flash is written in words, so chunks are padded with the erased value
+        let chunks: Vec<(u32, Vec<u8>)> = image
+            .segments
+            .iter()
+            .flat_map(|segment| {
+                segment
+                    .data
+                    .chunks(STM32_MAX_CHUNK)
+                    .enumerate()
+                    .map(|(index, chunk)| {
+                        let mut chunk = chunk.to_vec();
+                        chunk.resize(chunk.len().div_ceil(4) * 4, 0xFF);
+                        (segment.address + (index * STM32_MAX_CHUNK) as u32, chunk)
+                    })
+            })
+            .collect();
+        let total = chunks.len() as f32;
+
+        for (index, (address, chunk)) in chunks.iter().enumerate() {
+            progress(FirmwareStage::Writing, index as f32 / total);
+            self.write_memory(*address, chunk).await?;
+        }
+
+        for (index, (address, chunk)) in chunks.iter().enumerate() {
+            progress(FirmwareStage::Verifying, index as f32 / total);
+            let read = self.read_memory(*address, chunk.len()).await?;
+            if &read != chunk {
+                return Err(ManagerError::Other(format!(
+                    "Firmware: Verification failed at {address:#010x}"
+                )));
+            }
+        }
+
+        progress(FirmwareStage::Restarting, 0.0);
+        self.go(image.start_address()).await
+    }
+}
+
+fn publish_status(device_id: Uuid, stage: FirmwareStage, progress: f32) {
+    crate::server::protocols::v1::websocket::send_to_websockets(
+        json!(Answer::FirmwareUpdate(FirmwareUpdateStatus {
+            device_id,
+            stage,
+            progress,
+        })),
+        crate::server::protocols::v1::subscription::Topic::event(Some(device_id)),
+    );
+}
+
+async fn flash_ping1d(
+    path: String,
+    image: FirmwareImage,
+    device_id: Uuid,
+) -> Result<(), ManagerError> {
+    // Give the device some time to reboot into its bootloader
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let stream = tokio_serial::new(&path, STM32_BOOTLOADER_BAUDRATE)
+        .parity(tokio_serial::Parity::Even)
+        .open_native_async()
+        .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
+
+    let mut bootloader = Stm32Bootloader::connect(stream).await?;
+    let mut last_published = Instant::now();
+    bootloader
+        .flash(&image, |stage, progress| {
+            // Progress is throttled, each chunk would flood the websocket clients
+            if progress == 0.0 || last_published.elapsed() > Duration::from_millis(250) {
+                last_published = Instant::now();
+                publish_status(device_id, stage, progress);
+            }
+        })
+        .await
+}
+
+impl DeviceManager {
+    pub async fn firmware_update(
+        &mut self,
+        request: FirmwareUpdateStruct,
+    ) -> Result<Answer, ManagerError> {
+        let device_id = request.uuid;
+        let image = FirmwareImage::parse(request.image.as_slice())?;
+
+        let device = self.get_device(device_id)?;
+        let SourceSelection::SerialStream(serial) = &device.source else {
+            return Err(ManagerError::InvalidRequest(format!(
+                "Firmware update requires a serial source, device: {device_id}"
+            )));
+        };
+        let path = serial.path.clone();
+        match device.device_type {
+            DeviceSelection::Ping1D => {}
+            ref device_type => {
+                return Err(ManagerError::InvalidRequest(format!(
+                    "Firmware update not available for {device_type:?}, device: {device_id}"
+                )))
+            }
+        }
+        self.check_device_status(
+            device_id,
+            &[DeviceStatus::Running, DeviceStatus::ContinuousMode],
+        )?;
+        let continuous_mode = device.status == DeviceStatus::ContinuousMode;
+
+        info!(
+            "Firmware update: Flashing {} bytes, device: {device_id}",
+            image.size()
+        );
+
+        let handler = self.extract_handler(self.get_device_handler(device_id).await?)?;
+        // The device reboots right away, so the acknowledge may never arrive
+        if let Err(err) = handler
+            .send(PingRequest::Ping1D(Ping1DRequest::GotoBootloader))
+            .await
+        {
+            trace!("Firmware update: GotoBootloader answer: {err:?}, device: {device_id}");
+        }
+
+        // Release the serial port, the device is recreated by the supervisor once the update is over
+        let device = self.get_mut_device(device_id)?;
+        if let Some(recorder) = device.recording.take() {
+            recorder.stop().await;
+        }
+        if let Some(handle) = device.broadcast.take() {
+            handle.abort();
+        }
+        if let Some(handle) = device.actor.take() {
+            handle.abort();
+        }
+        device.handler = None;
+        device.recovery = None;
+        device.status = DeviceStatus::Stopped;
+
+        let done_sender = self.firmware_sender.clone();
+        tokio::spawn(async move {
+            let result = flash_ping1d(path, image, device_id).await;
+            let done = FirmwareUpdateDone {
+                device_id,
+                continuous_mode,
+                result,
+            };
+            if let Err(err) = done_sender.send(done).await {
+                error!("Firmware update: Failed to notify manager: {err:?}");
+            }
+        });
+
+        Ok(Answer::FirmwareUpdate(FirmwareUpdateStatus {
+            device_id,
+            stage: FirmwareStage::Preparing,
+            progress: 0.0,
+        }))
+    }
+
+    pub fn finish_firmware_update(&mut self, done: FirmwareUpdateDone) {
+        let stage = match &done.result {
+            Ok(()) => {
+                info!("Firmware update: Finished, device: {}", done.device_id);
+                FirmwareStage::Finished
+            }
+            Err(err) => {
+                warn!(
+                    "Firmware update: Failed: {err:?}, device: {}",
+                    done.device_id
+                );
+                FirmwareStage::Failed(format!("{err:?}"))
+            }
+        };
+        publish_status(done.device_id, stage, 1.0);
+
+        let Ok(device) = self.get_mut_device(done.device_id) else {
+            return;
+        };
+        device.recovery = Some(Recovery {
+            attempts: 0,
+            next_attempt: Instant::now() + Duration::from_secs(1),
+            continuous_mode: done.continuous_mode,
+            recording: false,
+            ping360_config: None,
+            reconnecting: false,
+        });
+    }
+}
+
+pub fn channel() -> (
+    mpsc::Sender<FirmwareUpdateDone>,
+    mpsc::Receiver<FirmwareUpdateDone>,
+) {
+    mpsc::channel(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intel_hex_parsing() {
+        let hex = ":020000040800F2\n\
+                   :0400000001020304F2\n\
+                   :0400040005060708DE\n\
+                   :00000001FF\n";
+        let image = FirmwareImage::parse(hex.as_bytes()).unwrap();
+        assert_eq!(
+            image.segments,
+            vec![FirmwareSegment {
+                address: 0x0800_0000,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_intel_hex() {
+        for image in [&b":0400000001020304F3\n"[..], b":04000000010203\n", b""] {
+            assert!(matches!(
+                FirmwareImage::parse(image),
+                Err(ManagerError::InvalidRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_firmware_request_without_image() {
+        let request: FirmwareUpdateStruct = serde_json::from_value(json!({
+            "uuid": Uuid::from_u128(1),
+            "image": [1, 2, 3],
+        }))
+        .unwrap();
+        assert!(request.image.as_slice().is_empty());
+
+        let request = FirmwareUpdateStruct {
+            uuid: Uuid::from_u128(1),
+            image: FirmwareUpload::new(vec![0; 4096]),
+        };
+        assert!(format!("{request:?}").contains("FirmwareUpload(4096 bytes)"));
+        assert!(serde_json::to_value(&request)
+            .unwrap()
+            .get("image")
+            .is_none());
+    }
+
+    // Minimal AN3155 bootloader keeping the flash in memory
+    #[cfg(unix)]
+    async fn fake_bootloader(mut port: tokio_serial::SerialStream, flash: &mut Vec<u8>) {
+        async fn read_address(port: &mut tokio_serial::SerialStream) -> usize {
+            let mut bytes = [0u8; 5];
+            port.read_exact(&mut bytes).await.unwrap();
+            assert_eq!(
+                bytes[..4].iter().fold(0u8, |sum, byte| sum ^ byte),
+                bytes[4]
+            );
+            port.write_all(&[STM32_ACK]).await.unwrap();
+            (u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) - STM32_FLASH_BASE)
+                as usize
+        }
+
+        assert_eq!(port.read_u8().await.unwrap(), STM32_SYNC);
+        port.write_all(&[STM32_ACK]).await.unwrap();
+
+        loop {
+            let mut command = [0u8; 2];
+            port.read_exact(&mut command).await.unwrap();
+            assert_eq!(command[0], !command[1]);
+            port.write_all(&[STM32_ACK]).await.unwrap();
+
+            match command[0] {
+                STM32_CMD_GET => {
+                    port.write_all(&[2, 0x31, STM32_CMD_GET, STM32_CMD_EXTENDED_ERASE, STM32_ACK])
+                        .await
+                        .unwrap();
+                }
+                STM32_CMD_EXTENDED_ERASE => {
+                    let mut erase = [0u8; 3];
+                    port.read_exact(&mut erase).await.unwrap();
+                    assert_eq!(erase, [0xFF, 0xFF, 0x00]);
+                    flash.iter_mut().for_each(|byte| *byte = 0xFF);
+                    port.write_all(&[STM32_ACK]).await.unwrap();
+                }
+                STM32_CMD_WRITE_MEMORY => {
+                    let offset = read_address(&mut port).await;
+                    let size = port.read_u8().await.unwrap() as usize + 1;
+                    let mut data = vec![0u8; size + 1];
+                    port.read_exact(&mut data).await.unwrap();
+                    flash[offset..offset + size].copy_from_slice(&data[..size]);
+                    port.write_all(&[STM32_ACK]).await.unwrap();
+                }
+                STM32_CMD_READ_MEMORY => {
+                    let offset = read_address(&mut port).await;
+                    let mut length = [0u8; 2];
+                    port.read_exact(&mut length).await.unwrap();
+                    let size = length[0] as usize + 1;
+                    port.write_all(&[STM32_ACK]).await.unwrap();
+                    port.write_all(&flash[offset..offset + size]).await.unwrap();
+                }
+                STM32_CMD_GO => {
+                    read_address(&mut port).await;
+                    return;
+                }
+                command => panic!("Unexpected bootloader command: {command:#04x}"),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_flash_against_fake_bootloader() {
+        let (host, device) = tokio_serial::SerialStream::pair().unwrap();
+
+        let firmware: Vec<u8> = (0..600u32).map(|value| value as u8).collect();
+        let image = FirmwareImage::parse(&firmware).unwrap();
+
+        let bootloader_task = tokio::spawn(async move {
+            let mut flash = vec![0u8; 1024];
+            fake_bootloader(device, &mut flash).await;
+            flash
+        });
+
+        let mut stages = Vec::new();
+        let mut bootloader = Stm32Bootloader::connect(host).await.unwrap();
+        bootloader
+            .flash(&image, |stage, _| {
+                if stages.last() != Some(&stage) {
+                    stages.push(stage)
+                }
+            })
+            .await
+            .unwrap();
+
+        let flash = bootloader_task.await.unwrap();
+        assert_eq!(&flash[..firmware.len()], firmware.as_slice());
+        assert!(flash[firmware.len()..].iter().all(|byte| *byte == 0xFF));
+        assert_eq!(
+            stages,
+            vec![
+                FirmwareStage::Erasing,
+                FirmwareStage::Writing,
+                FirmwareStage::Verifying,
+                FirmwareStage::Restarting
+            ]
+        );
+    }
+}
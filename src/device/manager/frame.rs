@@ -0,0 +1,83 @@
+use bluerobotics_ping::message::MessageInfo;
+
+// Raw ping-protocol frame layout:
+// ['B']['R'][payload length: u16 LE][message id: u16 LE][src id: u8][dst id: u8][payload][checksum: u16 LE]
+pub const HEADER_LENGTH: usize = 8;
+pub const CHECKSUM_LENGTH: usize = 2;
+
+pub fn encode(message_id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LENGTH + payload.len() + CHECKSUM_LENGTH);
+    frame.extend_from_slice(b"BR");
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(&message_id.to_le_bytes());
+    frame.push(0);
+    frame.push(0);
+    frame.extend_from_slice(payload);
+
+    let checksum = frame
+        .iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+    frame.extend_from_slice(&checksum.to_le_bytes());
+    frame
+}
+
+pub fn message_id(frame: &[u8]) -> Option<u16> {
+    if frame.len() < HEADER_LENGTH + CHECKSUM_LENGTH || &frame[0..2] != b"BR" {
+        return None;
+    }
+    Some(u16::from_le_bytes([frame[4], frame[5]]))
+}
+
+pub fn payload(frame: &[u8]) -> Option<&[u8]> {
+    if frame.len() < HEADER_LENGTH + CHECKSUM_LENGTH {
+        return None;
+    }
+    Some(&frame[HEADER_LENGTH..frame.len() - CHECKSUM_LENGTH])
+}
+
+pub fn ack(acked_id: u16) -> Vec<u8> {
+    encode(
+        <bluerobotics_ping::common::AckStruct as MessageInfo>::id(),
+        &acked_id.to_le_bytes(),
+    )
+}
+
+pub fn nack(nacked_id: u16, reason: &str) -> Vec<u8> {
+    let mut payload = nacked_id.to_le_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    payload.push(0);
+    encode(
+        <bluerobotics_ping::common::NackStruct as MessageInfo>::id(),
+        &payload,
+    )
+}
+
+// Returns the requested message id if the frame is a GeneralRequest
+pub fn general_request_id(message: &bluerobotics_ping::message::ProtocolMessage) -> Option<u16> {
+    if message.message_id != <bluerobotics_ping::common::GeneralRequestStruct as MessageInfo>::id()
+        || message.payload.len() < 2
+    {
+        return None;
+    }
+    Some(u16::from_le_bytes([message.payload[0], message.payload[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_encoding() {
+        // GeneralRequest for ProtocolVersion
+        let frame = encode(6, &5u16.to_le_bytes());
+        assert_eq!(frame, vec![b'B', b'R', 2, 0, 6, 0, 0, 0, 5, 0, 0xa1, 0x00]);
+        assert_eq!(message_id(&frame), Some(6));
+        assert_eq!(payload(&frame), Some([5u8, 0].as_slice()));
+    }
+
+    #[test]
+    fn test_invalid_frame() {
+        assert_eq!(message_id(b"XX"), None);
+        assert_eq!(message_id(&[0u8; 12]), None);
+    }
+}
@@ -0,0 +1,512 @@
+use std::time::Duration;
+
+use bluerobotics_ping::message::{MessageInfo, ProtocolMessage};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, time::Instant};
+use tracing::{info, trace};
+use uuid::Uuid;
+
+use super::{Answer, DeviceManager, DeviceSelection, DeviceStatus, ManagerError};
+use crate::device::health::{FrameTimestamp, ReceivedFrame};
+use crate::mavlink::{
+    codec::{
+        DistanceSensor, Heartbeat, ObstacleDistance, MAV_DISTANCE_SENSOR_ULTRASOUND,
+        MAV_FRAME_BODY_FRD, OBSTACLE_DISTANCE_SECTORS,
+    },
+    endpoint::{Address, Connection},
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// ArduPilot drops proximity data older than half a second
+const OBSTACLE_DISTANCE_INTERVAL: Duration = Duration::from_millis(200);
+// MAVLink requires UINT8_MAX when the measurement variance is unknown
+const UNKNOWN_COVARIANCE: u8 = u8::MAX;
+const OBSTACLE_SECTOR_DEGREES: f32 = 360.0 / OBSTACLE_DISTANCE_SECTORS as f32;
+const PING360_GRADIANS: f32 = 400.0;
+const PING360_SPEED_OF_SOUND: f32 = 1500.0;
+const PING360_SAMPLE_PERIOD_TICK: f32 = 25e-9;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+#[serde(default)]
+pub struct MavlinkOutputConfig {
+    /// MAVLink endpoint, udpout:<host>:<port> or tcpout:<host>:<port>
+    pub endpoint: String,
+    pub system_id: u8,
+    pub component_id: u8,
+    /// Ping1D only, DISTANCE_SENSOR id
+    pub sensor_id: u8,
+    /// Ping1D only, MAV_SENSOR_ORIENTATION, 25 (pitch 270) for a downward facing altimeter
+    pub orientation: u8,
+    pub min_distance_cm: u16,
+    pub max_distance_cm: u16,
+    /// Ping360 only, intensity from which a sample is considered an obstacle
+    pub threshold: u8,
+    /// Ping360 only, heading of the sonar angle 0 in degrees, clockwise from the vehicle front
+    pub angle_offset: f32,
+    /// Ping360 only, sectors not scanned for this long are reported as unknown
+    pub obstacle_timeout_ms: u32,
+}
+
+impl Default for MavlinkOutputConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "udpout:127.0.0.1:14550".to_string(),
+            system_id: 1,
+            component_id: 194,
+            sensor_id: 1,
+            orientation: 25,
+            min_distance_cm: 30,
+            max_distance_cm: 5000,
+            threshold: 150,
+            angle_offset: 0.0,
+            obstacle_timeout_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MavlinkOutput {
+    pub config: MavlinkOutputConfig,
+    pub handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl MavlinkOutput {
+    pub fn new(config: MavlinkOutputConfig) -> Self {
+        Self {
+            config,
+            handle: None,
+        }
+    }
+}
+
+impl Drop for MavlinkOutput {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+// Distance in millimeters and confidence in percent, from any Ping1D message carrying them
+pub(super) fn ping1d_distance(msg: &ProtocolMessage) -> Option<(u32, u8)> {
+    use bluerobotics_ping::{ping1d, Messages};
+
+    let id = msg.message_id;
+    if id != <ping1d::ProfileStruct as MessageInfo>::id()
+        && id != <ping1d::DistanceStruct as MessageInfo>::id()
+        && id != <ping1d::DistanceSimpleStruct as MessageInfo>::id()
+    {
+        return None;
+    }
+
+    match Messages::try_from(msg).ok()? {
+        Messages::Ping1D(ping1d::Messages::Profile(msg)) => {
+            Some((msg.distance, msg.confidence.min(100) as u8))
+        }
+        Messages::Ping1D(ping1d::Messages::Distance(msg)) => {
+            Some((msg.distance, msg.confidence.min(100) as u8))
+        }
+        Messages::Ping1D(ping1d::Messages::DistanceSimple(msg)) => {
+            Some((msg.distance, msg.confidence.min(100)))
+        }
+        _ => None,
+    }
+}
+
+// Angle in gradians, sample period in 25ns ticks and intensities, from both Ping360 scan messages
+fn ping360_scan(msg: &ProtocolMessage) -> Option<(u16, u16, Vec<u8>)> {
+    use bluerobotics_ping::{ping360, Messages};
+
+    let id = msg.message_id;
+    if id != <ping360::AutoDeviceDataStruct as MessageInfo>::id()
+        && id != <ping360::DeviceDataStruct as MessageInfo>::id()
+    {
+        return None;
+    }
+
+    match Messages::try_from(msg).ok()? {
+        Messages::Ping360(ping360::Messages::AutoDeviceData(msg)) => {
+            Some((msg.angle, msg.sample_period, msg.data))
+        }
+        Messages::Ping360(ping360::Messages::DeviceData(msg)) => {
+            Some((msg.angle, msg.sample_period, msg.data))
+        }
+        _ => None,
+    }
+}
+
+// Range of the first sample above the threshold, skipping the transducer ringing below the minimum distance
+pub fn nearest_obstacle(
+    samples: &[u8],
+    sample_period: u16,
+    threshold: u8,
+    min_distance_cm: u16,
+    max_distance_cm: u16,
+) -> Option<u16> {
+    let sample_cm =
+        sample_period as f32 * PING360_SAMPLE_PERIOD_TICK * PING360_SPEED_OF_SOUND / 2.0 * 100.0;
+    if sample_cm <= 0.0 {
+        return None;
+    }
+
+    samples
+        .iter()
+        .enumerate()
+        .map(|(index, intensity)| (index as f32 * sample_cm, *intensity))
+        .skip_while(|(distance, _)| *distance < min_distance_cm as f32)
+        .take_while(|(distance, _)| *distance <= max_distance_cm as f32)
+        .find(|(_, intensity)| *intensity >= threshold)
+        .map(|(distance, _)| distance.round() as u16)
+}
+
+// Nearest obstacle per sector on its latest sweep, sectors not scanned recently are unknown
+#[derive(Debug, Clone)]
+pub struct ObstacleMap {
+    distances: [u16; OBSTACLE_DISTANCE_SECTORS],
+    scanned_at: [Option<Instant>; OBSTACLE_DISTANCE_SECTORS],
+    // Sector of the previous reading, the sweep leaves a sector once a reading lands elsewhere
+    current_sector: Option<usize>,
+    angle_offset: f32,
+    max_distance_cm: u16,
+    timeout: Duration,
+}
+
+impl ObstacleMap {
+    pub fn new(angle_offset: f32, max_distance_cm: u16, timeout: Duration) -> Self {
+        Self {
+            distances: [u16::MAX; OBSTACLE_DISTANCE_SECTORS],
+            scanned_at: [None; OBSTACLE_DISTANCE_SECTORS],
+            current_sector: None,
+            angle_offset,
+            max_distance_cm,
+            timeout,
+        }
+    }
+
+    pub fn update(&mut self, angle_gradians: u16, obstacle_cm: Option<u16>, now: Instant) {
+        let degrees = (angle_gradians as f32 * 360.0 / PING360_GRADIANS + self.angle_offset)
+            .rem_euclid(360.0);
+        let sector = (degrees / OBSTACLE_SECTOR_DEGREES) as usize % OBSTACLE_DISTANCE_SECTORS;
+        // MAVLink uses max_distance + 1 for a sector scanned without obstacles
+        let distance = obstacle_cm.unwrap_or(self.max_distance_cm.saturating_add(1));
+
+        // A sector holds a few sonar angles, the nearest of them wins until the sweep comes back
+        if self.current_sector == Some(sector) {
+            self.distances[sector] = self.distances[sector].min(distance);
+        } else {
+            self.distances[sector] = distance;
+            self.current_sector = Some(sector);
+        }
+        self.scanned_at[sector] = Some(now);
+    }
+
+    pub fn distances(&self, now: Instant) -> [u16; OBSTACLE_DISTANCE_SECTORS] {
+        std::array::from_fn(|sector| match self.scanned_at[sector] {
+            Some(scanned_at) if now.duration_since(scanned_at) <= self.timeout => {
+                self.distances[sector]
+            }
+            _ => u16::MAX,
+        })
+    }
+}
+
+enum Pipeline {
+    DistanceSensor,
+    ObstacleDistance(ObstacleMap),
+}
+
+fn spawn_output(
+    config: MavlinkOutputConfig,
+    address: Address,
+    mut subscriber: broadcast::Receiver<ReceivedFrame>,
+    device_id: Uuid,
+    device_type: DeviceSelection,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut connection = Connection::new(address, config.system_id, config.component_id);
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut obstacle_interval = tokio::time::interval(OBSTACLE_DISTANCE_INTERVAL);
+        // Messages are stamped with the time their frame was read from the device, obstacles with the newest scan
+        let mut last_scan: Option<FrameTimestamp> = None;
+        let mut pipeline = match device_type {
+            DeviceSelection::Ping360 => Pipeline::ObstacleDistance(ObstacleMap::new(
+                config.angle_offset,
+                config.max_distance_cm,
+                Duration::from_millis(config.obstacle_timeout_ms as u64),
+            )),
+            _ => Pipeline::DistanceSensor,
+        };
+        let publish_obstacles = matches!(pipeline, Pipeline::ObstacleDistance(_));
+
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    if connection.ensure_connected().await {
+                        let _ = connection.send(&Heartbeat::default()).await;
+                    }
+                }
+                _ = obstacle_interval.tick(), if publish_obstacles => {
+                    let Pipeline::ObstacleDistance(map) = &pipeline else {
+                        continue;
+                    };
+                    let message = ObstacleDistance {
+                        time_usec: last_scan.unwrap_or_else(FrameTimestamp::now).monotonic_us,
+                        distances: map.distances(Instant::now()),
+                        min_distance: config.min_distance_cm,
+                        max_distance: config.max_distance_cm,
+                        sensor_type: MAV_DISTANCE_SENSOR_ULTRASOUND,
+                        increment: OBSTACLE_SECTOR_DEGREES as u8,
+                        increment_f: OBSTACLE_SECTOR_DEGREES,
+                        angle_offset: 0.0,
+                        frame: MAV_FRAME_BODY_FRD,
+                    };
+                    if let Err(err) = connection.send(&message).await {
+                        trace!("MAVLink: Failed to send OBSTACLE_DISTANCE: {err}, device: {device_id}");
+                    }
+                }
+                frame = subscriber.recv() => match frame {
+                    Ok(frame) => match &mut pipeline {
+                        Pipeline::DistanceSensor => {
+                            let Some((distance_mm, confidence)) = ping1d_distance(&frame.message) else {
+                                continue;
+                            };
+                            let message = DistanceSensor {
+                                time_boot_ms: (frame.timestamp.monotonic_us / 1000) as u32,
+                                min_distance: config.min_distance_cm,
+                                max_distance: config.max_distance_cm,
+                                current_distance: (distance_mm / 10).min(u16::MAX as u32) as u16,
+                                sensor_type: MAV_DISTANCE_SENSOR_ULTRASOUND,
+                                id: config.sensor_id,
+                                orientation: config.orientation,
+                                covariance: UNKNOWN_COVARIANCE,
+                                // Zero means unknown, so a measurement without confidence still reports the lowest quality
+                                signal_quality: confidence.max(1),
+                                ..Default::default()
+                            };
+                            if let Err(err) = connection.send(&message).await {
+                                trace!("MAVLink: Failed to send DISTANCE_SENSOR: {err}, device: {device_id}");
+                            }
+                        }
+                        Pipeline::ObstacleDistance(map) => {
+                            let Some((angle, sample_period, samples)) = ping360_scan(&frame.message) else {
+                                continue;
+                            };
+                            let obstacle = nearest_obstacle(
+                                &samples,
+                                sample_period,
+                                config.threshold,
+                                config.min_distance_cm,
+                                config.max_distance_cm,
+                            );
+                            map.update(angle, obstacle, Instant::now());
+                            last_scan = Some(frame.timestamp);
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        trace!("MAVLink: Output lagged {skipped} messages, device: {device_id}");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+
+        trace!("MAVLink: Output stopped, device: {device_id}");
+    })
+}
+
+impl DeviceManager {
+    // Setting None stops the output, otherwise it is (re)started with the new configuration
+    pub async fn set_mavlink_output(
+        &mut self,
+        device_id: Uuid,
+        config: Option<MavlinkOutputConfig>,
+    ) -> Result<Answer, ManagerError> {
+        let device = self.get_device(device_id)?;
+        if config.is_some()
+            && !matches!(
+                device.device_type,
+                DeviceSelection::Ping1D | DeviceSelection::Ping360
+            )
+        {
+            return Err(ManagerError::InvalidRequest(format!(
+                "MAVLink output is not available for {:?}, device: {device_id}",
+                device.device_type
+            )));
+        }
+        if let Some(config) = &config {
+            config
+                .endpoint
+                .parse::<Address>()
+                .map_err(ManagerError::InvalidRequest)?;
+        }
+
+        let device = self.get_mut_device(device_id)?;
+        device.mavlink = config.map(MavlinkOutput::new);
+        self.start_mavlink_output(device_id).await?;
+
+        Ok(Answer::DeviceInfo(vec![self.get_device(device_id)?.info()]))
+    }
+
+    // Stopped devices keep their configuration, the output is started again once they are back
+    pub async fn start_mavlink_output(&mut self, device_id: Uuid) -> Result<(), ManagerError> {
+        let device = self.get_device(device_id)?;
+        let Some(output) = &device.mavlink else {
+            return Ok(());
+        };
+        if !matches!(
+            device.status,
+            DeviceStatus::Running | DeviceStatus::ContinuousMode
+        ) {
+            return Ok(());
+        }
+
+        let config = output.config.clone();
+        let device_type = device.device_type.clone();
+        let address = config
+            .endpoint
+            .parse::<Address>()
+            .map_err(ManagerError::Other)?;
+        let subscriber = self.get_subscriber(device_id).await?;
+
+        info!("MAVLink: Sending {device_type:?} data to {address}, device: {device_id}");
+        let handle = spawn_output(config, address, subscriber, device_id, device_type);
+
+        if let Some(output) = self.get_mut_device(device_id)?.mavlink.as_mut() {
+            if let Some(previous) = output.handle.replace(handle) {
+                previous.abort();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{device::manager::frame, mavlink::codec::Decoder};
+
+    #[test]
+    fn test_nearest_obstacle() {
+        // A sample period of 80 ticks is 1.5 mm per sample
+        let mut samples = vec![20u8; 1200];
+        samples[5] = 250;
+        samples[1000] = 200;
+        assert_eq!(nearest_obstacle(&samples, 80, 150, 30, 5000), Some(150));
+        assert_eq!(nearest_obstacle(&samples, 80, 220, 30, 5000), None);
+        assert_eq!(nearest_obstacle(&samples, 80, 150, 30, 100), None);
+    }
+
+    #[test]
+    fn test_obstacle_map_sectors() {
+        let now = Instant::now();
+        let mut map = ObstacleMap::new(90.0, 5000, Duration::from_secs(10));
+        map.update(0, Some(320), now);
+        map.update(300, None, now);
+
+        let distances = map.distances(now);
+        // Sonar angle 0 points to the right of the vehicle, 300 gradians to its front
+        assert_eq!(distances[18], 320);
+        assert_eq!(distances[0], 5001);
+        assert_eq!(distances[1], u16::MAX);
+    }
+
+    #[test]
+    fn test_obstacle_map_sweep() {
+        let now = Instant::now();
+        let mut map = ObstacleMap::new(90.0, 5000, Duration::from_secs(10));
+
+        // Gradians 0 to 5 share sector 18, the nearest reading of the sweep is kept
+        map.update(0, Some(500), now);
+        map.update(1, Some(320), now);
+        map.update(2, None, now);
+        assert_eq!(map.distances(now)[18], 320);
+
+        // Coming back on the next sweep replaces the previous one
+        map.update(6, Some(900), now);
+        map.update(5, Some(700), now);
+        let distances = map.distances(now);
+        assert_eq!(distances[19], 900);
+        assert_eq!(distances[18], 700);
+
+        // Sectors not scanned within the timeout are unknown again
+        map.update(100, Some(400), now + Duration::from_secs(8));
+        let distances = map.distances(now + Duration::from_secs(11));
+        assert_eq!(distances[18], u16::MAX);
+        assert_eq!(distances[19], u16::MAX);
+        assert_eq!(distances[36], 400);
+    }
+
+    #[tokio::test]
+    async fn test_distance_sensor_output() {
+        let listener = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = MavlinkOutputConfig {
+            endpoint: format!("udpout:{}", listener.local_addr().unwrap()),
+            sensor_id: 3,
+            ..Default::default()
+        };
+        let address = config.endpoint.parse::<Address>().unwrap();
+
+        let (sender, subscriber) = broadcast::channel(10);
+        let handle = spawn_output(
+            config,
+            address,
+            subscriber,
+            Uuid::from_u128(1),
+            DeviceSelection::Ping1D,
+        );
+
+        let mut distance_simple = 1520u32.to_le_bytes().to_vec();
+        distance_simple.push(87);
+        let bytes = frame::encode(
+            <bluerobotics_ping::ping1d::DistanceSimpleStruct as MessageInfo>::id(),
+            &distance_simple,
+        );
+        let mut ping_decoder = bluerobotics_ping::decoder::Decoder::new();
+        let msg = bytes
+            .iter()
+            .find_map(|byte| match ping_decoder.parse_byte(*byte) {
+                bluerobotics_ping::decoder::DecoderResult::Success(msg) => Some(msg),
+                _ => None,
+            })
+            .unwrap();
+        let received = ReceivedFrame {
+            timestamp: FrameTimestamp {
+                utc_us: 0,
+                monotonic_us: 5_000_000,
+            },
+            heading: None,
+            message: msg,
+        };
+
+        let mut decoder = Decoder::new();
+        let mut buffer = [0u8; 512];
+        let distance_sensor = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                // Published repeatedly, the first messages may go out before the socket is connected
+                let _ = sender.send(received.clone());
+                if let Ok(Ok(size)) =
+                    tokio::time::timeout(Duration::from_millis(100), listener.recv(&mut buffer))
+                        .await
+                {
+                    if let Some(message) = decoder
+                        .push(&buffer[..size])
+                        .iter()
+                        .find_map(|frame| frame.message::<DistanceSensor>())
+                    {
+                        return message;
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap();
+        handle.abort();
+
+        assert_eq!(distance_sensor.time_boot_ms, 5000);
+        assert_eq!(distance_sensor.current_distance, 152);
+        assert_eq!(distance_sensor.signal_quality, 87);
+        assert_eq!(distance_sensor.id, 3);
+        assert_eq!(distance_sensor.orientation, 25);
+    }
+}
@@ -4,14 +4,37 @@ pub mod continuous_mode;
 pub mod device_discovery;
 /// Specially for continuous_mode methods, startup, shutdown, handle and errors routines for each device type
 pub mod device_handle;
+/// Specially for DeviceManager, expose link statistics and sensor readings of each device
+pub mod device_health;
 /// Specially for DeviceManager, allow discovery service to run on background
 pub mod discovery_service;
+/// Specially for DeviceManager, read and apply the settings shared by Ping1D and TSR1000
+pub mod echosounder;
+/// Specially for DeviceManager, publish device lifecycle events to websocket clients
+pub mod events;
+/// Specially for DeviceManager, flash firmware images through the device bootloader
+pub mod firmware;
+/// Specially for replay and simulated sources, helpers to build raw ping-protocol frames
+pub mod frame;
+/// Specially for DeviceManager, forward Ping1D distances and Ping360 obstacles to an autopilot over MAVLink
+pub mod mavlink_output;
+/// Specially for DeviceManager, publish Ping1D depth as NMEA 0183 sentences over UDP and TCP
+pub mod nmea_output;
+/// Specially for DeviceManager, allow device streams to be stored on disk
+pub mod recording;
+/// Specially for DeviceManager, store created devices on disk and restore them at boot
+pub mod registry;
+/// Specially for File sources, play a recording back as if it were a live device
+pub mod replay;
+/// Specially for Simulated sources, in-process Ping1D, Ping360 and TSR1000 devices with synthetic echoes
+pub mod simulation;
+/// Specially for DeviceManager, recover devices whose actor or stream stopped
+pub mod supervisor;
 
 use paperclip::actix::Apiv2Schema;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
-    hash::{Hash, Hasher},
+    collections::HashMap,
     net::{Ipv4Addr, SocketAddrV4, UdpSocket},
     ops::Deref,
     sync::{Arc, RwLock},
@@ -27,19 +50,27 @@ use tracing::{error, info, trace, warn};
 use udp_stream::UdpStream;
 use uuid::Uuid;
 
-use super::devices::{DeviceActor, DeviceActorHandler, DeviceType, PingAnswer};
+use super::devices::{DeviceActor, DeviceActorHandler, PingAnswer};
 use bluerobotics_ping::{
     common::{DeviceInformationStruct, ProtocolVersionStruct},
     device::{Ping1D, Ping360, Tsr1000},
 };
 use discovery_service::DiscoveryComponent;
+
+use crate::device::health::{LinkHealth, MonitoredStream};
 #[derive(Debug)]
 pub struct Device {
     pub id: Uuid,
+    pub name: Option<String>,
     pub source: SourceSelection,
     pub handler: Option<super::devices::DeviceActorHandler>,
     pub actor: Option<tokio::task::JoinHandle<DeviceActor>>,
     pub broadcast: Option<tokio::task::JoinHandle<()>>,
+    pub recording: Option<recording::Recorder>,
+    pub replay: Option<replay::ReplayHandle>,
+    pub recovery: Option<supervisor::Recovery>,
+    pub mavlink: Option<mavlink_output::MavlinkOutput>,
+    pub nmea: Option<nmea_output::NmeaOutput>,
     pub status: DeviceStatus,
     pub device_type: DeviceSelection,
     pub properties: Option<DeviceProperties>,
@@ -67,6 +98,20 @@ pub struct Ping360Config {
     pub delay: u8,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default, Apiv2Schema)]
+pub struct EchosounderConfig {
+    pub mode_auto: u8,
+    pub scan_start: u32,
+    pub scan_length: u32,
+    pub gain_setting: u8,
+    pub ping_interval: u16,
+    pub speed_of_sound: u32,
+    pub ping_enabled: u8,
+}
+
+pub type Ping1DConfig = EchosounderConfig;
+pub type Tsr1000Config = EchosounderConfig;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommonProperties {
     pub device_information: DeviceInformationStruct,
@@ -76,11 +121,17 @@ pub struct CommonProperties {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Ping1DProperties {
     pub common: CommonProperties,
+    /// Last settings read from the device, None when it couldn't answer them
+    #[serde(default)]
+    pub config: Option<Ping1DConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Tsr1000Properties {
     pub common: CommonProperties,
+    /// Last settings read from the device, None when it couldn't answer them
+    #[serde(default)]
+    pub config: Option<Tsr1000Config>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -92,19 +143,34 @@ pub struct Ping360Properties {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeviceInfo {
     pub id: Uuid,
+    #[serde(default)]
+    pub name: Option<String>,
     pub source: SourceSelection,
     pub status: DeviceStatus,
     pub device_type: DeviceSelection,
     pub properties: Option<DeviceProperties>,
+    #[serde(default)]
+    pub recording: Option<recording::RecordingInfo>,
+    #[serde(default)]
+    pub mavlink: Option<mavlink_output::MavlinkOutputConfig>,
+    #[serde(default)]
+    pub nmea: Option<nmea_output::NmeaOutputConfig>,
 }
 impl Device {
     pub fn info(&self) -> DeviceInfo {
         DeviceInfo {
             id: self.id,
+            name: self.name.clone(),
             source: self.source.clone(),
             status: self.status.clone(),
             device_type: self.device_type.clone(),
             properties: self.properties.clone(),
+            recording: self
+                .recording
+                .as_ref()
+                .map(|recorder| recorder.info.clone()),
+            mavlink: self.mavlink.as_ref().map(|output| output.config.clone()),
+            nmea: self.nmea.as_ref().map(|output| output.config.clone()),
         }
     }
 }
@@ -122,6 +188,10 @@ impl Drop for Device {
             trace!("Device broadcast handle closed for: {:?}", self.info().id);
             broadcast_handle.abort();
         }
+        if let Some(recorder) = self.recording.take() {
+            // Dropping the recorder closes its stop channel, so the file is flushed before the task ends
+            trace!("Device recording closed for: {:?}", recorder.info.path);
+        }
     }
 }
 
@@ -138,11 +208,27 @@ pub enum DeviceSelection {
 pub enum SourceSelection {
     UdpStream(SourceUdpStruct),
     SerialStream(SourceSerialStruct),
+    File(SourceFileStruct),
+    Simulated(SourceSimulatedStruct),
+}
+
+// Namespace of the ids derived from device sources
+const DEVICE_ID_NAMESPACE: Uuid = Uuid::from_u128(0x8f4d_2c1e_6b7a_4e9d_a3f0_5c2b_7d1e_9a64);
+
+impl SourceSelection {
+    // Same source, same id, across restarts and builds
+    pub fn device_id(&self) -> Uuid {
+        let source = serde_json::to_vec(self).unwrap_or_default();
+        Uuid::new_v5(&DEVICE_ID_NAMESPACE, &source)
+    }
 }
 
 enum SourceType {
     Udp(UdpStream),
     Serial(SerialStream),
+    Duplex(tokio::io::DuplexStream),
+    // Not monitored, the replay player reports the frames it plays itself
+    Replay(tokio::io::DuplexStream),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
@@ -157,6 +243,18 @@ pub struct SourceSerialStruct {
     pub baudrate: u32,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
+pub struct SourceFileStruct {
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
+pub struct SourceSimulatedStruct {
+    pub device: simulation::SimulatedDevice,
+    #[serde(default)]
+    pub scene: simulation::SimulatedScene,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DeviceStatus {
     Available,
@@ -169,6 +267,12 @@ pub struct DeviceManager {
     receiver: mpsc::Receiver<ManagerActorRequest>,
     pub device: HashMap<Uuid, Device>,
     discovery_service: DiscoveryComponent,
+    saved_registry: Option<registry::Registry>,
+    published_status: HashMap<Uuid, DeviceStatus>,
+    firmware_sender: mpsc::Sender<firmware::FirmwareUpdateDone>,
+    firmware_receiver: mpsc::Receiver<firmware::FirmwareUpdateDone>,
+    reconnection_sender: mpsc::Sender<supervisor::Reconnection>,
+    reconnection_receiver: mpsc::Receiver<supervisor::Reconnection>,
 }
 
 #[derive(Debug)]
@@ -188,6 +292,11 @@ pub enum Answer {
     InnerDeviceHandler(DeviceActorHandler),
     DeviceInfo(Vec<DeviceInfo>),
     DeviceConfig(ModifyDeviceResult),
+    ReplayStatus(replay::ReplayStatus),
+    DeviceHealth(device_health::DeviceHealth),
+    SearchResult(Vec<DeviceInfo>),
+    FirmwareUpdate(firmware::FirmwareUpdateStatus),
+    Metrics(Vec<device_health::DeviceHealth>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -200,6 +309,10 @@ pub enum ManagerError {
     NoDevices,
     TokioMpsc(String),
     NotImplemented(Request),
+    /// The request can't apply to this device or carries invalid data
+    InvalidRequest(String),
+    /// The request clashes with what the device is already doing
+    Conflict(String),
     Other(String),
 }
 
@@ -208,6 +321,12 @@ pub struct DeviceAnswer {
     #[serde(flatten)]
     pub answer: crate::device::devices::PingAnswer,
     pub device_id: Uuid,
+    /// Vehicle heading in degrees when the message was measured, only on Ping360 continuous mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading: Option<f32>,
+    /// When the message was read from the device, only on continuous mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<crate::device::health::FrameTimestamp>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
@@ -224,6 +343,15 @@ pub enum Request {
     ModifyDevice(ModifyDevice),
     EnableContinuousMode(UuidWrapper),
     DisableContinuousMode(UuidWrapper),
+    StartRecording(UuidWrapper),
+    StopRecording(UuidWrapper),
+    Replay(replay::ReplayControl),
+    Health(UuidWrapper),
+    FirmwareUpdate(firmware::FirmwareUpdateStruct),
+    Metrics,
+    /// Sent by the server when the process is exiting, the manager stops after answering it
+    #[serde(skip)]
+    Shutdown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
@@ -231,12 +359,21 @@ pub enum ModifyDeviceCommand {
     SetIp(Ipv4Addr),
     SetPing360Config(Ping360Config),
     GetPing360Config,
+    SetName(String),
+    SetPing1DConfig(Ping1DConfig),
+    GetPing1DConfig,
+    SetTsr1000Config(Tsr1000Config),
+    GetTsr1000Config,
+    SetMavlinkOutput(Option<mavlink_output::MavlinkOutputConfig>),
+    SetNmeaOutput(Option<nmea_output::NmeaOutputConfig>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ModifyDeviceResult {
     ConfigAcknowledge(ModifyDevice),
     Ping360Config(Ping360Config),
+    Ping1DConfig(Ping1DConfig),
+    Tsr1000Config(Tsr1000Config),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
@@ -315,6 +452,55 @@ impl DeviceManager {
                     error!("DeviceManager: Failed to return DisableContinuousMode response: {e:?}");
                 }
             }
+            Request::StartRecording(uuid) => {
+                let result = self.start_recording(*uuid).await;
+                if let Err(e) = actor_request.respond_to.send(result) {
+                    error!("DeviceManager: Failed to return StartRecording response: {e:?}");
+                }
+            }
+            Request::StopRecording(uuid) => {
+                let result = self.stop_recording(*uuid).await;
+                if let Err(e) = actor_request.respond_to.send(result) {
+                    error!("DeviceManager: Failed to return StopRecording response: {e:?}");
+                }
+            }
+            Request::Replay(request) => {
+                let result = self.replay_control(request).await;
+                if let Err(e) = actor_request.respond_to.send(result) {
+                    error!("DeviceManager: Failed to return Replay response: {e:?}");
+                }
+            }
+            Request::Health(uuid) => {
+                let result = self.health(*uuid);
+                if let Err(e) = actor_request.respond_to.send(result) {
+                    error!("DeviceManager: Failed to return Health response: {e:?}");
+                }
+            }
+            Request::FirmwareUpdate(request) => {
+                let result = self.firmware_update(request).await;
+                if let Err(e) = actor_request.respond_to.send(result) {
+                    error!("DeviceManager: Failed to return FirmwareUpdate response: {e:?}");
+                }
+            }
+            Request::Metrics => {
+                let result = Ok(self.metrics());
+                if let Err(e) = actor_request.respond_to.send(result) {
+                    error!("DeviceManager: Failed to return Metrics response: {e:?}");
+                }
+            }
+            Request::Search => {
+                // Probing sources takes a few seconds, so it runs outside the manager loop
+                let known_devices: Vec<DeviceInfo> =
+                    self.device.values().map(|device| device.info()).collect();
+                let config = self.discovery_service.config().clone();
+                let probing = self.discovery_service.probing();
+                tokio::spawn(async move {
+                    let result = discovery_service::search(known_devices, config, probing).await;
+                    if let Err(e) = actor_request.respond_to.send(result) {
+                        error!("DeviceManager: Failed to return Search response: {e:?}");
+                    }
+                });
+            }
             Request::GetDeviceHandler(id) => {
                 let answer = self.get_device_handler(*id).await;
                 if let Err(e) = actor_request.respond_to.send(answer) {
@@ -340,10 +526,18 @@ impl DeviceManager {
 
     pub fn new(size: usize) -> (Self, ManagerActorHandler) {
         let (sender, receiver) = mpsc::channel(size);
+        let (firmware_sender, firmware_receiver) = firmware::channel();
+        let (reconnection_sender, reconnection_receiver) = supervisor::channel();
         let actor = DeviceManager {
             receiver,
             device: HashMap::new(),
             discovery_service: DiscoveryComponent::new(),
+            saved_registry: None,
+            published_status: HashMap::new(),
+            firmware_sender,
+            firmware_receiver,
+            reconnection_sender,
+            reconnection_receiver,
         };
         let actor_handler = ManagerActorHandler { sender };
 
@@ -351,6 +545,10 @@ impl DeviceManager {
         (actor, actor_handler)
     }
 
+    pub fn set_discovery_config(&mut self, config: discovery_service::DiscoveryConfig) {
+        self.discovery_service.set_config(config);
+    }
+
     pub async fn run(mut self) {
         info!("DeviceManager is running");
 
@@ -361,14 +559,44 @@ impl DeviceManager {
         }
 
         let mut discovery_rx = self.discovery_service.get_discovery_rx();
+        let mut supervisor_interval = tokio::time::interval(Duration::from_secs(1));
+        supervisor_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut health_interval = tokio::time::interval(Duration::from_secs(5));
+        health_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
         loop {
             tokio::select! {
                 Some(msg) = self.receiver.recv() => {
-                    self.update_devices_status().await; // Todo: move to an outer process
+                    if let Request::Shutdown = msg.request {
+                        let result = self.shutdown().await;
+                        if let Err(e) = msg.respond_to.send(result) {
+                            error!("DeviceManager: Failed to return Shutdown response: {e:?}");
+                        }
+                        self.publish_status_changes();
+                        info!("DeviceManager has stopped");
+                        return;
+                    }
+                    self.update_devices_status().await;
                     self.handle_message(msg).await;
+                    self.save_registry().await;
+                }
+                _ = supervisor_interval.tick() => {
+                    self.update_devices_status().await;
+                    self.supervise_devices().await;
+                    self.save_registry().await;
+                }
+                _ = health_interval.tick() => {
+                    self.publish_devices_health();
+                }
+                Some(done) = self.firmware_receiver.recv() => {
+                    self.finish_firmware_update(done);
+                }
+                Some(reconnection) = self.reconnection_receiver.recv() => {
+                    self.finish_reconnection(reconnection).await;
+                    self.save_registry().await;
                 }
                 Ok(device_info) = discovery_rx.recv() => {
+                    events::publish(events::DeviceEvent::Discovered(device_info.clone()));
                     match self.register_device(device_info).await {
                         Ok(_) => {
                             if let Ok(Answer::DeviceInfo(inner) )= self.list().await {
@@ -382,44 +610,105 @@ impl DeviceManager {
                 }
                 else => break,
             }
+            self.publish_status_changes();
         }
 
         error!("DeviceManager has stopped please check your application");
     }
 
-    pub async fn update_devices_status(&mut self) {
-        if let Ok(Answer::DeviceInfo(answer)) = self.list().await {
-            for device in answer {
-                if let Some(device_entry) = self.device.get_mut(&device.id) {
-                    if device_entry.status == DeviceStatus::Stopped {
-                        break;
-                    }
-                    if let Some(handle) = &device_entry.actor {
-                        if handle.is_finished() {
-                            info!("Device stopped, device id: {device:?}");
-                            device_entry.status = DeviceStatus::Stopped;
-                        }
-                    }
+    // Leaves every device idle before the process exits, a Ping360 would otherwise keep its motor running.
+    // The registry is saved first, so devices in continuous mode get it back on the next start.
+    pub async fn shutdown(&mut self) -> Result<Answer, ManagerError> {
+        info!("DeviceManager: Shutting down");
+        self.save_registry().await;
+        self.discovery_service.stop_discovery();
+
+        let device_ids: Vec<Uuid> = self.device.keys().copied().collect();
+        for device_id in &device_ids {
+            if self.get_device(*device_id)?.status == DeviceStatus::ContinuousMode {
+                if let Err(err) = self.continuous_mode_off(*device_id).await {
+                    error!("DeviceManager: Failed to stop continuous mode on shutdown, device: {device_id}, details: {err:?}");
                 }
             }
         }
+        for device_id in &device_ids {
+            if self.get_device(*device_id)?.recording.is_some() {
+                if let Err(err) = self.stop_recording(*device_id).await {
+                    error!("DeviceManager: Failed to stop recording on shutdown, device: {device_id}, details: {err:?}");
+                }
+            }
+        }
+
+        Ok(Answer::DeviceInfo(
+            self.device.values().map(|device| device.info()).collect(),
+        ))
     }
 
     pub async fn create(
         &mut self,
         source: SourceSelection,
-        mut device_selection: DeviceSelection,
+        device_selection: DeviceSelection,
     ) -> Result<Answer, ManagerError> {
-        let mut hasher = DefaultHasher::new();
-        source.hash(&mut hasher);
-        let hash = Uuid::from_u128(hasher.finish().into());
+        let hash = source.device_id();
 
         if self.device.contains_key(&hash) {
             trace!("Device creation error: Device already exist for provided SourceSelection, details: {source:?}");
             return Err(ManagerError::DeviceAlreadyExist(hash));
         }
 
-        let port = match &source {
+        let (device, handler, device_selection, replay_handle) =
+            Self::open_device(&source, device_selection).await?;
+
+        let actor = tokio::spawn(async move { device.run().await });
+
+        let device = Device {
+            id: hash,
+            name: None,
+            source,
+            handler: Some(handler),
+            actor: Some(actor),
+            status: DeviceStatus::Running,
+            broadcast: None,
+            recording: None,
+            replay: replay_handle,
+            recovery: None,
+            mavlink: None,
+            nmea: None,
+            device_type: device_selection,
+            properties: None,
+        };
+
+        self.device.insert(hash, device);
+        events::publish(events::DeviceEvent::Registered(
+            self.get_device(hash)?.info(),
+        ));
+
+        trace!("Updating device properties for: {:?}", hash);
+        let _ = self.update_device_properties(hash).await?;
+
+        trace!("Device broadcast enable by default for: {hash:?}");
+        let device_info = self.continuous_mode(hash).await?;
+
+        info!("New device created and available, details: {device_info:?}");
+        Ok(device_info)
+    }
+
+    // Open the source and build the device actor, upgrading the device type when Auto is selected
+    pub async fn open_device(
+        source: &SourceSelection,
+        mut device_selection: DeviceSelection,
+    ) -> Result<
+        (
+            DeviceActor,
+            DeviceActorHandler,
+            DeviceSelection,
+            Option<replay::ReplayHandle>,
+        ),
+        ManagerError,
+    > {
+        let mut replay_handle = None;
+        let health = Arc::new(LinkHealth::default());
+        let port = match source {
             SourceSelection::UdpStream(source_udp_struct) => {
                 let socket_addr = SocketAddrV4::new(source_udp_struct.ip, source_udp_struct.port);
 
@@ -446,44 +735,97 @@ impl DeviceManager {
 
                 SourceType::Serial(serial_stream)
             }
+            SourceSelection::File(source_file_struct) => {
+                let (replay_stream, handle) =
+                    replay::ReplayPlayer::open(&source_file_struct.path, health.clone()).await?;
+                replay_handle = Some(handle);
+                SourceType::Replay(replay_stream)
+            }
+            SourceSelection::Simulated(source_simulated_struct) => {
+                SourceType::Duplex(simulation::SimulatedPing::start(
+                    source_simulated_struct.device.clone(),
+                    source_simulated_struct.scene.clone(),
+                ))
+            }
         };
 
         let device = match port {
-            SourceType::Udp(udp_port) => match device_selection {
-                DeviceSelection::Common | DeviceSelection::Auto => {
-                    crate::device::devices::DeviceType::Common(
-                        bluerobotics_ping::common::Device::new(udp_port),
-                    )
-                }
-                DeviceSelection::Ping1D => {
-                    crate::device::devices::DeviceType::Ping1D(Ping1D::new(udp_port))
+            SourceType::Udp(udp_port) => {
+                let udp_port = MonitoredStream::new(udp_port, health.clone());
+                match device_selection {
+                    DeviceSelection::Common | DeviceSelection::Auto => {
+                        crate::device::devices::DeviceType::Common(
+                            bluerobotics_ping::common::Device::new(udp_port),
+                        )
+                    }
+                    DeviceSelection::Ping1D => {
+                        crate::device::devices::DeviceType::Ping1D(Ping1D::new(udp_port))
+                    }
+                    DeviceSelection::Ping360 => {
+                        crate::device::devices::DeviceType::Ping360(Ping360::new(udp_port))
+                    }
+                    DeviceSelection::Tsr1000 => {
+                        crate::device::devices::DeviceType::Tsr1000(Tsr1000::new(udp_port))
+                    }
                 }
-                DeviceSelection::Ping360 => {
-                    crate::device::devices::DeviceType::Ping360(Ping360::new(udp_port))
+            }
+            SourceType::Serial(serial_port) => {
+                let serial_port = MonitoredStream::new(serial_port, health.clone());
+                match device_selection {
+                    DeviceSelection::Common | DeviceSelection::Auto => {
+                        crate::device::devices::DeviceType::Common(
+                            bluerobotics_ping::common::Device::new(serial_port),
+                        )
+                    }
+                    DeviceSelection::Ping1D => {
+                        crate::device::devices::DeviceType::Ping1D(Ping1D::new(serial_port))
+                    }
+                    DeviceSelection::Ping360 => {
+                        crate::device::devices::DeviceType::Ping360(Ping360::new(serial_port))
+                    }
+                    DeviceSelection::Tsr1000 => {
+                        crate::device::devices::DeviceType::Tsr1000(Tsr1000::new(serial_port))
+                    }
                 }
-                DeviceSelection::Tsr1000 => {
-                    crate::device::devices::DeviceType::Tsr1000(Tsr1000::new(udp_port))
+            }
+            SourceType::Duplex(duplex_port) => {
+                let duplex_port = MonitoredStream::new(duplex_port, health.clone());
+                match device_selection {
+                    DeviceSelection::Common | DeviceSelection::Auto => {
+                        crate::device::devices::DeviceType::Common(
+                            bluerobotics_ping::common::Device::new(duplex_port),
+                        )
+                    }
+                    DeviceSelection::Ping1D => {
+                        crate::device::devices::DeviceType::Ping1D(Ping1D::new(duplex_port))
+                    }
+                    DeviceSelection::Ping360 => {
+                        crate::device::devices::DeviceType::Ping360(Ping360::new(duplex_port))
+                    }
+                    DeviceSelection::Tsr1000 => {
+                        crate::device::devices::DeviceType::Tsr1000(Tsr1000::new(duplex_port))
+                    }
                 }
-            },
-            SourceType::Serial(serial_port) => match device_selection {
+            }
+            SourceType::Replay(replay_port) => match device_selection {
                 DeviceSelection::Common | DeviceSelection::Auto => {
                     crate::device::devices::DeviceType::Common(
-                        bluerobotics_ping::common::Device::new(serial_port),
+                        bluerobotics_ping::common::Device::new(replay_port),
                     )
                 }
                 DeviceSelection::Ping1D => {
-                    crate::device::devices::DeviceType::Ping1D(Ping1D::new(serial_port))
+                    crate::device::devices::DeviceType::Ping1D(Ping1D::new(replay_port))
                 }
                 DeviceSelection::Ping360 => {
-                    crate::device::devices::DeviceType::Ping360(Ping360::new(serial_port))
+                    crate::device::devices::DeviceType::Ping360(Ping360::new(replay_port))
                 }
                 DeviceSelection::Tsr1000 => {
-                    crate::device::devices::DeviceType::Tsr1000(Tsr1000::new(serial_port))
+                    crate::device::devices::DeviceType::Tsr1000(Tsr1000::new(replay_port))
                 }
             },
         };
 
-        let (mut device, handler) = super::devices::DeviceActor::new(device, 10);
+        let (mut device, handler) = super::devices::DeviceActor::with_health(device, 10, health);
 
         if device_selection == DeviceSelection::Auto {
             let mut retry_count = 0;
@@ -532,29 +874,7 @@ impl DeviceManager {
             }
         }
 
-        let actor = tokio::spawn(async move { device.run().await });
-
-        let device = Device {
-            id: hash,
-            source,
-            handler: Some(handler),
-            actor: Some(actor),
-            status: DeviceStatus::Running,
-            broadcast: None,
-            device_type: device_selection,
-            properties: None,
-        };
-
-        self.device.insert(hash, device);
-
-        trace!("Updating device properties for: {:?}", hash);
-        let _ = self.update_device_properties(hash).await?;
-
-        trace!("Device broadcast enable by default for: {hash:?}");
-        let device_info = self.continuous_mode(hash).await?;
-
-        info!("New device created and available, details: {device_info:?}");
-        Ok(device_info)
+        Ok((device, handler, device_selection, replay_handle))
     }
 
     pub async fn auto_create(&mut self) -> Result<Answer, ManagerError> {
@@ -620,60 +940,15 @@ impl DeviceManager {
         source: SourceSelection,
         device_type: DeviceSelection,
     ) -> Result<DeviceInfo, ManagerError> {
-        let port = match &source {
-            SourceSelection::UdpStream(source_udp_struct) => {
-                let socket_addr = SocketAddrV4::new(source_udp_struct.ip, source_udp_struct.port);
-
-                let udp_stream = UdpStream::connect(socket_addr.into())
-                    .await
-                    .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
-                SourceType::Udp(udp_stream)
-            }
-            SourceSelection::SerialStream(source_serial_struct) => {
-                let mut serial_stream: SerialStream =
-                    tokio_serial::new(&source_serial_struct.path, source_serial_struct.baudrate)
-                        .open_native_async()
-                        .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
-
-                device_discovery::set_baudrate_pre_routine(
-                    &mut serial_stream,
-                    source_serial_struct.baudrate,
-                )
-                .await?;
-
-                serial_stream
-                    .clear(tokio_serial::ClearBuffer::All)
-                    .map_err(|err| ManagerError::DeviceSourceError(err.to_string()))?;
-
-                SourceType::Serial(serial_stream)
-            }
-        };
-
-        let device_type_inner = match port {
-            SourceType::Udp(udp_port) => match device_type {
-                DeviceSelection::Common | DeviceSelection::Auto => {
-                    DeviceType::Common(bluerobotics_ping::common::Device::new(udp_port))
-                }
-                DeviceSelection::Ping1D => DeviceType::Ping1D(Ping1D::new(udp_port)),
-                DeviceSelection::Ping360 => DeviceType::Ping360(Ping360::new(udp_port)),
-                DeviceSelection::Tsr1000 => DeviceType::Tsr1000(Tsr1000::new(udp_port)),
-            },
-            SourceType::Serial(serial_port) => match device_type {
-                DeviceSelection::Common | DeviceSelection::Auto => {
-                    DeviceType::Common(bluerobotics_ping::common::Device::new(serial_port))
-                }
-                DeviceSelection::Ping1D => DeviceType::Ping1D(Ping1D::new(serial_port)),
-                DeviceSelection::Ping360 => DeviceType::Ping360(Ping360::new(serial_port)),
-                DeviceSelection::Tsr1000 => DeviceType::Tsr1000(Tsr1000::new(serial_port)),
-            },
-        };
-
-        let (device_actor, handler) = super::devices::DeviceActor::new(device_type_inner, 10);
+        let (device_actor, handler, device_type, replay_handle) =
+            Self::open_device(&source, device_type).await?;
         let actor = tokio::spawn(async move { device_actor.run().await });
 
         if let Some(device) = self.device.get_mut(&device_id) {
             device.handler = Some(handler.clone());
             device.actor = Some(actor);
+            device.replay = replay_handle;
+            device.device_type = device_type;
             device.status = DeviceStatus::Running;
         } else {
             return Err(ManagerError::DeviceNotExist(device_id));
@@ -732,11 +1007,17 @@ impl DeviceManager {
 
         let device = Device {
             id: device_info.id,
+            name: device_info.name,
             source: device_info.source,
             handler: None,
             actor: None,
             status: DeviceStatus::Available,
             broadcast: None,
+            recording: None,
+            replay: None,
+            recovery: None,
+            mavlink: None,
+            nmea: None,
             device_type: device_info.device_type,
             properties: device_info.properties,
         };
@@ -744,6 +1025,7 @@ impl DeviceManager {
         let info = device.info();
 
         self.device.insert(id, device);
+        events::publish(events::DeviceEvent::Registered(info.clone()));
 
         if let Ok(Answer::DeviceInfo(inner)) = self.list().await {
             self.discovery_service.broadcast_known_devices(&inner);
@@ -758,6 +1040,7 @@ impl DeviceManager {
             .remove(&id)
             .ok_or(ManagerError::DeviceNotExist(id))?;
         let device_info = device.info();
+        events::publish(events::DeviceEvent::Deleted(device_info.clone()));
 
         if let Ok(Answer::DeviceInfo(inner)) = self.list().await {
             self.discovery_service.broadcast_known_devices(&inner);
@@ -811,6 +1094,9 @@ impl DeviceManager {
                 device.status = DeviceStatus::ContinuousMode;
 
                 let updated_device_info = self.get_device(device_id)?.info();
+                events::publish(events::DeviceEvent::ContinuousModeStarted(
+                    updated_device_info.clone(),
+                ));
 
                 Ok(Answer::DeviceInfo(vec![updated_device_info]))
             }
@@ -832,6 +1118,10 @@ impl DeviceManager {
 
         let updated_device_info = device.info();
 
+        events::publish(events::DeviceEvent::ContinuousModeStopped(
+            updated_device_info.clone(),
+        ));
+
         self.continuous_mode_shutdown_routine(device_id, device_type)
             .await?;
 
@@ -849,9 +1139,23 @@ impl DeviceManager {
         )?;
 
         let handler = self.extract_handler(self.get_device_handler(device_id).await?)?;
+        let device_type = self.get_device(device_id)?.device_type.clone();
+
+        let properties = Self::read_device_properties(&handler, device_id, &device_type).await?;
 
         let device = self.get_mut_device(device_id)?;
+        device.properties = properties;
+        events::publish(events::DeviceEvent::PropertiesUpdated(device.info()));
 
+        Ok(())
+    }
+
+    // Asks the device for its properties, without the manager so it can be done off its loop too
+    pub async fn read_device_properties(
+        handler: &DeviceActorHandler,
+        device_id: Uuid,
+        device_type: &DeviceSelection,
+    ) -> Result<Option<DeviceProperties>, ManagerError> {
         let device_information = handler
             .send(super::devices::PingRequest::Common(
                 super::devices::PingCommonRequest::DeviceInformation,
@@ -897,23 +1201,41 @@ impl DeviceManager {
             protocol_version,
         };
 
-        match &device.device_type {
-            DeviceSelection::Common => {
-                device.properties = Some(DeviceProperties::Common(common_properties))
-            }
+        let properties = match device_type {
+            DeviceSelection::Common => Some(DeviceProperties::Common(common_properties)),
             DeviceSelection::Ping1D => {
+                let config = Self::read_echosounder_config::<super::devices::Ping1DRequest>(
+                    &handler, device_id,
+                )
+                .await
+                .map_err(|err| {
+                    warn!("Failed to read Ping1DConfig: {err:?}, device: {device_id}");
+                })
+                .ok();
+
                 let ping_1d_properties = Ping1DProperties {
                     common: common_properties,
+                    config,
                 };
 
-                device.properties = Some(DeviceProperties::Ping1D(ping_1d_properties))
+                Some(DeviceProperties::Ping1D(ping_1d_properties))
             }
             DeviceSelection::Tsr1000 => {
+                let config = Self::read_echosounder_config::<super::devices::Tsr1000Request>(
+                    &handler, device_id,
+                )
+                .await
+                .map_err(|err| {
+                    warn!("Failed to read Tsr1000Config: {err:?}, device: {device_id}");
+                })
+                .ok();
+
                 let tsr1000_properties = Tsr1000Properties {
                     common: common_properties,
+                    config,
                 };
 
-                device.properties = Some(DeviceProperties::Tsr1000(tsr1000_properties))
+                Some(DeviceProperties::Tsr1000(tsr1000_properties))
             }
             DeviceSelection::Ping360 => {
                 let device_data = handler
@@ -953,12 +1275,12 @@ impl DeviceManager {
                     continuous_mode_settings: Arc::new(RwLock::new(auto_transmit)),
                 };
 
-                device.properties = Some(DeviceProperties::Ping360(ping_360_properties))
+                Some(DeviceProperties::Ping360(ping_360_properties))
             }
-            DeviceSelection::Auto => device.properties = None,
+            DeviceSelection::Auto => None,
         };
 
-        Ok(())
+        Ok(properties)
     }
 
     async fn get_device_properties(
@@ -1013,7 +1335,28 @@ impl DeviceManager {
         ))
     }
 
+    // Applied settings are published as ConfigChanged, reading them is not an event
     pub async fn modify_device(&mut self, request: ModifyDevice) -> Result<Answer, ManagerError> {
+        let device_id = request.uuid;
+        let change = (!matches!(
+            request.modify,
+            ModifyDeviceCommand::GetPing360Config
+                | ModifyDeviceCommand::GetPing1DConfig
+                | ModifyDeviceCommand::GetTsr1000Config
+        ))
+        .then(|| request.modify.clone());
+
+        let answer = self.apply_modify_device(request).await?;
+        if let Some(change) = change {
+            events::publish(events::DeviceEvent::ConfigChanged(events::ConfigChange {
+                device_id,
+                change,
+            }));
+        }
+        Ok(answer)
+    }
+
+    async fn apply_modify_device(&mut self, request: ModifyDevice) -> Result<Answer, ManagerError> {
         match request.modify {
             ModifyDeviceCommand::SetIp(ip) => {
                 let device_info = self.info(request.uuid).await?;
@@ -1044,6 +1387,34 @@ impl DeviceManager {
                 )))
             }
             ModifyDeviceCommand::GetPing360Config => self.get_ping360_config(request.uuid).await,
+            ModifyDeviceCommand::SetName(name) => {
+                let device = self.get_mut_device(request.uuid)?;
+                let name = name.trim();
+                device.name = (!name.is_empty()).then(|| name.to_string());
+                Ok(Answer::DeviceInfo(vec![device.info()]))
+            }
+            ModifyDeviceCommand::SetPing1DConfig(config) => {
+                self.set_echosounder_config::<super::devices::Ping1DRequest>(request.uuid, config)
+                    .await
+            }
+            ModifyDeviceCommand::GetPing1DConfig => {
+                self.get_echosounder_config::<super::devices::Ping1DRequest>(request.uuid)
+                    .await
+            }
+            ModifyDeviceCommand::SetTsr1000Config(config) => {
+                self.set_echosounder_config::<super::devices::Tsr1000Request>(request.uuid, config)
+                    .await
+            }
+            ModifyDeviceCommand::GetTsr1000Config => {
+                self.get_echosounder_config::<super::devices::Tsr1000Request>(request.uuid)
+                    .await
+            }
+            ModifyDeviceCommand::SetMavlinkOutput(config) => {
+                self.set_mavlink_output(request.uuid, config).await
+            }
+            ModifyDeviceCommand::SetNmeaOutput(config) => {
+                self.set_nmea_output(request.uuid, config).await
+            }
         }
     }
 
@@ -1068,6 +1439,11 @@ impl DeviceManager {
 }
 
 impl ManagerActorHandler {
+    // Requests waiting for the manager loop to pick them up
+    pub fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
     pub async fn send(&self, request: Request) -> Result<Answer, ManagerError> {
         let (result_sender, result_receiver) = oneshot::channel();
 
@@ -1111,6 +1487,8 @@ impl ManagerActorHandler {
                                 Ok(Answer::DeviceMessage(DeviceAnswer {
                                     answer: result,
                                     device_id: request.uuid,
+                                    heading: None,
+                                    timestamp: None,
                                 }))
                             }
                             Err(err) => {
@@ -0,0 +1,339 @@
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::broadcast,
+};
+use tracing::{info, trace};
+use uuid::Uuid;
+
+use super::{
+    mavlink_output::ping1d_distance, Answer, DeviceManager, DeviceSelection, DeviceStatus,
+    ManagerError,
+};
+use crate::device::health::ReceivedFrame;
+
+const METERS_PER_FOOT: f32 = 0.3048;
+const METERS_PER_FATHOM: f32 = 1.8288;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+#[serde(default)]
+pub struct NmeaOutputConfig {
+    /// UDP destination of the sentences, broadcast addresses are allowed, None disables UDP
+    pub udp_address: Option<String>,
+    /// Address of the TCP server clients connect to, None disables TCP.
+    /// Disabled by default, since each device needs its own port, e.g. "0.0.0.0:10110"
+    pub tcp_address: Option<String>,
+    /// Meters, positive from the transducer to the waterline, negative from the transducer to the keel
+    pub transducer_offset: f32,
+}
+
+impl Default for NmeaOutputConfig {
+    fn default() -> Self {
+        Self {
+            udp_address: Some("255.255.255.255:10110".to_string()),
+            tcp_address: None,
+            transducer_offset: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NmeaOutput {
+    pub config: NmeaOutputConfig,
+    pub handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl NmeaOutput {
+    pub fn new(config: NmeaOutputConfig) -> Self {
+        Self {
+            config,
+            handle: None,
+        }
+    }
+
+    // Waits for the task to finish, so its sockets are released before binding new ones
+    pub async fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            let _ = handle.await;
+        }
+    }
+}
+
+impl Drop for NmeaOutput {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+fn sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+    format!("${body}*{checksum:02X}\r\n")
+}
+
+// Depth below transducer
+pub fn dbt_sentence(depth: f32) -> String {
+    sentence(&format!(
+        "SDDBT,{:.2},f,{depth:.2},M,{:.2},F",
+        depth / METERS_PER_FOOT,
+        depth / METERS_PER_FATHOM
+    ))
+}
+
+// Depth below transducer and the offset receivers apply to get the depth below waterline or keel
+pub fn dpt_sentence(depth: f32, transducer_offset: f32) -> String {
+    sentence(&format!("SDDPT,{depth:.2},{transducer_offset:.2}"))
+}
+
+struct Sockets {
+    udp: Option<(UdpSocket, String)>,
+    tcp: Option<TcpListener>,
+}
+
+impl Sockets {
+    async fn open(config: &NmeaOutputConfig) -> Result<Self, ManagerError> {
+        let udp = match &config.udp_address {
+            Some(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")
+                    .await
+                    .map_err(|err| ManagerError::Other(format!("NMEA: UDP: {err}")))?;
+                socket
+                    .set_broadcast(true)
+                    .map_err(|err| ManagerError::Other(format!("NMEA: UDP: {err}")))?;
+                Some((socket, address.clone()))
+            }
+            None => None,
+        };
+
+        let tcp = match &config.tcp_address {
+            Some(address) => Some(TcpListener::bind(address).await.map_err(|err| {
+                ManagerError::Other(format!("NMEA: Failed to listen on {address}: {err}"))
+            })?),
+            None => None,
+        };
+
+        Ok(Self { udp, tcp })
+    }
+}
+
+async fn accept(
+    listener: &Option<TcpListener>,
+) -> std::io::Result<(TcpStream, std::net::SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn spawn_output(
+    config: NmeaOutputConfig,
+    sockets: Sockets,
+    mut subscriber: broadcast::Receiver<ReceivedFrame>,
+    device_id: Uuid,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut clients: Vec<TcpStream> = Vec::new();
+
+        loop {
+            tokio::select! {
+                client = accept(&sockets.tcp) => match client {
+                    Ok((stream, address)) => {
+                        trace!("NMEA: Client connected from {address}, device: {device_id}");
+                        clients.push(stream);
+                    }
+                    Err(err) => trace!("NMEA: Failed to accept client: {err}, device: {device_id}"),
+                },
+                frame = subscriber.recv() => match frame {
+                    Ok(frame) => {
+                        let Some((distance_mm, confidence)) = ping1d_distance(&frame.message) else {
+                            continue;
+                        };
+                        // Without confidence the device has lost the bottom, a zero depth would trigger shallow alarms
+                        if confidence == 0 {
+                            continue;
+                        }
+
+                        let depth = distance_mm as f32 / 1000.0;
+                        let sentences =
+                            dbt_sentence(depth) + &dpt_sentence(depth, config.transducer_offset);
+
+                        if let Some((socket, address)) = &sockets.udp {
+                            if let Err(err) = socket.send_to(sentences.as_bytes(), address).await {
+                                trace!("NMEA: Failed to send to {address}: {err}, device: {device_id}");
+                            }
+                        }
+
+                        let mut index = 0;
+                        while index < clients.len() {
+                            if clients[index].write_all(sentences.as_bytes()).await.is_err() {
+                                trace!("NMEA: Client disconnected, device: {device_id}");
+                                clients.swap_remove(index);
+                            } else {
+                                index += 1;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        trace!("NMEA: Output lagged {skipped} messages, device: {device_id}");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+
+        trace!("NMEA: Output stopped, device: {device_id}");
+    })
+}
+
+impl DeviceManager {
+    // Setting None stops the output, otherwise it is (re)started with the new configuration
+    pub async fn set_nmea_output(
+        &mut self,
+        device_id: Uuid,
+        config: Option<NmeaOutputConfig>,
+    ) -> Result<Answer, ManagerError> {
+        let device = self.get_device(device_id)?;
+        if config.is_some() && device.device_type != DeviceSelection::Ping1D {
+            return Err(ManagerError::InvalidRequest(format!(
+                "NMEA output is only available for Ping1D, device: {device_id}"
+            )));
+        }
+
+        if let Some(mut output) = self.get_mut_device(device_id)?.nmea.take() {
+            output.stop().await;
+        }
+        self.get_mut_device(device_id)?.nmea = config.map(NmeaOutput::new);
+        self.start_nmea_output(device_id).await?;
+
+        Ok(Answer::DeviceInfo(vec![self.get_device(device_id)?.info()]))
+    }
+
+    // Stopped devices keep their configuration, the output is started again once they are back
+    pub async fn start_nmea_output(&mut self, device_id: Uuid) -> Result<(), ManagerError> {
+        let device = self.get_device(device_id)?;
+        let Some(output) = &device.nmea else {
+            return Ok(());
+        };
+        if !matches!(
+            device.status,
+            DeviceStatus::Running | DeviceStatus::ContinuousMode
+        ) {
+            return Ok(());
+        }
+
+        let config = output.config.clone();
+        let subscriber = self.get_subscriber(device_id).await?;
+        if let Some(output) = self.get_mut_device(device_id)?.nmea.as_mut() {
+            output.stop().await;
+        }
+        let sockets = Sockets::open(&config).await?;
+
+        info!(
+            "NMEA: Sending depth to udp: {:?}, tcp: {:?}, device: {device_id}",
+            config.udp_address, config.tcp_address
+        );
+        let handle = spawn_output(config, sockets, subscriber, device_id);
+
+        if let Some(output) = self.get_mut_device(device_id)?.nmea.as_mut() {
+            output.handle = Some(handle);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bluerobotics_ping::message::MessageInfo;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use super::*;
+    use crate::device::{health::FrameTimestamp, manager::frame};
+
+    #[test]
+    fn test_sentences() {
+        assert_eq!(dbt_sentence(1.52), "$SDDBT,4.99,f,1.52,M,0.83,F*3F\r\n");
+        assert_eq!(dpt_sentence(1.52, 0.3), "$SDDPT,1.52,0.30*52\r\n");
+    }
+
+    // Several devices keep the defaults, so they must not claim a fixed port
+    #[tokio::test]
+    async fn test_default_config_per_device() {
+        let config = NmeaOutputConfig::default();
+        let first = Sockets::open(&config).await.unwrap();
+        let second = Sockets::open(&config).await.unwrap();
+        assert!(first.tcp.is_none() && second.tcp.is_none());
+        assert!(first.udp.is_some() && second.udp.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_depth_output() {
+        let udp_listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = NmeaOutputConfig {
+            udp_address: Some(udp_listener.local_addr().unwrap().to_string()),
+            tcp_address: Some("127.0.0.1:0".to_string()),
+            transducer_offset: 0.3,
+        };
+        let sockets = Sockets::open(&config).await.unwrap();
+        let tcp_address = sockets.tcp.as_ref().unwrap().local_addr().unwrap();
+
+        let (sender, subscriber) = broadcast::channel(10);
+        let handle = spawn_output(config, sockets, subscriber, Uuid::from_u128(1));
+        let mut tcp_client = BufReader::new(TcpStream::connect(tcp_address).await.unwrap());
+
+        let mut distance_simple = 1520u32.to_le_bytes().to_vec();
+        distance_simple.push(87);
+        let bytes = frame::encode(
+            <bluerobotics_ping::ping1d::DistanceSimpleStruct as MessageInfo>::id(),
+            &distance_simple,
+        );
+        let mut ping_decoder = bluerobotics_ping::decoder::Decoder::new();
+        let msg = bytes
+            .iter()
+            .find_map(|byte| match ping_decoder.parse_byte(*byte) {
+                bluerobotics_ping::decoder::DecoderResult::Success(msg) => Some(msg),
+                _ => None,
+            })
+            .unwrap();
+        let received = ReceivedFrame {
+            timestamp: FrameTimestamp::now(),
+            heading: None,
+            message: msg,
+        };
+
+        let mut buffer = [0u8; 512];
+        let (udp, tcp) = tokio::time::timeout(Duration::from_secs(5), async {
+            // Published repeatedly, the first messages may go out before the TCP client is accepted
+            let mut line = String::new();
+            loop {
+                let _ = sender.send(received.clone());
+                if let Ok(Ok(size)) = tokio::time::timeout(
+                    Duration::from_millis(100),
+                    tcp_client.read_line(&mut line),
+                )
+                .await
+                {
+                    if size > 0 {
+                        break;
+                    }
+                }
+            }
+            let size = udp_listener.recv(&mut buffer).await.unwrap();
+            (String::from_utf8_lossy(&buffer[..size]).to_string(), line)
+        })
+        .await
+        .unwrap();
+        handle.abort();
+
+        assert_eq!(
+            udp,
+            "$SDDBT,4.99,f,1.52,M,0.83,F*3F\r\n$SDDPT,1.52,0.30*52\r\n"
+        );
+        assert_eq!(tcp, "$SDDBT,4.99,f,1.52,M,0.83,F*3F\r\n");
+    }
+}
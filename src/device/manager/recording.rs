@@ -0,0 +1,426 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bluerobotics_ping::message::MessageInfo;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{broadcast, oneshot},
+};
+use tracing::{error, info, trace, warn};
+use uuid::Uuid;
+
+use crate::device::health::ReceivedFrame;
+use crate::device::manager::{
+    frame, DeviceManager, DeviceProperties, DeviceSelection, ManagerError, SourceSelection,
+};
+
+/// Magic bytes used to identify a ping-viewer-next recording file.
+pub const RECORDING_MAGIC: &[u8; 8] = b"PVNREC01";
+pub const RECORDING_EXTENSION: &str = "pvr";
+// Ping-protocol frames carry at most a u16 payload, plus 8 bytes of header and 2 of checksum
+const MAX_FRAME_SIZE: usize = u16::MAX as usize + 10;
+
+// Recording file layout:
+// [magic: 8 bytes][header length: u32 LE][header: JSON RecordingHeader]
+// followed by frames of:
+// [timestamp: i64 LE, microseconds since UNIX epoch when the frame was read from the device]
+// [heading: f32 LE, vehicle heading in degrees when the frame was read, NaN when unknown]
+// [frame length: u32 LE][raw ping-protocol frame]
+
+// Bytes taken in the file by a frame carrying this much data
+pub fn frame_size(data_length: usize) -> u64 {
+    8 + 4 + 4 + data_length as u64
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecordingHeader {
+    pub device_id: Uuid,
+    pub source: SourceSelection,
+    pub device_type: DeviceSelection,
+    pub started_at: String,
+    /// DeviceInformation and ProtocolVersion frames of the device, the stream doesn't repeat them
+    pub identification: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecordingInfo {
+    pub path: String,
+    pub started_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    pub timestamp_us: i64,
+    pub heading: Option<f32>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    pub info: RecordingInfo,
+    stop: Option<oneshot::Sender<()>>,
+    handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub async fn start(
+        mut subscriber: broadcast::Receiver<ReceivedFrame>,
+        header: RecordingHeader,
+        directory: &Path,
+    ) -> Result<Self, ManagerError> {
+        tokio::fs::create_dir_all(directory)
+            .await
+            .map_err(|err| ManagerError::Other(format!("Recording: {err}")))?;
+
+        let file_name = format!(
+            "{}_{}.{RECORDING_EXTENSION}",
+            chrono::Local::now().format("%Y-%m-%d-%H-%M-%S"),
+            header.device_id
+        );
+        let path = directory.join(file_name);
+
+        let file = File::create(&path)
+            .await
+            .map_err(|err| ManagerError::Other(format!("Recording: {err}")))?;
+        let mut writer = BufWriter::new(file);
+
+        write_header(&mut writer, &header)
+            .await
+            .map_err(|err| ManagerError::Other(format!("Recording: {err}")))?;
+
+        let info = RecordingInfo {
+            path: path.to_string_lossy().to_string(),
+            started_at: header.started_at.clone(),
+        };
+
+        let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+        let device_id = header.device_id;
+
+        let handle = tokio::spawn(async move {
+            let mut flush_interval = tokio::time::interval(Duration::from_secs(1));
+
+            loop {
+                tokio::select! {
+                    frame = subscriber.recv() => match frame {
+                        Ok(frame) => {
+                            if let Err(err) = write_frame(&mut writer, frame.timestamp.utc_us, frame.heading, &frame.message.serialized()).await {
+                                error!("Recording: Failed to write frame: {err:?}, device: {device_id}");
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Recording: Lagged behind device stream, {skipped} messages lost, device: {device_id}");
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            trace!("Recording: Device stream closed, device: {device_id}");
+                            break;
+                        }
+                    },
+                    _ = flush_interval.tick() => {
+                        if let Err(err) = writer.flush().await {
+                            error!("Recording: Failed to flush file: {err:?}, device: {device_id}");
+                            break;
+                        }
+                    }
+                    _ = &mut stop_rx => break,
+                }
+            }
+
+            if let Err(err) = writer.flush().await {
+                error!("Recording: Failed to flush file: {err:?}, device: {device_id}");
+            }
+            trace!("Recording: Finished, device: {device_id}");
+        });
+
+        Ok(Self {
+            info,
+            stop: Some(stop_tx),
+            handle: Some(handle),
+        })
+    }
+
+    pub async fn stop(mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = handle.await {
+                error!("Recording: Failed to finish recording task: {err:?}");
+            }
+        }
+    }
+}
+
+pub async fn write_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &RecordingHeader,
+) -> std::io::Result<()> {
+    let header = serde_json::to_vec(header)?;
+    writer.write_all(RECORDING_MAGIC).await?;
+    writer
+        .write_all(&(header.len() as u32).to_le_bytes())
+        .await?;
+    writer.write_all(&header).await
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    timestamp_us: i64,
+    heading: Option<f32>,
+    data: &[u8],
+) -> std::io::Result<()> {
+    writer.write_all(&timestamp_us.to_le_bytes()).await?;
+    writer
+        .write_all(&heading.unwrap_or(f32::NAN).to_le_bytes())
+        .await?;
+    writer.write_all(&(data.len() as u32).to_le_bytes()).await?;
+    writer.write_all(data).await
+}
+
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<RecordingHeader> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).await?;
+    if &magic != RECORDING_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Not a ping-viewer-next recording file",
+        ));
+    }
+
+    let length = reader.read_u32_le().await?;
+    let mut header = vec![0u8; length as usize];
+    reader.read_exact(&mut header).await?;
+
+    serde_json::from_slice(&header)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+// Returns None when the end of the recording is reached, a frame cut short by a crash or power loss
+// while recording is the end of the recording too
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<Option<RecordedFrame>> {
+    match read_complete_frame(reader).await {
+        Ok(frame) => Ok(Some(frame)),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn read_complete_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<RecordedFrame> {
+    let timestamp_us = reader.read_i64_le().await?;
+    let heading = Some(reader.read_f32_le().await?).filter(|heading| !heading.is_nan());
+
+    let length = reader.read_u32_le().await?;
+    if length as usize > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Frame of {length} bytes is larger than a ping-protocol frame"),
+        ));
+    }
+    let mut data = vec![0u8; length as usize];
+    reader.read_exact(&mut data).await?;
+
+    Ok(RecordedFrame {
+        timestamp_us,
+        heading,
+        data,
+    })
+}
+
+pub fn get_recording_dir() -> PathBuf {
+    #[cfg(feature = "desktop-app")]
+    {
+        crate::logger::manager::get_app_home_dir().join("recordings")
+    }
+
+    #[cfg(not(feature = "desktop-app"))]
+    {
+        PathBuf::from(crate::cli::manager::recording_path())
+    }
+}
+
+// Encoded from the properties, requesting them could stop a Ping360 streaming in auto-transmit mode
+fn identification_frames(properties: Option<&DeviceProperties>) -> Vec<Vec<u8>> {
+    let Some(common) = properties.map(|properties| match properties {
+        DeviceProperties::Common(common) => common,
+        DeviceProperties::Ping1D(properties) => &properties.common,
+        DeviceProperties::Ping360(properties) => &properties.common,
+        DeviceProperties::Tsr1000(properties) => &properties.common,
+    }) else {
+        return Vec::new();
+    };
+
+    let information = &common.device_information;
+    let version = &common.protocol_version;
+    vec![
+        frame::encode(
+            <bluerobotics_ping::common::DeviceInformationStruct as MessageInfo>::id(),
+            &[
+                information.device_type,
+                information.device_revision,
+                information.firmware_version_major,
+                information.firmware_version_minor,
+                information.firmware_version_patch,
+                information.reserved,
+            ],
+        ),
+        frame::encode(
+            <bluerobotics_ping::common::ProtocolVersionStruct as MessageInfo>::id(),
+            &[
+                version.version_major,
+                version.version_minor,
+                version.version_patch,
+                version.reserved,
+            ],
+        ),
+    ]
+}
+
+impl DeviceManager {
+    pub async fn start_recording(
+        &mut self,
+        device_id: Uuid,
+    ) -> Result<super::Answer, ManagerError> {
+        self.start_recording_to(device_id, &get_recording_dir())
+            .await
+    }
+
+    pub async fn start_recording_to(
+        &mut self,
+        device_id: Uuid,
+        directory: &Path,
+    ) -> Result<super::Answer, ManagerError> {
+        if let Some(recording) = &self.get_device(device_id)?.recording {
+            return Err(ManagerError::Conflict(format!(
+                "Device is already being recorded to: {}, device: {device_id}",
+                recording.info.path
+            )));
+        }
+
+        let subscriber = self.get_subscriber(device_id).await?;
+
+        let device = self.get_device(device_id)?;
+        let header = RecordingHeader {
+            device_id,
+            source: device.source.clone(),
+            device_type: device.device_type.clone(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            identification: identification_frames(device.properties.as_ref()),
+        };
+
+        let recorder = Recorder::start(subscriber, header, directory).await?;
+        info!(
+            "Recording started for device: {device_id}, file: {}",
+            recorder.info.path
+        );
+
+        let device = self.get_mut_device(device_id)?;
+        device.recording = Some(recorder);
+
+        Ok(super::Answer::DeviceInfo(vec![device.info()]))
+    }
+
+    pub async fn stop_recording(&mut self, device_id: Uuid) -> Result<super::Answer, ManagerError> {
+        let device = self.get_mut_device(device_id)?;
+        let Some(recorder) = device.recording.take() else {
+            return Err(ManagerError::Conflict(format!(
+                "Device is not being recorded, device: {device_id}"
+            )));
+        };
+
+        let path = recorder.info.path.clone();
+        recorder.stop().await;
+        info!("Recording stopped for device: {device_id}, file: {path}");
+
+        Ok(super::Answer::DeviceInfo(vec![self
+            .get_device(device_id)?
+            .info()]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::manager::SourceUdpStruct;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn test_recording_roundtrip() {
+        let header = RecordingHeader {
+            device_id: Uuid::from_u128(1),
+            source: SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::new(192, 168, 2, 2),
+                port: 9092,
+            }),
+            device_type: DeviceSelection::Ping360,
+            started_at: "2024-01-01T00:00:00+00:00".to_string(),
+            identification: vec![frame::encode(5, &[1, 0, 0, 0])],
+        };
+        let frames = vec![
+            RecordedFrame {
+                timestamp_us: 1_000,
+                heading: Some(271.5),
+                data: vec![b'B', b'R', 0, 0],
+            },
+            RecordedFrame {
+                timestamp_us: 2_500,
+                heading: None,
+                data: vec![b'B', b'R', 1, 2, 3],
+            },
+        ];
+
+        let mut buffer = Vec::new();
+        write_header(&mut buffer, &header).await.unwrap();
+        for frame in &frames {
+            write_frame(&mut buffer, frame.timestamp_us, frame.heading, &frame.data)
+                .await
+                .unwrap();
+        }
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_header(&mut reader).await.unwrap(), header);
+        for frame in frames {
+            assert_eq!(read_frame(&mut reader).await.unwrap(), Some(frame));
+        }
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+        assert_eq!(frame_size(4), 20);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_recording() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, 1_000, None, &[b'B', b'R', 0, 0])
+            .await
+            .unwrap();
+        let complete = buffer.len();
+        write_frame(&mut buffer, 2_000, None, &[b'B', b'R', 1, 2, 3])
+            .await
+            .unwrap();
+
+        // Cut in the timestamp, the heading, the length and the data of the last frame
+        for length in [complete + 4, complete + 10, complete + 14, buffer.len() - 1] {
+            let mut reader = &buffer[..length];
+            assert!(read_frame(&mut reader).await.unwrap().is_some());
+            assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+        }
+
+        // Lengths come from the file, they are not trusted beyond a ping-protocol frame
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&1_000i64.to_le_bytes());
+        buffer.extend_from_slice(&f32::NAN.to_le_bytes());
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = buffer.as_slice();
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_recording_header() {
+        let mut reader: &[u8] = b"INVALID_RECORDING";
+        assert!(read_header(&mut reader).await.is_err());
+    }
+}
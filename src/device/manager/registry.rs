@@ -0,0 +1,389 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{error, info, trace, warn};
+use uuid::Uuid;
+
+use super::{
+    mavlink_output::{MavlinkOutput, MavlinkOutputConfig},
+    nmea_output::{NmeaOutput, NmeaOutputConfig},
+    supervisor::Recovery,
+    Device, DeviceManager, DeviceProperties, DeviceSelection, DeviceStatus, ManagerError,
+    Ping360Config, SourceSelection,
+};
+
+const REGISTRY_VERSION: u32 = 1;
+
+// Devices created by the user, stored to be restored at boot
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RegistryEntry {
+    pub id: Uuid,
+    pub source: SourceSelection,
+    pub device_type: DeviceSelection,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub ping360_config: Option<Ping360Config>,
+    #[serde(default)]
+    pub continuous_mode: bool,
+    #[serde(default)]
+    pub mavlink: Option<MavlinkOutputConfig>,
+    #[serde(default)]
+    pub nmea: Option<NmeaOutputConfig>,
+}
+
+// Devices declared on the configuration file, created at boot when they are not on the registry yet
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConfiguredDevice {
+    pub source: SourceSelection,
+    #[serde(default = "default_device_selection")]
+    pub device_selection: DeviceSelection,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub mavlink_output: Option<MavlinkOutputConfig>,
+    #[serde(default)]
+    pub nmea_output: Option<NmeaOutputConfig>,
+}
+
+fn default_device_selection() -> DeviceSelection {
+    DeviceSelection::Auto
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Registry {
+    pub version: u32,
+    pub devices: Vec<RegistryEntry>,
+}
+
+pub fn get_registry_path() -> PathBuf {
+    #[cfg(feature = "desktop-app")]
+    {
+        crate::logger::manager::get_app_home_dir().join("settings.json")
+    }
+
+    #[cfg(not(feature = "desktop-app"))]
+    {
+        PathBuf::from(crate::cli::manager::settings_path())
+    }
+}
+
+pub async fn load(path: &Path) -> Result<Registry, ManagerError> {
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|err| ManagerError::Other(format!("Registry: {}: {err}", path.display())))?;
+    let registry: Registry = serde_json::from_slice(&content)
+        .map_err(|err| ManagerError::Other(format!("Registry: {}: {err}", path.display())))?;
+    if registry.version != REGISTRY_VERSION {
+        return Err(ManagerError::Other(format!(
+            "Registry: {}: unsupported version {}",
+            path.display(),
+            registry.version
+        )));
+    }
+    Ok(registry)
+}
+
+// The file is replaced atomically, so a reboot during a save never leaves a truncated registry
+pub async fn save(path: &Path, registry: &Registry) -> Result<(), ManagerError> {
+    if let Some(directory) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(directory)
+            .await
+            .map_err(|err| ManagerError::Other(format!("Registry: {err}")))?;
+    }
+
+    let content = serde_json::to_vec_pretty(registry)
+        .map_err(|err| ManagerError::Other(format!("Registry: {err}")))?;
+    let temporary = path.with_extension("json.tmp");
+    tokio::fs::write(&temporary, content)
+        .await
+        .map_err(|err| ManagerError::Other(format!("Registry: {err}")))?;
+    tokio::fs::rename(&temporary, path)
+        .await
+        .map_err(|err| ManagerError::Other(format!("Registry: {err}")))?;
+    Ok(())
+}
+
+pub fn reset(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => info!("Registry: Settings file removed: {}", path.display()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => error!(
+            "Registry: Failed to remove settings file {}: {err}",
+            path.display()
+        ),
+    }
+}
+
+impl Device {
+    // Available devices come from discovery and are found again at boot, so only created ones are kept
+    fn registry_entry(&self) -> Option<RegistryEntry> {
+        if self.status == DeviceStatus::Available {
+            return None;
+        }
+
+        let ping360_config = match &self.properties {
+            Some(DeviceProperties::Ping360(properties)) => properties
+                .continuous_mode_settings
+                .read()
+                .ok()
+                .map(|config| *config),
+            _ => None,
+        }
+        .or(self
+            .recovery
+            .as_ref()
+            .and_then(|recovery| recovery.ping360_config));
+
+        let continuous_mode = match &self.recovery {
+            Some(recovery) => recovery.continuous_mode,
+            None => self.status == DeviceStatus::ContinuousMode,
+        };
+
+        Some(RegistryEntry {
+            id: self.id,
+            source: self.source.clone(),
+            device_type: self.device_type.clone(),
+            name: self.name.clone(),
+            ping360_config,
+            continuous_mode,
+            mavlink: self.mavlink.as_ref().map(|output| output.config.clone()),
+            nmea: self.nmea.as_ref().map(|output| output.config.clone()),
+        })
+    }
+}
+
+impl DeviceManager {
+    pub fn registry(&self) -> Registry {
+        let mut devices: Vec<RegistryEntry> = self
+            .device
+            .values()
+            .filter_map(|device| device.registry_entry())
+            .collect();
+        devices.sort_by_key(|entry| entry.id);
+
+        Registry {
+            version: REGISTRY_VERSION,
+            devices,
+        }
+    }
+
+    // Writes the registry to disk when it differs from the last saved one
+    pub async fn save_registry(&mut self) {
+        let registry = self.registry();
+        if self.saved_registry.as_ref() == Some(&registry) {
+            return;
+        }
+
+        let path = get_registry_path();
+        match save(&path, &registry).await {
+            Ok(()) => {
+                trace!("Registry: Saved {} devices", registry.devices.len());
+                self.saved_registry = Some(registry);
+            }
+            Err(err) => error!("Registry: Failed to save devices: {err:?}"),
+        }
+    }
+
+    // Restored devices start as stopped and are brought back by the supervisor, which keeps retrying the missing ones
+    pub async fn restore_registry(&mut self) {
+        let path = get_registry_path();
+        if !path.exists() {
+            trace!("Registry: No settings file found at {}", path.display());
+            return;
+        }
+
+        let registry = match load(&path).await {
+            Ok(registry) => registry,
+            Err(err) => {
+                warn!("Registry: Failed to load devices: {err:?}");
+                return;
+            }
+        };
+
+        let now = Instant::now();
+        for entry in &registry.devices {
+            if self.device.contains_key(&entry.id) {
+                warn!("Registry: Device already exists, skipping: {}", entry.id);
+                continue;
+            }
+
+            let device = Device {
+                id: entry.id,
+                name: entry.name.clone(),
+                source: entry.source.clone(),
+                handler: None,
+                actor: None,
+                status: DeviceStatus::Stopped,
+                broadcast: None,
+                recording: None,
+                replay: None,
+                recovery: Some(Recovery {
+                    attempts: 0,
+                    next_attempt: now,
+                    continuous_mode: entry.continuous_mode,
+                    recording: false,
+                    ping360_config: entry.ping360_config,
+                    reconnecting: false,
+                }),
+                mavlink: entry.mavlink.clone().map(MavlinkOutput::new),
+                nmea: entry.nmea.clone().map(NmeaOutput::new),
+                device_type: entry.device_type.clone(),
+                properties: None,
+            };
+            self.device.insert(entry.id, device);
+        }
+
+        info!(
+            "Registry: Restoring {} devices from {}",
+            registry.devices.len(),
+            path.display()
+        );
+
+        self.saved_registry = Some(registry);
+        self.supervise_devices().await;
+    }
+
+    // Like restored devices, configured ones are opened by the supervisor, the configured settings win over the registry
+    pub async fn add_configured_devices(&mut self, devices: &[ConfiguredDevice]) {
+        let now = Instant::now();
+        for configured in devices {
+            let id = configured.source.device_id();
+
+            let device = self.device.entry(id).or_insert_with(|| {
+                info!("Registry: Adding configured device: {id}");
+                Device {
+                    id,
+                    name: None,
+                    source: configured.source.clone(),
+                    handler: None,
+                    actor: None,
+                    status: DeviceStatus::Stopped,
+                    broadcast: None,
+                    recording: None,
+                    replay: None,
+                    recovery: Some(Recovery {
+                        attempts: 0,
+                        next_attempt: now,
+                        continuous_mode: false,
+                        recording: false,
+                        ping360_config: None,
+                        reconnecting: false,
+                    }),
+                    mavlink: None,
+                    nmea: None,
+                    device_type: configured.device_selection.clone(),
+                    properties: None,
+                }
+            });
+
+            if configured.name.is_some() {
+                device.name = configured.name.clone();
+            }
+            let mavlink_changed = configured.mavlink_output.as_ref().is_some_and(|config| {
+                device.mavlink.as_ref().map(|output| &output.config) != Some(config)
+            });
+            let nmea_changed = configured.nmea_output.as_ref().is_some_and(|config| {
+                device.nmea.as_ref().map(|output| &output.config) != Some(config)
+            });
+
+            // Restored devices may be running already, so their outputs are restarted with the configured settings
+            if mavlink_changed {
+                device.mavlink = configured.mavlink_output.clone().map(MavlinkOutput::new);
+                if let Err(err) = self.start_mavlink_output(id).await {
+                    warn!("Registry: Failed to start MAVLink output: {err:?}, device: {id}");
+                }
+            }
+            if nmea_changed {
+                if let Some(mut output) = self
+                    .device
+                    .get_mut(&id)
+                    .and_then(|device| device.nmea.take())
+                {
+                    output.stop().await;
+                }
+                if let Some(device) = self.device.get_mut(&id) {
+                    device.nmea = configured.nmea_output.clone().map(NmeaOutput::new);
+                }
+                if let Err(err) = self.start_nmea_output(id).await {
+                    warn!("Registry: Failed to start NMEA output: {err:?}, device: {id}");
+                }
+            }
+        }
+
+        self.supervise_devices().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::device::manager::{
+        simulation::SimulatedDevice, SourceSimulatedStruct, SourceUdpStruct,
+    };
+
+    #[tokio::test]
+    async fn test_registry_roundtrip() {
+        let path = std::env::temp_dir().join(format!(
+            "ping-viewer-next-registry-{}.json",
+            std::process::id()
+        ));
+        let registry = Registry {
+            version: REGISTRY_VERSION,
+            devices: vec![RegistryEntry {
+                id: Uuid::from_u128(1),
+                source: SourceSelection::Simulated(SourceSimulatedStruct {
+                    device: SimulatedDevice::Ping1D,
+                    scene: Default::default(),
+                }),
+                device_type: DeviceSelection::Ping1D,
+                name: Some("Bow altimeter".to_string()),
+                ping360_config: None,
+                continuous_mode: true,
+                mavlink: Some(MavlinkOutputConfig::default()),
+                nmea: Some(NmeaOutputConfig::default()),
+            }],
+        };
+
+        save(&path, &registry).await.unwrap();
+        assert_eq!(load(&path).await.unwrap(), registry);
+
+        reset(&path);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_configured_device_id() {
+        let source = SourceSelection::Simulated(SourceSimulatedStruct {
+            device: SimulatedDevice::Ping1D,
+            scene: Default::default(),
+        });
+        // Persisted ids must not change between builds
+        let udp = SourceSelection::UdpStream(SourceUdpStruct {
+            ip: Ipv4Addr::new(192, 168, 2, 2),
+            port: 9092,
+        });
+        assert_eq!(
+            udp.device_id(),
+            Uuid::parse_str("c5a76dea-07ec-5c48-8b7c-730dcb18f25f").unwrap()
+        );
+
+        let configured = ConfiguredDevice {
+            source: source.clone(),
+            device_selection: DeviceSelection::Ping1D,
+            name: Some("Bow altimeter".to_string()),
+            mavlink_output: None,
+            nmea_output: None,
+        };
+        let (mut manager, _handler) = DeviceManager::new(10);
+        manager
+            .add_configured_devices(&[configured.clone(), configured])
+            .await;
+        assert_eq!(manager.device.len(), 1);
+        let device = manager.get_device(source.device_id()).unwrap();
+        assert_eq!(device.name.as_deref(), Some("Bow altimeter"));
+    }
+}
@@ -0,0 +1,659 @@
+use std::{collections::HashMap, io::SeekFrom, sync::Arc, time::Duration};
+
+use bluerobotics_ping::{
+    decoder::{Decoder, DecoderResult},
+    message::MessageInfo,
+};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tracing::{error, info, trace, warn};
+use uuid::Uuid;
+
+use super::{frame, recording, Answer, DeviceManager, ManagerError};
+use crate::device::health::{FrameTimestamp, LinkHealth, ReceivedFrame};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub enum ReplayCommand {
+    Play,
+    Pause,
+    /// Position in milliseconds from the beginning of the recording
+    Seek(u64),
+    /// Playback speed multiplier, 1.0 is real time
+    SetSpeed(f32),
+    Status,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ReplayControl {
+    pub uuid: Uuid,
+    pub command: ReplayCommand,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReplayState {
+    Playing,
+    Paused,
+    Finished,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayStatus {
+    pub path: String,
+    pub state: ReplayState,
+    pub speed: f32,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug)]
+pub struct ReplayRequest {
+    pub command: ReplayCommand,
+    pub respond_to: oneshot::Sender<ReplayStatus>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayHandle {
+    pub sender: mpsc::Sender<ReplayRequest>,
+}
+
+impl ReplayHandle {
+    pub async fn send(&self, command: ReplayCommand) -> Result<ReplayStatus, ManagerError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+
+        self.sender
+            .send(ReplayRequest {
+                command,
+                respond_to: result_sender,
+            })
+            .await
+            .map_err(|err| ManagerError::TokioMpsc(err.to_string()))?;
+
+        result_receiver
+            .await
+            .map_err(|err| ManagerError::TokioMpsc(err.to_string()))
+    }
+}
+
+// Position of each recorded frame inside the file, used for seeking
+struct FrameIndex {
+    timestamp_us: i64,
+    offset: u64,
+}
+
+pub struct ReplayPlayer {
+    path: String,
+    reader: BufReader<File>,
+    index: Vec<FrameIndex>,
+    // Last frame seen for each message id, used to answer requests from the device
+    cache: HashMap<u16, Vec<u8>>,
+    device_reader: ReadHalf<DuplexStream>,
+    device_writer: WriteHalf<DuplexStream>,
+    receiver: mpsc::Receiver<ReplayRequest>,
+    decoder: Decoder,
+    // Frames sent to the device are reported here instead of by a monitored stream, to carry the recorded heading
+    health: Arc<LinkHealth>,
+    link_decoder: Decoder,
+    state: ReplayState,
+    speed: f32,
+    position: usize,
+    anchor: (Instant, i64),
+}
+
+impl ReplayPlayer {
+    // Opens a recording and returns the stream to be used by the device, together with the playback handle
+    pub async fn open(
+        path: &str,
+        health: Arc<LinkHealth>,
+    ) -> Result<(DuplexStream, ReplayHandle), ManagerError> {
+        let file = File::open(path)
+            .await
+            .map_err(|err| ManagerError::DeviceSourceError(format!("Replay: {path}: {err}")))?;
+        let mut reader = BufReader::new(file);
+
+        let header = recording::read_header(&mut reader)
+            .await
+            .map_err(|err| ManagerError::DeviceSourceError(format!("Replay: {path}: {err}")))?;
+
+        let (index, cache) = Self::build_index(&mut reader, &header.identification)
+            .await
+            .map_err(|err| ManagerError::DeviceSourceError(format!("Replay: {path}: {err}")))?;
+
+        if index.is_empty() {
+            return Err(ManagerError::DeviceSourceError(format!(
+                "Replay: {path}: recording has no frames"
+            )));
+        }
+
+        info!(
+            "Replay: Opened recording {path} from device {}, {} frames",
+            header.device_id,
+            index.len()
+        );
+
+        let (device_stream, player_stream) = tokio::io::duplex(64 * 1024);
+        let (device_reader, device_writer) = tokio::io::split(player_stream);
+        let (sender, receiver) = mpsc::channel(10);
+
+        let mut player = ReplayPlayer {
+            path: path.to_string(),
+            reader,
+            index,
+            cache,
+            device_reader,
+            device_writer,
+            receiver,
+            decoder: Decoder::new(),
+            health,
+            link_decoder: Decoder::new(),
+            state: ReplayState::Playing,
+            speed: 1.0,
+            position: 0,
+            anchor: (Instant::now(), 0),
+        };
+        player
+            .seek_to_index(0)
+            .await
+            .map_err(|err| ManagerError::DeviceSourceError(format!("Replay: {path}: {err}")))?;
+
+        tokio::spawn(async move { player.run().await });
+
+        Ok((device_stream, ReplayHandle { sender }))
+    }
+
+    async fn build_index(
+        reader: &mut BufReader<File>,
+        identification: &[Vec<u8>],
+    ) -> std::io::Result<(Vec<FrameIndex>, HashMap<u16, Vec<u8>>)> {
+        let mut index = Vec::new();
+        let mut cache: HashMap<u16, Vec<u8>> = identification
+            .iter()
+            .filter_map(|data| Some((frame::message_id(data)?, data.clone())))
+            .collect();
+        let mut offset = reader.stream_position().await?;
+
+        while let Some(recorded) = recording::read_frame(reader).await? {
+            index.push(FrameIndex {
+                timestamp_us: recorded.timestamp_us,
+                offset,
+            });
+            offset += recording::frame_size(recorded.data.len());
+
+            if let Some(message_id) = frame::message_id(&recorded.data) {
+                cache.entry(message_id).or_insert(recorded.data);
+            }
+        }
+
+        // Recordings made in Ping360 auto-transmit mode carry no DeviceData, which is required
+        // to build the device properties, so it is derived from the first AutoDeviceData.
+        let device_data_id = <bluerobotics_ping::ping360::DeviceDataStruct as MessageInfo>::id();
+        let auto_device_data_id =
+            <bluerobotics_ping::ping360::AutoDeviceDataStruct as MessageInfo>::id();
+        if !cache.contains_key(&device_data_id) {
+            if let Some(payload) = cache
+                .get(&auto_device_data_id)
+                .and_then(|auto_device_data| frame::payload(auto_device_data))
+                .filter(|payload| payload.len() >= 16)
+            {
+                let device_data = [&payload[0..10], &payload[16..]].concat();
+                cache.insert(device_data_id, frame::encode(device_data_id, &device_data));
+            }
+        }
+
+        Ok((index, cache))
+    }
+
+    async fn run(mut self) {
+        let mut buffer = [0u8; 1024];
+
+        loop {
+            let next_frame_due = match self.state {
+                ReplayState::Playing => self.next_frame_due(),
+                _ => None,
+            };
+
+            tokio::select! {
+                request = self.receiver.recv() => match request {
+                    Some(request) => {
+                        self.handle_command(request.command).await;
+                        let _ = request.respond_to.send(self.status());
+                    }
+                    None => {
+                        trace!("Replay: Handle dropped, stopping playback of {}", self.path);
+                        break;
+                    }
+                },
+                read = self.device_reader.read(&mut buffer) => match read {
+                    Ok(0) | Err(_) => {
+                        trace!("Replay: Device stream closed, stopping playback of {}", self.path);
+                        break;
+                    }
+                    Ok(size) => {
+                        if let Err(err) = self.handle_device_bytes(&buffer[..size]).await {
+                            error!("Replay: Failed to answer device request: {err:?}, file: {}", self.path);
+                            break;
+                        }
+                    }
+                },
+                _ = tokio::time::sleep_until(next_frame_due.unwrap_or_else(Instant::now)), if next_frame_due.is_some() => {
+                    if let Err(err) = self.play_next_frame().await {
+                        error!("Replay: Failed to play frame: {err:?}, file: {}", self.path);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_frame_due(&self) -> Option<Instant> {
+        let next = self.index.get(self.position)?;
+        let elapsed_us = (next.timestamp_us - self.anchor.1).max(0) as f64 / self.speed as f64;
+        Some(self.anchor.0 + Duration::from_micros(elapsed_us as u64))
+    }
+
+    async fn play_next_frame(&mut self) -> std::io::Result<()> {
+        let Some(recorded) = recording::read_frame(&mut self.reader).await? else {
+            self.state = ReplayState::Finished;
+            return Ok(());
+        };
+
+        if let Some(message_id) = frame::message_id(&recorded.data) {
+            self.cache.insert(message_id, recorded.data.clone());
+        }
+        self.send_to_device(&recorded.data, recorded.heading)
+            .await?;
+
+        self.position += 1;
+        if self.position >= self.index.len() {
+            info!("Replay: Finished playback of {}", self.path);
+            self.state = ReplayState::Finished;
+        }
+        Ok(())
+    }
+
+    async fn handle_command(&mut self, command: ReplayCommand) {
+        match command {
+            ReplayCommand::Play => {
+                if self.state == ReplayState::Finished {
+                    if let Err(err) = self.seek_to_index(0).await {
+                        error!(
+                            "Replay: Failed to restart playback: {err:?}, file: {}",
+                            self.path
+                        );
+                        return;
+                    }
+                }
+                self.reset_anchor();
+                self.state = ReplayState::Playing;
+            }
+            ReplayCommand::Pause => {
+                if self.state == ReplayState::Playing {
+                    self.state = ReplayState::Paused;
+                }
+            }
+            ReplayCommand::Seek(position_ms) => {
+                let target_us = self.index[0].timestamp_us + (position_ms as i64) * 1000;
+                let position = self
+                    .index
+                    .partition_point(|frame| frame.timestamp_us < target_us)
+                    .min(self.index.len() - 1);
+                if let Err(err) = self.seek_to_index(position).await {
+                    error!("Replay: Failed to seek: {err:?}, file: {}", self.path);
+                    return;
+                }
+                if self.state == ReplayState::Finished {
+                    self.state = ReplayState::Paused;
+                }
+            }
+            ReplayCommand::SetSpeed(speed) => {
+                if speed.is_finite() && speed > 0.0 {
+                    self.speed = speed;
+                    self.reset_anchor();
+                } else {
+                    warn!("Replay: Invalid speed {speed}, file: {}", self.path);
+                }
+            }
+            ReplayCommand::Status => {}
+        }
+    }
+
+    async fn seek_to_index(&mut self, position: usize) -> std::io::Result<()> {
+        self.reader
+            .seek(SeekFrom::Start(self.index[position].offset))
+            .await?;
+        self.position = position;
+        self.reset_anchor();
+        Ok(())
+    }
+
+    // Playback timing restarts from the current frame, so pauses and speed changes don't cause bursts
+    fn reset_anchor(&mut self) {
+        let timestamp_us = self
+            .index
+            .get(self.position)
+            .map(|frame| frame.timestamp_us)
+            .unwrap_or_default();
+        self.anchor = (Instant::now(), timestamp_us);
+    }
+
+    // Requests are answered with the last recorded frame of the requested id, or with the device identification
+    // from the header, commands are acknowledged.
+    // Ping360 transducer requests are answered by the DeviceData frames in the recording stream.
+    async fn handle_device_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        for byte in bytes {
+            let DecoderResult::Success(message) = self.decoder.parse_byte(*byte) else {
+                continue;
+            };
+
+            if let Some(requested_id) = frame::general_request_id(&message) {
+                let answer = match self.cache.get(&requested_id) {
+                    Some(cached) => cached.clone(),
+                    None => frame::nack(requested_id, "Not available in recording"),
+                };
+                self.send_to_device(&answer, None).await?;
+            } else if message.message_id
+                != <bluerobotics_ping::ping360::TransducerStruct as MessageInfo>::id()
+            {
+                self.send_to_device(&frame::ack(message.message_id), None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_to_device(&mut self, data: &[u8], heading: Option<f32>) -> std::io::Result<()> {
+        for byte in data {
+            match self.link_decoder.parse_byte(*byte) {
+                DecoderResult::Success(message) => self.health.record_frame(ReceivedFrame {
+                    timestamp: FrameTimestamp::now(),
+                    heading,
+                    message,
+                }),
+                DecoderResult::Error(error) => self.health.record_parse_error(&error),
+                _ => {}
+            }
+        }
+        self.device_writer.write_all(data).await
+    }
+
+    fn status(&self) -> ReplayStatus {
+        let first = self.index[0].timestamp_us;
+        let last = self.index[self.index.len() - 1].timestamp_us;
+        let current = self
+            .index
+            .get(self.position)
+            .map(|frame| frame.timestamp_us)
+            .unwrap_or(last);
+
+        ReplayStatus {
+            path: self.path.clone(),
+            state: self.state.clone(),
+            speed: self.speed,
+            position_ms: ((current - first).max(0) / 1000) as u64,
+            duration_ms: ((last - first).max(0) / 1000) as u64,
+        }
+    }
+}
+
+impl DeviceManager {
+    pub async fn replay_control(&mut self, request: ReplayControl) -> Result<Answer, ManagerError> {
+        let device = self.get_device(request.uuid)?;
+        let Some(replay) = &device.replay else {
+            return Err(ManagerError::InvalidRequest(format!(
+                "Device is not a replay source, device: {}",
+                request.uuid
+            )));
+        };
+
+        let status = replay.send(request.command).await?;
+        Ok(Answer::ReplayStatus(status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bluerobotics_ping::message::ProtocolMessage;
+
+    use super::*;
+    use crate::device::manager::{DeviceSelection, SourceSelection, SourceUdpStruct};
+
+    const DISTANCE_SIMPLE_ID: u16 = 1211;
+    const PROTOCOL_VERSION_ID: u16 = 5;
+
+    async fn write_recording(frames: &[(i64, Option<f32>, Vec<u8>)]) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "replay-{}.{}",
+            Uuid::new_v4(),
+            recording::RECORDING_EXTENSION
+        ));
+        let mut file = File::create(&path).await.unwrap();
+        let header = recording::RecordingHeader {
+            device_id: Uuid::from_u128(1),
+            source: SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::new(192, 168, 2, 2),
+                port: 9092,
+            }),
+            device_type: DeviceSelection::Ping1D,
+            started_at: "2024-01-01T00:00:00+00:00".to_string(),
+            identification: vec![frame::encode(PROTOCOL_VERSION_ID, &[1, 0, 0, 0])],
+        };
+        recording::write_header(&mut file, &header).await.unwrap();
+        for (timestamp_us, heading, data) in frames {
+            recording::write_frame(&mut file, *timestamp_us, *heading, data)
+                .await
+                .unwrap();
+        }
+        file.flush().await.unwrap();
+        path.display().to_string()
+    }
+
+    async fn next_message(stream: &mut DuplexStream, decoder: &mut Decoder) -> ProtocolMessage {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            let mut byte = [0u8; 1];
+            loop {
+                stream.read_exact(&mut byte).await.unwrap();
+                if let DecoderResult::Success(message) = decoder.parse_byte(byte[0]) {
+                    return message;
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    fn distance_simple(distance: u32) -> Vec<u8> {
+        let mut payload = distance.to_le_bytes().to_vec();
+        payload.push(100);
+        frame::encode(DISTANCE_SIMPLE_ID, &payload)
+    }
+
+    fn request(requested_id: u16) -> Vec<u8> {
+        frame::encode(
+            <bluerobotics_ping::common::GeneralRequestStruct as MessageInfo>::id(),
+            &requested_id.to_le_bytes(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_replay_playback() {
+        // AutoDeviceData: 10 bytes shared with DeviceData, 6 bytes of sweep settings, then the samples
+        let auto_device_data_id =
+            <bluerobotics_ping::ping360::AutoDeviceDataStruct as MessageInfo>::id();
+        let mut auto_payload: Vec<u8> = (1..=16).collect();
+        auto_payload.extend_from_slice(&[3, 0, 3, 0, 7, 8, 9]);
+
+        let path = write_recording(&[
+            (
+                0,
+                Some(12.5),
+                frame::encode(auto_device_data_id, &auto_payload),
+            ),
+            (1_000_000, None, distance_simple(1_000)),
+            (2_000_000, None, distance_simple(2_000)),
+            (3_000_000, None, distance_simple(3_000)),
+            (4_000_000, None, distance_simple(4_000)),
+        ])
+        .await;
+
+        let health = Arc::new(LinkHealth::default());
+        let mut frames = health.subscribe();
+        let (mut stream, handle) = ReplayPlayer::open(&path, health).await.unwrap();
+        let mut decoder = Decoder::new();
+
+        // The first frame is played right away, the next one is due a second later
+        let first = next_message(&mut stream, &mut decoder).await;
+        assert_eq!(first.message_id, auto_device_data_id);
+
+        // Played frames are reported with the heading they were recorded with
+        let reported = frames.recv().await.unwrap();
+        assert_eq!(reported.message.message_id, auto_device_data_id);
+        assert_eq!(reported.heading, Some(12.5));
+
+        let status = handle.send(ReplayCommand::Pause).await.unwrap();
+        assert_eq!(status.state, ReplayState::Paused);
+        assert_eq!(status.position_ms, 1_000);
+        assert_eq!(status.duration_ms, 4_000);
+
+        let status = handle.send(ReplayCommand::Seek(2_500)).await.unwrap();
+        assert_eq!(status.state, ReplayState::Paused);
+        assert_eq!(status.position_ms, 3_000);
+
+        let status = handle.send(ReplayCommand::SetSpeed(0.0)).await.unwrap();
+        assert_eq!(status.speed, 1.0);
+        let status = handle.send(ReplayCommand::SetSpeed(100.0)).await.unwrap();
+        assert_eq!(status.speed, 100.0);
+
+        // Playback goes on from the seek position, a second of recording takes 10 ms at 100x
+        handle.send(ReplayCommand::Play).await.unwrap();
+        for distance in [3_000u32, 4_000] {
+            let message = next_message(&mut stream, &mut decoder).await;
+            assert_eq!(message.message_id, DISTANCE_SIMPLE_ID);
+            assert_eq!(message.payload[0..4], distance.to_le_bytes());
+        }
+        let status = handle.send(ReplayCommand::Status).await.unwrap();
+        assert_eq!(status.state, ReplayState::Finished);
+
+        // General requests are answered with the last played frame of the requested id
+        stream
+            .write_all(&request(DISTANCE_SIMPLE_ID))
+            .await
+            .unwrap();
+        let answer = next_message(&mut stream, &mut decoder).await;
+        assert_eq!(answer.payload[0..4], 4_000u32.to_le_bytes());
+
+        // DeviceData is derived from AutoDeviceData, without the sweep settings
+        let device_data_id = <bluerobotics_ping::ping360::DeviceDataStruct as MessageInfo>::id();
+        stream.write_all(&request(device_data_id)).await.unwrap();
+        let answer = next_message(&mut stream, &mut decoder).await;
+        assert_eq!(answer.message_id, device_data_id);
+        assert_eq!(
+            answer.payload,
+            [&auto_payload[0..10], &auto_payload[16..]].concat()
+        );
+
+        // The device identification is answered from the header
+        stream
+            .write_all(&request(PROTOCOL_VERSION_ID))
+            .await
+            .unwrap();
+        let answer = next_message(&mut stream, &mut decoder).await;
+        assert_eq!(answer.message_id, PROTOCOL_VERSION_ID);
+        assert_eq!(answer.payload, [1, 0, 0, 0]);
+
+        // Messages missing from the recording are refused
+        let mode_auto_id = <bluerobotics_ping::ping1d::ModeAutoStruct as MessageInfo>::id();
+        stream.write_all(&request(mode_auto_id)).await.unwrap();
+        let answer = next_message(&mut stream, &mut decoder).await;
+        assert_eq!(
+            answer.message_id,
+            <bluerobotics_ping::common::NackStruct as MessageInfo>::id()
+        );
+        assert_eq!(answer.payload[0..2], mode_auto_id.to_le_bytes());
+
+        // Commands are acknowledged
+        let set_ping_enable_id =
+            <bluerobotics_ping::ping1d::SetPingEnableStruct as MessageInfo>::id();
+        stream
+            .write_all(&frame::encode(set_ping_enable_id, &[1]))
+            .await
+            .unwrap();
+        let answer = next_message(&mut stream, &mut decoder).await;
+        assert_eq!(
+            answer.message_id,
+            <bluerobotics_ping::common::AckStruct as MessageInfo>::id()
+        );
+
+        // Playing a finished recording starts it over
+        let status = handle.send(ReplayCommand::Play).await.unwrap();
+        assert_eq!(status.state, ReplayState::Playing);
+        assert_eq!(
+            next_message(&mut stream, &mut decoder).await.message_id,
+            auto_device_data_id
+        );
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_device_recording() {
+        use crate::device::manager::{
+            simulation::{SimulatedDevice, SimulatedScene},
+            DeviceStatus, SourceFileStruct, SourceSimulatedStruct,
+        };
+
+        let (mut manager, _handler) = DeviceManager::new(10);
+        let Ok(Answer::DeviceInfo(info)) = manager
+            .create(
+                SourceSelection::Simulated(SourceSimulatedStruct {
+                    device: SimulatedDevice::Ping1D,
+                    scene: SimulatedScene::default(),
+                }),
+                DeviceSelection::Ping1D,
+            )
+            .await
+        else {
+            panic!("Failed to create simulated Ping1D");
+        };
+        let device_id = info[0].id;
+
+        let directory = std::env::temp_dir().join(format!("replay-{}", Uuid::new_v4()));
+        // Created devices stream right away
+        let Ok(Answer::DeviceInfo(info)) = manager.start_recording_to(device_id, &directory).await
+        else {
+            panic!("Failed to start recording");
+        };
+        let path = info[0].recording.clone().unwrap().path;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        manager.stop_recording(device_id).await.unwrap();
+
+        // The device type is found again by probing the recording
+        let Ok(Answer::DeviceInfo(info)) = manager
+            .create(
+                SourceSelection::File(SourceFileStruct { path }),
+                DeviceSelection::Auto,
+            )
+            .await
+        else {
+            panic!("Failed to create a device from the recording");
+        };
+        assert_eq!(info[0].device_type, DeviceSelection::Ping1D);
+        assert_eq!(info[0].status, DeviceStatus::ContinuousMode);
+        assert!(info[0].properties.is_some());
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_empty_recording() {
+        let path = write_recording(&[]).await;
+        assert!(ReplayPlayer::open(&path, Arc::default()).await.is_err());
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
@@ -0,0 +1,666 @@
+use std::time::Duration;
+
+use bluerobotics_ping::{
+    decoder::{Decoder, DecoderResult},
+    message::{MessageInfo, ProtocolMessage},
+};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, WriteHalf};
+use tracing::{error, trace};
+
+use super::frame;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
+pub enum SimulatedDevice {
+    Ping1D,
+    Ping360,
+    Tsr1000,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
+pub struct SimulatedTarget {
+    /// Bearing of the target in gradians, Ping360 only
+    pub angle: u16,
+    pub distance_mm: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
+pub struct SimulatedScene {
+    /// Distance to the bottom for Ping1D and TSR1000, or to the surrounding walls for Ping360
+    pub distance_mm: u32,
+    pub targets: Vec<SimulatedTarget>,
+}
+
+impl Default for SimulatedScene {
+    fn default() -> Self {
+        Self {
+            distance_mm: 10_000,
+            targets: vec![SimulatedTarget {
+                angle: 100,
+                distance_mm: 5_000,
+            }],
+        }
+    }
+}
+
+const PING1D_PROFILE_SAMPLES: usize = 200;
+const PING1D_PING_INTERVAL: Duration = Duration::from_millis(100);
+const PING360_SPEED_OF_SOUND: f64 = 1500.0;
+const PING360_SAMPLE_PERIOD_TICK: f64 = 25e-9;
+
+// Ping360 transducer parameters shared by Transducer and AutoTransmit requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ping360Parameters {
+    mode: u8,
+    gain_setting: u8,
+    transmit_duration: u16,
+    sample_period: u16,
+    transmit_frequency: u16,
+    number_of_samples: u16,
+}
+
+impl Default for Ping360Parameters {
+    fn default() -> Self {
+        Self {
+            mode: 1,
+            gain_setting: 0,
+            transmit_duration: 32,
+            sample_period: 80,
+            transmit_frequency: 740,
+            number_of_samples: 1200,
+        }
+    }
+}
+
+// Ping1D and TSR1000 settings changed by the Set* requests and reported back by the matching general requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EchosounderSettings {
+    mode_auto: u8,
+    scan_start: u32,
+    scan_length: u32,
+    gain_setting: u32,
+    ping_interval: u16,
+    speed_of_sound: u32,
+    ping_enabled: u8,
+}
+
+impl Default for EchosounderSettings {
+    fn default() -> Self {
+        Self {
+            mode_auto: 1,
+            scan_start: 0,
+            scan_length: 10_000,
+            gain_setting: 0,
+            ping_interval: PING1D_PING_INTERVAL.as_millis() as u16,
+            speed_of_sound: 1_500_000,
+            ping_enabled: 1,
+        }
+    }
+}
+
+// Ping1D and TSR1000 share the same messages, each one with the ids of its own namespace
+#[derive(Clone, Copy, Debug)]
+struct EchosounderIds {
+    continuous_start: u16,
+    continuous_stop: u16,
+    set_mode_auto: u16,
+    set_range: u16,
+    set_gain_setting: u16,
+    set_ping_interval: u16,
+    set_speed_of_sound: u16,
+    set_ping_enable: u16,
+    mode_auto: u16,
+    range: u16,
+    gain_setting: u16,
+    ping_interval: u16,
+    speed_of_sound: u16,
+    ping_enable: u16,
+    distance: u16,
+    profile: u16,
+}
+
+macro_rules! echosounder_ids {
+    ($protocol:ident) => {
+        EchosounderIds {
+            continuous_start:
+                <bluerobotics_ping::$protocol::ContinuousStartStruct as MessageInfo>::id(),
+            continuous_stop:
+                <bluerobotics_ping::$protocol::ContinuousStopStruct as MessageInfo>::id(),
+            set_mode_auto: <bluerobotics_ping::$protocol::SetModeAutoStruct as MessageInfo>::id(),
+            set_range: <bluerobotics_ping::$protocol::SetRangeStruct as MessageInfo>::id(),
+            set_gain_setting:
+                <bluerobotics_ping::$protocol::SetGainSettingStruct as MessageInfo>::id(),
+            set_ping_interval:
+                <bluerobotics_ping::$protocol::SetPingIntervalStruct as MessageInfo>::id(),
+            set_speed_of_sound:
+                <bluerobotics_ping::$protocol::SetSpeedOfSoundStruct as MessageInfo>::id(),
+            set_ping_enable: <bluerobotics_ping::$protocol::SetPingEnableStruct as MessageInfo>::id(
+            ),
+            mode_auto: <bluerobotics_ping::$protocol::ModeAutoStruct as MessageInfo>::id(),
+            range: <bluerobotics_ping::$protocol::RangeStruct as MessageInfo>::id(),
+            gain_setting: <bluerobotics_ping::$protocol::GainSettingStruct as MessageInfo>::id(),
+            ping_interval: <bluerobotics_ping::$protocol::PingIntervalStruct as MessageInfo>::id(),
+            speed_of_sound: <bluerobotics_ping::$protocol::SpeedOfSoundStruct as MessageInfo>::id(),
+            ping_enable: <bluerobotics_ping::$protocol::PingEnableStruct as MessageInfo>::id(),
+            distance: <bluerobotics_ping::$protocol::DistanceStruct as MessageInfo>::id(),
+            profile: <bluerobotics_ping::$protocol::ProfileStruct as MessageInfo>::id(),
+        }
+    };
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AutoTransmit {
+    parameters: Ping360Parameters,
+    start_angle: u16,
+    stop_angle: u16,
+    num_steps: u8,
+    delay: u8,
+    angle: u16,
+    direction: i8,
+}
+
+pub struct SimulatedPing {
+    device: SimulatedDevice,
+    scene: SimulatedScene,
+    writer: WriteHalf<DuplexStream>,
+    ping_number: u32,
+    echosounder_settings: EchosounderSettings,
+    ping360_parameters: Ping360Parameters,
+    ping360_angle: u16,
+    // Ping1D or TSR1000 message id requested by ContinuousStart
+    continuous: Option<u16>,
+    auto_transmit: Option<AutoTransmit>,
+}
+
+impl SimulatedPing {
+    // Spawns the simulated device and returns the stream to be used by the device actor
+    pub fn start(device: SimulatedDevice, scene: SimulatedScene) -> DuplexStream {
+        let (device_stream, simulation_stream) = tokio::io::duplex(64 * 1024);
+        let (mut reader, writer) = tokio::io::split(simulation_stream);
+
+        let mut simulation = SimulatedPing {
+            device,
+            scene,
+            writer,
+            ping_number: 0,
+            echosounder_settings: EchosounderSettings::default(),
+            ping360_parameters: Ping360Parameters::default(),
+            ping360_angle: 0,
+            continuous: None,
+            auto_transmit: None,
+        };
+
+        tokio::spawn(async move {
+            let mut decoder = Decoder::new();
+            let mut buffer = [0u8; 1024];
+            let mut interval = tokio::time::interval(PING1D_PING_INTERVAL);
+
+            loop {
+                let streaming =
+                    simulation.continuous.is_some() || simulation.auto_transmit.is_some();
+
+                tokio::select! {
+                    read = reader.read(&mut buffer) => match read {
+                        Ok(0) | Err(_) => {
+                            trace!("Simulation: Device stream closed, stopping simulated {:?}", simulation.device);
+                            break;
+                        }
+                        Ok(size) => {
+                            for byte in &buffer[..size] {
+                                if let DecoderResult::Success(message) = decoder.parse_byte(*byte) {
+                                    if let Err(err) = simulation.handle_message(message).await {
+                                        error!("Simulation: Failed to answer request: {err:?}");
+                                        return;
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    _ = interval.tick(), if streaming => {
+                        if let Err(err) = simulation.stream_next().await {
+                            error!("Simulation: Failed to stream message: {err:?}");
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        device_stream
+    }
+
+    async fn handle_message(&mut self, message: ProtocolMessage) -> std::io::Result<()> {
+        let payload = message.payload.as_slice();
+
+        if let Some(requested_id) = frame::general_request_id(&message) {
+            let answer = self
+                .answer_request(requested_id)
+                .unwrap_or_else(|| frame::nack(requested_id, "Not available in simulation"));
+            return self.writer.write_all(&answer).await;
+        }
+
+        let id = message.message_id;
+        match self.device {
+            SimulatedDevice::Ping1D | SimulatedDevice::Tsr1000 => {
+                let ids = self.echosounder_ids();
+                if id == ids.continuous_start && payload.len() >= 2 {
+                    self.continuous = Some(u16::from_le_bytes([payload[0], payload[1]]));
+                } else if id == ids.continuous_stop {
+                    self.continuous = None;
+                } else {
+                    self.echosounder_set(&ids, id, payload);
+                }
+            }
+            SimulatedDevice::Ping360 => {
+                if id == <bluerobotics_ping::ping360::TransducerStruct as MessageInfo>::id()
+                    && payload.len() >= 14
+                {
+                    self.ping360_parameters = Ping360Parameters {
+                        mode: payload[0],
+                        gain_setting: payload[1],
+                        transmit_duration: u16::from_le_bytes([payload[4], payload[5]]),
+                        sample_period: u16::from_le_bytes([payload[6], payload[7]]),
+                        transmit_frequency: u16::from_le_bytes([payload[8], payload[9]]),
+                        number_of_samples: u16::from_le_bytes([payload[10], payload[11]]),
+                    };
+                    self.ping360_angle = u16::from_le_bytes([payload[2], payload[3]]) % 400;
+                    // Transducer is answered with DeviceData instead of an acknowledge
+                    let answer = self.ping360_device_data();
+                    return self.writer.write_all(&answer).await;
+                }
+                if id == <bluerobotics_ping::ping360::AutoTransmitStruct as MessageInfo>::id()
+                    && payload.len() >= 16
+                {
+                    let parameters = Ping360Parameters {
+                        mode: payload[0],
+                        gain_setting: payload[1],
+                        transmit_duration: u16::from_le_bytes([payload[2], payload[3]]),
+                        sample_period: u16::from_le_bytes([payload[4], payload[5]]),
+                        transmit_frequency: u16::from_le_bytes([payload[6], payload[7]]),
+                        number_of_samples: u16::from_le_bytes([payload[8], payload[9]]),
+                    };
+                    let start_angle = u16::from_le_bytes([payload[10], payload[11]]) % 400;
+                    self.ping360_parameters = parameters;
+                    self.auto_transmit = Some(AutoTransmit {
+                        parameters,
+                        start_angle,
+                        stop_angle: u16::from_le_bytes([payload[12], payload[13]]) % 400,
+                        num_steps: payload[14].max(1),
+                        delay: payload[15],
+                        angle: start_angle,
+                        direction: 1,
+                    });
+                } else if id == <bluerobotics_ping::ping360::MotorOffStruct as MessageInfo>::id() {
+                    self.auto_transmit = None;
+                }
+            }
+        }
+
+        self.writer.write_all(&frame::ack(id)).await
+    }
+
+    fn answer_request(&mut self, requested_id: u16) -> Option<Vec<u8>> {
+        if requested_id == <bluerobotics_ping::common::DeviceInformationStruct as MessageInfo>::id()
+        {
+            // Ping360 firmware 3.3 or newer is required by the auto-transmit continuous mode
+            let payload = match self.device {
+                SimulatedDevice::Ping1D => [1, 1, 3, 29, 0, 0],
+                SimulatedDevice::Ping360 => [2, 1, 3, 3, 0, 0],
+                SimulatedDevice::Tsr1000 => [100, 1, 1, 0, 0, 0],
+            };
+            return Some(frame::encode(requested_id, &payload));
+        }
+        if requested_id == <bluerobotics_ping::common::ProtocolVersionStruct as MessageInfo>::id() {
+            return Some(frame::encode(requested_id, &[1, 0, 0, 0]));
+        }
+
+        match self.device {
+            SimulatedDevice::Ping1D | SimulatedDevice::Tsr1000 => {
+                let ids = self.echosounder_ids();
+                if requested_id == ids.profile || requested_id == ids.distance {
+                    return Some(self.echosounder_measurement(&ids, requested_id));
+                }
+                self.echosounder_setting(&ids, requested_id)
+            }
+            SimulatedDevice::Ping360 => {
+                if requested_id
+                    == <bluerobotics_ping::ping360::DeviceDataStruct as MessageInfo>::id()
+                {
+                    return Some(self.ping360_device_data());
+                }
+                None
+            }
+        }
+    }
+
+    async fn stream_next(&mut self) -> std::io::Result<()> {
+        if let Some(message_id) = self.continuous {
+            let answer = self
+                .answer_request(message_id)
+                .unwrap_or_else(|| frame::nack(message_id, "Not available in simulation"));
+            self.writer.write_all(&answer).await?;
+        }
+
+        if let Some(mut auto_transmit) = self.auto_transmit {
+            let answer = self.ping360_auto_device_data(&auto_transmit, auto_transmit.angle);
+            self.writer.write_all(&answer).await?;
+
+            Self::advance_angle(&mut auto_transmit);
+            self.auto_transmit = Some(auto_transmit);
+        }
+        Ok(())
+    }
+
+    fn advance_angle(state: &mut AutoTransmit) {
+        let step = state.num_steps as u16;
+        if state.start_angle == 0 && state.stop_angle == 399 {
+            state.angle = (state.angle + step) % 400;
+        } else if state.direction > 0 {
+            if state.angle + step > state.stop_angle {
+                state.direction = -1;
+                state.angle = state.stop_angle;
+            } else {
+                state.angle += step;
+            }
+        } else if state.angle <= state.start_angle + step {
+            state.direction = 1;
+            state.angle = state.start_angle;
+        } else {
+            state.angle -= step;
+        }
+    }
+
+    // Ping360 has no echosounder messages, so its ids are never looked up
+    fn echosounder_ids(&self) -> EchosounderIds {
+        match self.device {
+            SimulatedDevice::Tsr1000 => echosounder_ids!(tsr1000),
+            SimulatedDevice::Ping1D | SimulatedDevice::Ping360 => echosounder_ids!(ping1d),
+        }
+    }
+
+    // Malformed payloads are ignored, the request is still acknowledged like any other
+    fn echosounder_set(&mut self, ids: &EchosounderIds, id: u16, payload: &[u8]) {
+        let settings = &mut self.echosounder_settings;
+        let u32_at = |index: usize| {
+            payload
+                .get(index..index + 4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        if id == ids.set_mode_auto {
+            if let Some(mode_auto) = payload.first() {
+                settings.mode_auto = *mode_auto;
+            }
+        } else if id == ids.set_range {
+            if let (Some(scan_start), Some(scan_length)) = (u32_at(0), u32_at(4)) {
+                // Like the firmware, a manual range leaves the automatic mode
+                settings.scan_start = scan_start;
+                settings.scan_length = scan_length;
+                settings.mode_auto = 0;
+            }
+        } else if id == ids.set_gain_setting {
+            if let Some(gain_setting) = payload.first() {
+                settings.gain_setting = *gain_setting as u32;
+                settings.mode_auto = 0;
+            }
+        } else if id == ids.set_ping_interval {
+            if payload.len() >= 2 {
+                settings.ping_interval = u16::from_le_bytes([payload[0], payload[1]]);
+            }
+        } else if id == ids.set_speed_of_sound {
+            if let Some(speed_of_sound) = u32_at(0) {
+                settings.speed_of_sound = speed_of_sound;
+            }
+        } else if id == ids.set_ping_enable {
+            if let Some(ping_enabled) = payload.first() {
+                settings.ping_enabled = *ping_enabled;
+            }
+        }
+    }
+
+    fn echosounder_setting(&self, ids: &EchosounderIds, requested_id: u16) -> Option<Vec<u8>> {
+        let settings = &self.echosounder_settings;
+        let (scan_start, scan_length) = self.echosounder_range();
+
+        let payload = if requested_id == ids.mode_auto {
+            vec![settings.mode_auto]
+        } else if requested_id == ids.range {
+            [scan_start.to_le_bytes(), scan_length.to_le_bytes()].concat()
+        } else if requested_id == ids.gain_setting {
+            settings.gain_setting.to_le_bytes().to_vec()
+        } else if requested_id == ids.ping_interval {
+            settings.ping_interval.to_le_bytes().to_vec()
+        } else if requested_id == ids.speed_of_sound {
+            settings.speed_of_sound.to_le_bytes().to_vec()
+        } else if requested_id == ids.ping_enable {
+            vec![settings.ping_enabled]
+        } else {
+            return None;
+        };
+
+        Some(frame::encode(requested_id, &payload))
+    }
+
+    // In automatic mode the range follows the bottom, otherwise it is the one requested
+    fn echosounder_range(&self) -> (u32, u32) {
+        if self.echosounder_settings.mode_auto == 0 {
+            return (
+                self.echosounder_settings.scan_start,
+                self.echosounder_settings.scan_length,
+            );
+        }
+        (0, (self.scene.distance_mm as f64 * 1.5).max(1_000.0) as u32)
+    }
+
+    // Profile or Distance with a slow swell over the configured bottom distance
+    fn echosounder_measurement(&mut self, ids: &EchosounderIds, message_id: u16) -> Vec<u8> {
+        self.ping_number = self.ping_number.wrapping_add(1);
+
+        let swell = (self.ping_number as f64 / 20.0).sin() * 50.0;
+        let distance = (self.scene.distance_mm as f64 + swell).max(0.0) as u32;
+        let (scan_start, scan_length) = self.echosounder_range();
+        let confidence = 100u16;
+        let transmit_duration = 100u16;
+        let gain_setting = self.echosounder_settings.gain_setting;
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&distance.to_le_bytes());
+        payload.extend_from_slice(&confidence.to_le_bytes());
+        payload.extend_from_slice(&transmit_duration.to_le_bytes());
+        payload.extend_from_slice(&self.ping_number.to_le_bytes());
+        payload.extend_from_slice(&scan_start.to_le_bytes());
+        payload.extend_from_slice(&scan_length.to_le_bytes());
+        payload.extend_from_slice(&gain_setting.to_le_bytes());
+
+        if message_id == ids.profile {
+            let peak = (distance as f64 - scan_start as f64) / scan_length.max(1) as f64
+                * PING1D_PROFILE_SAMPLES as f64;
+            let profile = echo_profile(PING1D_PROFILE_SAMPLES, &[peak], self.ping_number);
+            payload.extend_from_slice(&(profile.len() as u16).to_le_bytes());
+            payload.extend_from_slice(&profile);
+        }
+
+        frame::encode(message_id, &payload)
+    }
+
+    fn ping360_samples(&mut self, parameters: &Ping360Parameters, angle: u16) -> Vec<u8> {
+        self.ping_number = self.ping_number.wrapping_add(1);
+
+        let meters_per_sample =
+            parameters.sample_period as f64 * PING360_SAMPLE_PERIOD_TICK * PING360_SPEED_OF_SOUND
+                / 2.0;
+        let sample_of = |distance_mm: u32| distance_mm as f64 / 1000.0 / meters_per_sample;
+
+        let mut peaks = vec![sample_of(self.scene.distance_mm)];
+        peaks.extend(
+            self.scene
+                .targets
+                .iter()
+                .filter(|target| angular_distance(target.angle, angle) <= 2)
+                .map(|target| sample_of(target.distance_mm)),
+        );
+
+        echo_profile(
+            parameters.number_of_samples as usize,
+            &peaks,
+            self.ping_number,
+        )
+    }
+
+    fn ping360_device_data(&mut self) -> Vec<u8> {
+        let parameters = self.ping360_parameters;
+        let angle = self.ping360_angle;
+        let data = self.ping360_samples(&parameters, angle);
+
+        let mut payload = vec![parameters.mode, parameters.gain_setting];
+        payload.extend_from_slice(&angle.to_le_bytes());
+        payload.extend_from_slice(&parameters.transmit_duration.to_le_bytes());
+        payload.extend_from_slice(&parameters.sample_period.to_le_bytes());
+        payload.extend_from_slice(&parameters.transmit_frequency.to_le_bytes());
+        payload.extend_from_slice(&parameters.number_of_samples.to_le_bytes());
+        payload.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        payload.extend_from_slice(&data);
+
+        frame::encode(
+            <bluerobotics_ping::ping360::DeviceDataStruct as MessageInfo>::id(),
+            &payload,
+        )
+    }
+
+    fn ping360_auto_device_data(&mut self, auto_transmit: &AutoTransmit, angle: u16) -> Vec<u8> {
+        let parameters = auto_transmit.parameters;
+        let data = self.ping360_samples(&parameters, angle);
+
+        let mut payload = vec![parameters.mode, parameters.gain_setting];
+        payload.extend_from_slice(&angle.to_le_bytes());
+        payload.extend_from_slice(&parameters.transmit_duration.to_le_bytes());
+        payload.extend_from_slice(&parameters.sample_period.to_le_bytes());
+        payload.extend_from_slice(&parameters.transmit_frequency.to_le_bytes());
+        payload.extend_from_slice(&auto_transmit.start_angle.to_le_bytes());
+        payload.extend_from_slice(&auto_transmit.stop_angle.to_le_bytes());
+        payload.push(auto_transmit.num_steps);
+        payload.push(auto_transmit.delay);
+        payload.extend_from_slice(&parameters.number_of_samples.to_le_bytes());
+        payload.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        payload.extend_from_slice(&data);
+
+        frame::encode(
+            <bluerobotics_ping::ping360::AutoDeviceDataStruct as MessageInfo>::id(),
+            &payload,
+        )
+    }
+}
+
+fn angular_distance(first: u16, second: u16) -> u16 {
+    let difference = (first as i32 - second as i32).rem_euclid(400) as u16;
+    difference.min(400 - difference)
+}
+
+// Builds an intensity profile with gaussian echoes centered at each peak sample plus a low noise floor
+fn echo_profile(samples: usize, peaks: &[f64], seed: u32) -> Vec<u8> {
+    let mut noise = seed.wrapping_mul(2_654_435_761).max(1);
+
+    (0..samples)
+        .map(|sample| {
+            // xorshift, enough for a deterministic noise floor
+            noise ^= noise << 13;
+            noise ^= noise >> 17;
+            noise ^= noise << 5;
+            let floor = (noise % 16) as f64;
+
+            let echo = peaks
+                .iter()
+                .map(|peak| 230.0 * (-((sample as f64 - peak).powi(2)) / 8.0).exp())
+                .fold(0.0, f64::max);
+
+            (floor + echo).min(255.0) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_profile_peak() {
+        let profile = echo_profile(200, &[120.0], 1);
+        assert_eq!(profile.len(), 200);
+
+        let (max_index, _) = profile
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, value)| **value)
+            .unwrap();
+        assert_eq!(max_index, 120);
+    }
+
+    async fn create_simulated(
+        manager: &mut crate::device::manager::DeviceManager,
+        device: SimulatedDevice,
+        device_type: crate::device::manager::DeviceSelection,
+    ) -> uuid::Uuid {
+        use crate::device::manager::{Answer, SourceSelection, SourceSimulatedStruct};
+
+        let Ok(Answer::DeviceInfo(info)) = manager
+            .create(
+                SourceSelection::Simulated(SourceSimulatedStruct {
+                    device: device.clone(),
+                    scene: SimulatedScene::default(),
+                }),
+                device_type,
+            )
+            .await
+        else {
+            panic!("Failed to create simulated {device:?}");
+        };
+        info[0].id
+    }
+
+    #[tokio::test]
+    async fn test_tsr1000_continuous_mode() {
+        use crate::device::manager::{DeviceManager, DeviceSelection, DeviceStatus};
+
+        let (mut manager, _handler) = DeviceManager::new(10);
+        let device_id = create_simulated(
+            &mut manager,
+            SimulatedDevice::Tsr1000,
+            DeviceSelection::Tsr1000,
+        )
+        .await;
+        let mut subscriber = manager.get_subscriber(device_id).await.unwrap();
+
+        manager.continuous_mode(device_id).await.unwrap();
+        assert_eq!(
+            manager.get_device(device_id).unwrap().status,
+            DeviceStatus::ContinuousMode
+        );
+
+        let profile_id = <bluerobotics_ping::tsr1000::ProfileStruct as MessageInfo>::id();
+        let profile = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let frame = subscriber.recv().await.unwrap();
+                if frame.message.message_id == profile_id {
+                    break frame.message;
+                }
+            }
+        })
+        .await
+        .expect("No TSR1000 profile was streamed");
+        assert!(matches!(
+            bluerobotics_ping::Messages::try_from(&profile),
+            Ok(bluerobotics_ping::Messages::Tsr1000(
+                bluerobotics_ping::tsr1000::Messages::Profile(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_angular_distance() {
+        assert_eq!(angular_distance(0, 399), 1);
+        assert_eq!(angular_distance(399, 0), 1);
+        assert_eq!(angular_distance(100, 300), 200);
+        assert_eq!(angular_distance(10, 15), 5);
+    }
+}
@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+use tracing::{error, info, trace, warn};
+use uuid::Uuid;
+
+use super::{
+    replay::ReplayHandle, DeviceManager, DeviceProperties, DeviceSelection, DeviceStatus,
+    ManagerError, Ping360Config, SourceSelection,
+};
+use crate::device::devices::{DeviceActor, DeviceActorHandler};
+
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+// State captured when a device stops, used to bring it back as it was
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub attempts: u32,
+    pub next_attempt: Instant,
+    pub continuous_mode: bool,
+    pub recording: bool,
+    pub ping360_config: Option<Ping360Config>,
+    /// An attempt is running, its result is sent back through the reconnection channel
+    pub reconnecting: bool,
+}
+
+// Sent back to the manager once a reconnection attempt is over, attempts run on their own task so
+// unresponsive sources don't hold the manager requests
+#[derive(Debug)]
+pub struct Reconnection {
+    pub device_id: Uuid,
+    result: Result<Reconnected, ManagerError>,
+}
+
+#[derive(Debug)]
+struct Reconnected {
+    handler: DeviceActorHandler,
+    actor: JoinHandle<DeviceActor>,
+    replay: Option<ReplayHandle>,
+    device_type: DeviceSelection,
+    properties: Option<DeviceProperties>,
+}
+
+pub fn channel() -> (mpsc::Sender<Reconnection>, mpsc::Receiver<Reconnection>) {
+    mpsc::channel(16)
+}
+
+impl Recovery {
+    fn backoff(attempts: u32) -> Duration {
+        RECONNECT_INITIAL_DELAY
+            .saturating_mul(2u32.saturating_pow(attempts))
+            .min(RECONNECT_MAX_DELAY)
+    }
+}
+
+impl DeviceManager {
+    // Mark devices whose actor or continuous mode task has died as Stopped, keeping what is needed to restore them
+    pub async fn update_devices_status(&mut self) {
+        for device in self.device.values_mut() {
+            if !matches!(
+                device.status,
+                DeviceStatus::Running | DeviceStatus::ContinuousMode
+            ) {
+                continue;
+            }
+
+            let actor_finished = device
+                .actor
+                .as_ref()
+                .is_some_and(|handle| handle.is_finished());
+            let broadcast_finished = device.status == DeviceStatus::ContinuousMode
+                && device
+                    .broadcast
+                    .as_ref()
+                    .is_some_and(|handle| handle.is_finished());
+
+            if !actor_finished && !broadcast_finished {
+                continue;
+            }
+
+            info!("Device stopped, device id: {:?}", device.id);
+
+            let ping360_config = match &device.properties {
+                Some(DeviceProperties::Ping360(properties)) => properties
+                    .continuous_mode_settings
+                    .read()
+                    .ok()
+                    .map(|config| *config),
+                _ => None,
+            };
+
+            device.recovery = Some(Recovery {
+                attempts: 0,
+                next_attempt: Instant::now() + RECONNECT_INITIAL_DELAY,
+                continuous_mode: device.status == DeviceStatus::ContinuousMode,
+                recording: device.recording.is_some(),
+                ping360_config,
+                reconnecting: false,
+            });
+
+            if let Some(handle) = device.actor.take() {
+                handle.abort();
+            }
+            if let Some(handle) = device.broadcast.take() {
+                handle.abort();
+            }
+            device.handler = None;
+            device.replay = None;
+            device.status = DeviceStatus::Stopped;
+        }
+    }
+
+    // Start reconnecting stopped devices whose backoff delay has expired
+    pub async fn supervise_devices(&mut self) {
+        let now = Instant::now();
+        for device in self.device.values_mut() {
+            if device.status != DeviceStatus::Stopped {
+                continue;
+            }
+            let Some(recovery) = device.recovery.as_mut() else {
+                continue;
+            };
+            if recovery.reconnecting || recovery.next_attempt > now {
+                continue;
+            }
+            recovery.reconnecting = true;
+
+            let device_id = device.id;
+            let source = device.source.clone();
+            let device_type = device.device_type.clone();
+            let sender = self.reconnection_sender.clone();
+            tokio::spawn(async move {
+                let result = reconnect(device_id, source, device_type).await;
+                if let Err(mpsc::error::SendError(reconnection)) =
+                    sender.send(Reconnection { device_id, result }).await
+                {
+                    trace!(
+                        "Reconnection finished after the manager stopped, device id: {device_id}"
+                    );
+                    if let Ok(reconnected) = reconnection.result {
+                        reconnected.actor.abort();
+                    }
+                }
+            });
+        }
+    }
+
+    pub async fn finish_reconnection(&mut self, reconnection: Reconnection) {
+        let device_id = reconnection.device_id;
+
+        // Deleted, or taken over by a firmware update, while reconnecting
+        let pending = self
+            .device
+            .get(&device_id)
+            .and_then(|device| device.recovery.as_ref())
+            .is_some_and(|recovery| recovery.reconnecting);
+        if !pending {
+            if let Ok(reconnected) = reconnection.result {
+                reconnected.actor.abort();
+            }
+            return;
+        }
+
+        let result = match reconnection.result {
+            Ok(reconnected) => self.restore_device(device_id, reconnected).await,
+            Err(err) => Err(err),
+        };
+        let err = match result {
+            Ok(()) => {
+                info!("Device reconnected, device id: {device_id}");
+                return;
+            }
+            Err(err) => err,
+        };
+
+        // The device may have been partially restored, make sure it is stopped before retrying
+        let Ok(device) = self.get_mut_device(device_id) else {
+            return;
+        };
+        if let Some(handle) = device.actor.take() {
+            handle.abort();
+        }
+        if let Some(handle) = device.broadcast.take() {
+            handle.abort();
+        }
+        device.handler = None;
+        device.replay = None;
+        device.status = DeviceStatus::Stopped;
+
+        if let Some(recovery) = device.recovery.as_mut() {
+            recovery.reconnecting = false;
+            recovery.attempts = recovery.attempts.saturating_add(1);
+            let delay = Recovery::backoff(recovery.attempts);
+            recovery.next_attempt = Instant::now() + delay;
+            warn!(
+                "Device reconnection attempt {} failed: {err:?}, retrying in {delay:?}, device id: {device_id}",
+                recovery.attempts
+            );
+        }
+    }
+
+    async fn restore_device(
+        &mut self,
+        device_id: Uuid,
+        reconnected: Reconnected,
+    ) -> Result<(), ManagerError> {
+        let device = self.get_mut_device(device_id)?;
+        let recovery = device.recovery.clone().ok_or(ManagerError::Other(format!(
+            "Device has no recovery state, device: {device_id}"
+        )))?;
+
+        device.handler = Some(reconnected.handler);
+        device.actor = Some(reconnected.actor);
+        device.replay = reconnected.replay;
+        device.device_type = reconnected.device_type;
+        device.properties = reconnected.properties;
+        device.status = DeviceStatus::Running;
+        super::events::publish(super::events::DeviceEvent::PropertiesUpdated(device.info()));
+
+        if recovery.continuous_mode {
+            self.continuous_mode(device_id).await?;
+        }
+
+        // Properties are rebuilt with default settings, the continuous mode task shares them and picks the restored ones up
+        if let Some(config) = recovery.ping360_config {
+            self.update_ping360_config(device_id, config).await?;
+        }
+
+        if recovery.recording {
+            if let Some(recorder) = self.get_mut_device(device_id)?.recording.take() {
+                recorder.stop().await;
+            }
+            if let Err(err) = self.start_recording(device_id).await {
+                error!(
+                    "Failed to restart recording after reconnection: {err:?}, device: {device_id}"
+                );
+            }
+        }
+
+        if let Err(err) = self.start_mavlink_output(device_id).await {
+            error!(
+                "Failed to restart MAVLink output after reconnection: {err:?}, device: {device_id}"
+            );
+        }
+
+        if let Err(err) = self.start_nmea_output(device_id).await {
+            error!(
+                "Failed to restart NMEA output after reconnection: {err:?}, device: {device_id}"
+            );
+        }
+
+        self.get_mut_device(device_id)?.recovery = None;
+        Ok(())
+    }
+}
+
+// Opens the source with the stored device type, and makes sure the device answers before handing it to the manager
+async fn reconnect(
+    device_id: Uuid,
+    source: SourceSelection,
+    device_type: DeviceSelection,
+) -> Result<Reconnected, ManagerError> {
+    trace!("Reconnecting device: {device_id}, source: {source:?}");
+
+    let (device_actor, handler, device_type, replay) =
+        DeviceManager::open_device(&source, device_type).await?;
+    let actor = tokio::spawn(async move { device_actor.run().await });
+
+    match DeviceManager::read_device_properties(&handler, device_id, &device_type).await {
+        Ok(properties) => Ok(Reconnected {
+            handler,
+            actor,
+            replay,
+            device_type,
+            properties,
+        }),
+        Err(err) => {
+            actor.abort();
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_backoff() {
+        assert_eq!(Recovery::backoff(0), Duration::from_secs(1));
+        assert_eq!(Recovery::backoff(1), Duration::from_secs(2));
+        assert_eq!(Recovery::backoff(3), Duration::from_secs(8));
+        assert_eq!(Recovery::backoff(10), RECONNECT_MAX_DELAY);
+        assert_eq!(Recovery::backoff(u32::MAX), RECONNECT_MAX_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_dropped_device() {
+        use crate::device::manager::{
+            simulation::{SimulatedDevice, SimulatedScene},
+            Answer, SourceSimulatedStruct,
+        };
+
+        let (mut manager, _handler) = DeviceManager::new(10);
+        let Ok(Answer::DeviceInfo(info)) = manager
+            .create(
+                SourceSelection::Simulated(SourceSimulatedStruct {
+                    device: SimulatedDevice::Tsr1000,
+                    scene: SimulatedScene::default(),
+                }),
+                DeviceSelection::Tsr1000,
+            )
+            .await
+        else {
+            panic!("Failed to create simulated TSR1000");
+        };
+        let device_id = info[0].id;
+
+        // Drop the device link
+        manager
+            .get_device(device_id)
+            .unwrap()
+            .actor
+            .as_ref()
+            .unwrap()
+            .abort();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                manager.update_devices_status().await;
+                if manager.get_device(device_id).unwrap().status == DeviceStatus::Stopped {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Device was not found stopped");
+
+        let recovery = manager
+            .get_mut_device(device_id)
+            .unwrap()
+            .recovery
+            .as_mut()
+            .unwrap();
+        assert!(recovery.continuous_mode);
+        recovery.next_attempt = Instant::now();
+
+        manager.supervise_devices().await;
+        let reconnection =
+            tokio::time::timeout(Duration::from_secs(5), manager.reconnection_receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        manager.finish_reconnection(reconnection).await;
+
+        let device = manager.get_device(device_id).unwrap();
+        assert_eq!(device.status, DeviceStatus::ContinuousMode);
+        assert_eq!(device.device_type, DeviceSelection::Tsr1000);
+        assert!(device.recovery.is_none());
+    }
+}
@@ -4,6 +4,12 @@
 /// The `DeviceHandler` can forward requests defined in the `PingRequest` enum.
 pub mod devices;
 
+/// The `health` module tracks link statistics for each device.
+///
+/// Incoming bytes are decoded a second time to account messages and parser errors,
+/// while the `DeviceHandler` accounts request latency and timeouts.
+pub mod health;
+
 /// The `manager` module provides the `Manager` and `ManagerHandler` structures.
 ///
 /// The `Manager` can handle requests from multiple threads. The `ManagerHandler`
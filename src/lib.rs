@@ -1,6 +1,7 @@
 pub mod cli;
 pub mod device;
 pub mod logger;
+pub mod mavlink;
 pub mod server;
 
 use serde::{Deserialize, Serialize};
@@ -9,4 +10,5 @@ use serde::{Deserialize, Serialize};
 #[serde(tag = "module")]
 pub enum ModuleType {
     DeviceManager(device::manager::Request),
+    Websocket(server::protocols::v1::websocket::WebsocketControl),
 }
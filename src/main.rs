@@ -1,6 +1,6 @@
-use tracing::info;
+use tracing::{error, info};
 
-use ping_viewer_next::{cli, device, logger, server};
+use ping_viewer_next::{cli, device, logger, mavlink, server};
 
 #[tokio::main]
 async fn main() {
@@ -10,8 +10,16 @@ async fn main() {
     logger::manager::init();
 
     let (mut manager, handler) = device::manager::DeviceManager::new(10);
+    manager.set_discovery_config(cli::manager::discovery());
+
+    if cli::manager::is_reset() {
+        device::manager::registry::reset(&device::manager::registry::get_registry_path());
+    }
+    manager.restore_registry().await;
+    manager
+        .add_configured_devices(&cli::manager::configured_devices())
+        .await;
 
-    //Todo: Load previous devices
     if cli::manager::is_enable_auto_create() {
         match manager.auto_create().await {
             Ok(answer) => info!("DeviceManager initialized with following devices: {answer:?}"),
@@ -19,9 +27,43 @@ async fn main() {
         }
     }
 
+    if let Some(source) = cli::manager::vehicle_pose() {
+        match source.parse::<mavlink::pose::PoseSource>() {
+            Ok(source) => {
+                mavlink::pose::spawn(source);
+            }
+            Err(err) => error!("Vehicle pose disabled: {err}"),
+        }
+    }
+
+    // Refuse to start with broken tokens instead of leaving the API open
+    let tokens = match cli::manager::auth_tokens_path() {
+        Some(path) => match server::auth::ApiTokens::load(&path) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                error!("{err}");
+                std::process::exit(1);
+            }
+        },
+        None => server::auth::ApiTokens::default(),
+    };
+
+    let tls = match cli::manager::tls() {
+        Some(config) => {
+            match server::tls::server_config(&config, &cli::manager::server_address()) {
+                Ok(tls) => Some(tls),
+                Err(err) => {
+                    error!("{err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+
     tokio::spawn(async move { manager.run().await });
 
-    server::manager::run(&cli::manager::server_address(), handler)
+    server::manager::run(&cli::manager::server_address(), handler, tokens, tls)
         .await
         .unwrap();
 }
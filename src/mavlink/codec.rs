@@ -0,0 +1,600 @@
+// Minimal MAVLink 2 framing, only the messages used by the application are described here
+
+const MAVLINK_V2_STX: u8 = 0xFD;
+const MAVLINK_V2_HEADER_SIZE: usize = 10;
+const MAVLINK_V2_CHECKSUM_SIZE: usize = 2;
+const MAVLINK_IFLAG_SIGNED: u8 = 0x01;
+const MAVLINK_SIGNATURE_SIZE: usize = 13;
+
+pub trait Message: Sized {
+    const ID: u32;
+    const CRC_EXTRA: u8;
+    const LENGTH: usize;
+
+    fn serialize(&self) -> Vec<u8>;
+    // Payload is zero extended to LENGTH before being handed over, as MAVLink 2 truncates trailing zeros
+    fn deserialize(payload: &[u8]) -> Self;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub sequence: u8,
+    pub system_id: u8,
+    pub component_id: u8,
+    pub message_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new<M: Message>(message: &M, sequence: u8, system_id: u8, component_id: u8) -> Self {
+        let mut payload = message.serialize();
+        while payload.len() > 1 && payload.last() == Some(&0) {
+            payload.pop();
+        }
+
+        Self {
+            sequence,
+            system_id,
+            component_id,
+            message_id: M::ID,
+            payload,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let crc_extra = crc_extra(self.message_id).unwrap_or_default();
+        let id = self.message_id.to_le_bytes();
+
+        let mut frame = Vec::with_capacity(
+            MAVLINK_V2_HEADER_SIZE + self.payload.len() + MAVLINK_V2_CHECKSUM_SIZE,
+        );
+        frame.extend_from_slice(&[
+            MAVLINK_V2_STX,
+            self.payload.len() as u8,
+            0,
+            0,
+            self.sequence,
+            self.system_id,
+            self.component_id,
+            id[0],
+            id[1],
+            id[2],
+        ]);
+        frame.extend_from_slice(&self.payload);
+
+        let mut crc = crc_accumulate(0xFFFF, &frame[1..]);
+        crc = crc_accumulate(crc, &[crc_extra]);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    pub fn message<M: Message>(&self) -> Option<M> {
+        if self.message_id != M::ID {
+            return None;
+        }
+        let mut payload = self.payload.clone();
+        payload.resize(M::LENGTH.max(payload.len()), 0);
+        Some(M::deserialize(&payload))
+    }
+}
+
+fn crc_extra(message_id: u32) -> Option<u8> {
+    match message_id {
+        Heartbeat::ID => Some(Heartbeat::CRC_EXTRA),
+        DistanceSensor::ID => Some(DistanceSensor::CRC_EXTRA),
+        ObstacleDistance::ID => Some(ObstacleDistance::CRC_EXTRA),
+        Attitude::ID => Some(Attitude::CRC_EXTRA),
+        GlobalPositionInt::ID => Some(GlobalPositionInt::CRC_EXTRA),
+        _ => None,
+    }
+}
+
+// CRC-16/MCRF4XX, as used by MAVLink
+pub fn crc_accumulate(mut crc: u16, bytes: &[u8]) -> u16 {
+    for byte in bytes {
+        let mut tmp = byte ^ (crc & 0xFF) as u8;
+        tmp ^= tmp << 4;
+        crc = (crc >> 8) ^ ((tmp as u16) << 8) ^ ((tmp as u16) << 3) ^ ((tmp as u16) >> 4);
+    }
+    crc
+}
+
+// Stream decoder, frames of unknown messages are skipped since their checksum can't be verified
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Frame> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut frames = Vec::new();
+        loop {
+            match self.buffer.iter().position(|byte| *byte == MAVLINK_V2_STX) {
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    self.buffer.clear();
+                    break;
+                }
+            }
+            if self.buffer.len() < MAVLINK_V2_HEADER_SIZE {
+                break;
+            }
+
+            let message_id =
+                u32::from_le_bytes([self.buffer[7], self.buffer[8], self.buffer[9], 0]);
+            let Some(crc_extra) =
+                crc_extra(message_id).filter(|_| self.buffer[2] & !MAVLINK_IFLAG_SIGNED == 0)
+            else {
+                // Skip the start byte only, a real frame may begin inside the discarded bytes
+                self.buffer.drain(..1);
+                continue;
+            };
+
+            let payload_size = self.buffer[1] as usize;
+            let signature_size = if self.buffer[2] & MAVLINK_IFLAG_SIGNED != 0 {
+                MAVLINK_SIGNATURE_SIZE
+            } else {
+                0
+            };
+            let size =
+                MAVLINK_V2_HEADER_SIZE + payload_size + MAVLINK_V2_CHECKSUM_SIZE + signature_size;
+            if self.buffer.len() < size {
+                break;
+            }
+
+            let checksum_start = MAVLINK_V2_HEADER_SIZE + payload_size;
+            let checksum =
+                u16::from_le_bytes([self.buffer[checksum_start], self.buffer[checksum_start + 1]]);
+
+            let crc = crc_accumulate(0xFFFF, &self.buffer[1..checksum_start]);
+            if crc_accumulate(crc, &[crc_extra]) != checksum {
+                self.buffer.drain(..1);
+                continue;
+            }
+
+            frames.push(Frame {
+                sequence: self.buffer[4],
+                system_id: self.buffer[5],
+                component_id: self.buffer[6],
+                message_id,
+                payload: self.buffer[MAVLINK_V2_HEADER_SIZE..checksum_start].to_vec(),
+            });
+            self.buffer.drain(..size);
+        }
+        frames
+    }
+}
+
+fn f32_at(payload: &[u8], index: usize) -> f32 {
+    f32::from_le_bytes([
+        payload[index],
+        payload[index + 1],
+        payload[index + 2],
+        payload[index + 3],
+    ])
+}
+
+// Values of the MAVLink enums used by the application
+pub const MAV_TYPE_ONBOARD_CONTROLLER: u8 = 18;
+pub const MAV_AUTOPILOT_INVALID: u8 = 8;
+pub const MAV_STATE_ACTIVE: u8 = 4;
+pub const MAV_DISTANCE_SENSOR_ULTRASOUND: u8 = 1;
+pub const MAV_FRAME_BODY_FRD: u8 = 12;
+pub const MAV_COMP_ID_AUTOPILOT1: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heartbeat {
+    pub custom_mode: u32,
+    pub mav_type: u8,
+    pub autopilot: u8,
+    pub base_mode: u8,
+    pub system_status: u8,
+    pub mavlink_version: u8,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            custom_mode: 0,
+            mav_type: MAV_TYPE_ONBOARD_CONTROLLER,
+            autopilot: MAV_AUTOPILOT_INVALID,
+            base_mode: 0,
+            system_status: MAV_STATE_ACTIVE,
+            mavlink_version: 3,
+        }
+    }
+}
+
+impl Message for Heartbeat {
+    const ID: u32 = 0;
+    const CRC_EXTRA: u8 = 50;
+    const LENGTH: usize = 9;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut payload = self.custom_mode.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[
+            self.mav_type,
+            self.autopilot,
+            self.base_mode,
+            self.system_status,
+            self.mavlink_version,
+        ]);
+        payload
+    }
+
+    fn deserialize(payload: &[u8]) -> Self {
+        Self {
+            custom_mode: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            mav_type: payload[4],
+            autopilot: payload[5],
+            base_mode: payload[6],
+            system_status: payload[7],
+            mavlink_version: payload[8],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DistanceSensor {
+    pub time_boot_ms: u32,
+    /// Distances in centimeters
+    pub min_distance: u16,
+    pub max_distance: u16,
+    pub current_distance: u16,
+    pub sensor_type: u8,
+    pub id: u8,
+    pub orientation: u8,
+    pub covariance: u8,
+    pub horizontal_fov: f32,
+    pub vertical_fov: f32,
+    pub quaternion: [f32; 4],
+    /// 0 when unknown, otherwise from 1 to 100
+    pub signal_quality: u8,
+}
+
+impl Message for DistanceSensor {
+    const ID: u32 = 132;
+    const CRC_EXTRA: u8 = 85;
+    const LENGTH: usize = 39;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::LENGTH);
+        payload.extend_from_slice(&self.time_boot_ms.to_le_bytes());
+        payload.extend_from_slice(&self.min_distance.to_le_bytes());
+        payload.extend_from_slice(&self.max_distance.to_le_bytes());
+        payload.extend_from_slice(&self.current_distance.to_le_bytes());
+        payload.extend_from_slice(&[self.sensor_type, self.id, self.orientation, self.covariance]);
+        payload.extend_from_slice(&self.horizontal_fov.to_le_bytes());
+        payload.extend_from_slice(&self.vertical_fov.to_le_bytes());
+        for value in self.quaternion {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        payload.push(self.signal_quality);
+        payload
+    }
+
+    fn deserialize(payload: &[u8]) -> Self {
+        Self {
+            time_boot_ms: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            min_distance: u16::from_le_bytes([payload[4], payload[5]]),
+            max_distance: u16::from_le_bytes([payload[6], payload[7]]),
+            current_distance: u16::from_le_bytes([payload[8], payload[9]]),
+            sensor_type: payload[10],
+            id: payload[11],
+            orientation: payload[12],
+            covariance: payload[13],
+            horizontal_fov: f32_at(payload, 14),
+            vertical_fov: f32_at(payload, 18),
+            quaternion: [
+                f32_at(payload, 22),
+                f32_at(payload, 26),
+                f32_at(payload, 30),
+                f32_at(payload, 34),
+            ],
+            signal_quality: payload[38],
+        }
+    }
+}
+
+pub const OBSTACLE_DISTANCE_SECTORS: usize = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObstacleDistance {
+    pub time_usec: u64,
+    /// Distances in centimeters, UINT16_MAX when unknown and max_distance + 1 when there is no obstacle
+    pub distances: [u16; OBSTACLE_DISTANCE_SECTORS],
+    pub min_distance: u16,
+    pub max_distance: u16,
+    pub sensor_type: u8,
+    pub increment: u8,
+    pub increment_f: f32,
+    /// Angle of the first sector in degrees, clockwise from the vehicle front
+    pub angle_offset: f32,
+    pub frame: u8,
+}
+
+impl Default for ObstacleDistance {
+    fn default() -> Self {
+        Self {
+            time_usec: 0,
+            distances: [u16::MAX; OBSTACLE_DISTANCE_SECTORS],
+            min_distance: 0,
+            max_distance: 0,
+            sensor_type: 0,
+            increment: 0,
+            increment_f: 0.0,
+            angle_offset: 0.0,
+            frame: 0,
+        }
+    }
+}
+
+impl Message for ObstacleDistance {
+    const ID: u32 = 330;
+    const CRC_EXTRA: u8 = 23;
+    const LENGTH: usize = 167;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::LENGTH);
+        payload.extend_from_slice(&self.time_usec.to_le_bytes());
+        for distance in self.distances {
+            payload.extend_from_slice(&distance.to_le_bytes());
+        }
+        payload.extend_from_slice(&self.min_distance.to_le_bytes());
+        payload.extend_from_slice(&self.max_distance.to_le_bytes());
+        payload.extend_from_slice(&[self.sensor_type, self.increment]);
+        payload.extend_from_slice(&self.increment_f.to_le_bytes());
+        payload.extend_from_slice(&self.angle_offset.to_le_bytes());
+        payload.push(self.frame);
+        payload
+    }
+
+    fn deserialize(payload: &[u8]) -> Self {
+        let mut distances = [0u16; OBSTACLE_DISTANCE_SECTORS];
+        for (index, distance) in distances.iter_mut().enumerate() {
+            *distance = u16::from_le_bytes([payload[8 + index * 2], payload[9 + index * 2]]);
+        }
+        Self {
+            time_usec: u64::from_le_bytes([
+                payload[0], payload[1], payload[2], payload[3], payload[4], payload[5], payload[6],
+                payload[7],
+            ]),
+            distances,
+            min_distance: u16::from_le_bytes([payload[152], payload[153]]),
+            max_distance: u16::from_le_bytes([payload[154], payload[155]]),
+            sensor_type: payload[156],
+            increment: payload[157],
+            increment_f: f32_at(payload, 158),
+            angle_offset: f32_at(payload, 162),
+            frame: payload[166],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Attitude {
+    pub time_boot_ms: u32,
+    /// Angles in radians, yaw is clockwise from north
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub rollspeed: f32,
+    pub pitchspeed: f32,
+    pub yawspeed: f32,
+}
+
+impl Message for Attitude {
+    const ID: u32 = 30;
+    const CRC_EXTRA: u8 = 39;
+    const LENGTH: usize = 28;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::LENGTH);
+        payload.extend_from_slice(&self.time_boot_ms.to_le_bytes());
+        for value in [
+            self.roll,
+            self.pitch,
+            self.yaw,
+            self.rollspeed,
+            self.pitchspeed,
+            self.yawspeed,
+        ] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        payload
+    }
+
+    fn deserialize(payload: &[u8]) -> Self {
+        Self {
+            time_boot_ms: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            roll: f32_at(payload, 4),
+            pitch: f32_at(payload, 8),
+            yaw: f32_at(payload, 12),
+            rollspeed: f32_at(payload, 16),
+            pitchspeed: f32_at(payload, 20),
+            yawspeed: f32_at(payload, 24),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GlobalPositionInt {
+    pub time_boot_ms: u32,
+    /// Degrees * 1E7
+    pub lat: i32,
+    pub lon: i32,
+    /// Millimeters
+    pub alt: i32,
+    pub relative_alt: i32,
+    /// Centimeters per second
+    pub vx: i16,
+    pub vy: i16,
+    pub vz: i16,
+    /// Centidegrees, UINT16_MAX when unknown
+    pub hdg: u16,
+}
+
+impl Message for GlobalPositionInt {
+    const ID: u32 = 33;
+    const CRC_EXTRA: u8 = 104;
+    const LENGTH: usize = 28;
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::LENGTH);
+        payload.extend_from_slice(&self.time_boot_ms.to_le_bytes());
+        for value in [self.lat, self.lon, self.alt, self.relative_alt] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [self.vx, self.vy, self.vz] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        payload.extend_from_slice(&self.hdg.to_le_bytes());
+        payload
+    }
+
+    fn deserialize(payload: &[u8]) -> Self {
+        let i32_at = |index: usize| {
+            i32::from_le_bytes([
+                payload[index],
+                payload[index + 1],
+                payload[index + 2],
+                payload[index + 3],
+            ])
+        };
+        let i16_at = |index: usize| i16::from_le_bytes([payload[index], payload[index + 1]]);
+        Self {
+            time_boot_ms: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            lat: i32_at(4),
+            lon: i32_at(8),
+            alt: i32_at(12),
+            relative_alt: i32_at(16),
+            vx: i16_at(20),
+            vy: i16_at(22),
+            vz: i16_at(24),
+            hdg: u16::from_le_bytes([payload[26], payload[27]]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_check_value() {
+        assert_eq!(crc_accumulate(0xFFFF, b"123456789"), 0x6F91);
+    }
+
+    #[test]
+    fn test_heartbeat_frame() {
+        let frame = Frame::new(&Heartbeat::default(), 7, 1, 191).encode();
+        assert_eq!(
+            &frame[..MAVLINK_V2_HEADER_SIZE],
+            &[0xFD, 9, 0, 0, 7, 1, 191, 0, 0, 0]
+        );
+        assert_eq!(
+            frame.len(),
+            MAVLINK_V2_HEADER_SIZE + 9 + MAVLINK_V2_CHECKSUM_SIZE
+        );
+    }
+
+    #[test]
+    fn test_distance_sensor_roundtrip() {
+        let message = DistanceSensor {
+            time_boot_ms: 1234,
+            min_distance: 30,
+            max_distance: 5000,
+            current_distance: 1520,
+            sensor_type: MAV_DISTANCE_SENSOR_ULTRASOUND,
+            id: 1,
+            orientation: 25,
+            covariance: 255,
+            signal_quality: 87,
+            ..Default::default()
+        };
+        let bytes = Frame::new(&message, 0, 1, 191).encode();
+
+        let mut decoder = Decoder::new();
+        // Garbage and a split frame must not prevent decoding
+        let mut frames = decoder.push(&[0x00, 0xFD, 0x42]);
+        frames.extend(decoder.push(&bytes[..5]));
+        frames.extend(decoder.push(&bytes[5..]));
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].message::<DistanceSensor>(), Some(message));
+    }
+
+    #[test]
+    fn test_obstacle_distance_roundtrip() {
+        let mut message = ObstacleDistance {
+            time_usec: 5_000_000,
+            min_distance: 75,
+            max_distance: 5000,
+            sensor_type: MAV_DISTANCE_SENSOR_ULTRASOUND,
+            increment: 5,
+            increment_f: 5.0,
+            frame: MAV_FRAME_BODY_FRD,
+            ..Default::default()
+        };
+        message.distances[0] = 320;
+        message.distances[71] = 5001;
+
+        let bytes = Frame::new(&message, 0, 1, 194).encode();
+        let frames = Decoder::new().push(&bytes);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].message::<ObstacleDistance>(), Some(message));
+    }
+
+    #[test]
+    fn test_payload_truncation() {
+        let frame = Frame::new(
+            &DistanceSensor {
+                current_distance: 100,
+                ..Default::default()
+            },
+            0,
+            1,
+            1,
+        );
+        assert_eq!(frame.payload.len(), 9);
+        assert_eq!(
+            frame.message::<DistanceSensor>().unwrap().current_distance,
+            100
+        );
+    }
+
+    #[test]
+    fn test_pose_messages_roundtrip() {
+        let attitude = Attitude {
+            time_boot_ms: 52000,
+            roll: 0.01,
+            pitch: -0.02,
+            yaw: 1.5,
+            ..Default::default()
+        };
+        let position = GlobalPositionInt {
+            time_boot_ms: 52010,
+            lat: -275_000_000,
+            lon: -485_000_000,
+            alt: -3200,
+            hdg: 8594,
+            ..Default::default()
+        };
+
+        let mut bytes = Frame::new(&attitude, 0, 1, MAV_COMP_ID_AUTOPILOT1).encode();
+        bytes.extend(Frame::new(&position, 1, 1, MAV_COMP_ID_AUTOPILOT1).encode());
+
+        let frames = Decoder::new().push(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].message::<Attitude>(), Some(attitude));
+        assert_eq!(frames[1].message::<GlobalPositionInt>(), Some(position));
+    }
+}
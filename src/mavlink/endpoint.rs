@@ -0,0 +1,137 @@
+use std::{fmt, io, str::FromStr};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket},
+};
+use tracing::{trace, warn};
+
+use super::codec::{Frame, Message};
+
+// Same notation used by mavlink-router and pymavlink, e.g. udpout:192.168.2.1:14550
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    UdpOut(String),
+    TcpOut(String),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let (scheme, host) = address
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid MAVLink endpoint: {address}"))?;
+        if host.rsplit_once(':').is_none() {
+            return Err(format!("MAVLink endpoint is missing the port: {address}"));
+        }
+
+        match scheme {
+            "udpout" => Ok(Self::UdpOut(host.to_string())),
+            "tcpout" => Ok(Self::TcpOut(host.to_string())),
+            _ => Err(format!(
+                "Unsupported MAVLink endpoint, expected udpout or tcpout: {address}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UdpOut(host) => write!(f, "udpout:{host}"),
+            Self::TcpOut(host) => write!(f, "tcpout:{host}"),
+        }
+    }
+}
+
+enum Link {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+pub struct Connection {
+    address: Address,
+    link: Option<Link>,
+    sequence: u8,
+    system_id: u8,
+    component_id: u8,
+}
+
+impl Connection {
+    pub fn new(address: Address, system_id: u8, component_id: u8) -> Self {
+        Self {
+            address,
+            link: None,
+            sequence: 0,
+            system_id,
+            component_id,
+        }
+    }
+
+    // Failures are kept quiet, the caller retries on its own pace
+    pub async fn ensure_connected(&mut self) -> bool {
+        if self.link.is_some() {
+            return true;
+        }
+
+        let link = match &self.address {
+            Address::UdpOut(host) => match UdpSocket::bind("0.0.0.0:0").await {
+                Ok(socket) => socket.connect(host).await.map(|_| Link::Udp(socket)),
+                Err(err) => Err(err),
+            },
+            Address::TcpOut(host) => TcpStream::connect(host).await.map(Link::Tcp),
+        };
+
+        match link {
+            Ok(link) => {
+                trace!("MAVLink: Connected to {}", self.address);
+                self.link = Some(link);
+                true
+            }
+            Err(err) => {
+                trace!("MAVLink: Failed to connect to {}: {err}", self.address);
+                false
+            }
+        }
+    }
+
+    pub async fn send<M: Message>(&mut self, message: &M) -> io::Result<()> {
+        let frame = Frame::new(message, self.sequence, self.system_id, self.component_id).encode();
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let result = match &mut self.link {
+            Some(Link::Udp(socket)) => socket.send(&frame).await.map(|_| ()),
+            Some(Link::Tcp(stream)) => stream.write_all(&frame).await,
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        };
+
+        // A refused datagram only means nobody is listening yet, a broken TCP stream must be reopened
+        if let Err(err) = &result {
+            if matches!(self.link, Some(Link::Tcp(_))) {
+                warn!("MAVLink: Connection to {} lost: {err}", self.address);
+                self.link = None;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_parsing() {
+        assert_eq!(
+            "udpout:127.0.0.1:14550".parse::<Address>(),
+            Ok(Address::UdpOut("127.0.0.1:14550".to_string()))
+        );
+        assert_eq!(
+            "tcpout:blueos.local:5777".parse::<Address>(),
+            Ok(Address::TcpOut("blueos.local:5777".to_string()))
+        );
+        assert!("udpin:0.0.0.0:14550".parse::<Address>().is_err());
+        assert!("udpout:127.0.0.1".parse::<Address>().is_err());
+    }
+}
@@ -0,0 +1,10 @@
+/// The `codec` module encodes and decodes MAVLink 2 frames.
+///
+/// Only the messages exchanged by the application are described, frames of other messages are skipped.
+pub mod codec;
+
+/// The `endpoint` module keeps a connection to a MAVLink router or autopilot, over UDP or TCP.
+pub mod endpoint;
+
+/// The `pose` module follows the vehicle heading from the autopilot, to stabilize sonar data.
+pub mod pose;
@@ -0,0 +1,294 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tokio::net::UdpSocket;
+use tokio_tungstenite::tungstenite;
+use tracing::{info, trace, warn};
+
+use super::codec::{Attitude, Decoder, Frame, GlobalPositionInt, MAV_COMP_ID_AUTOPILOT1};
+
+// Long enough to cover the time a Ping360 reply takes to arrive
+const HISTORY_LENGTH: Duration = Duration::from_secs(5);
+// Older headings are not trusted anymore, e.g. when the autopilot link is lost
+const HEADING_TIMEOUT: Duration = Duration::from_secs(1);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref HEADING: RwLock<HeadingHistory> = RwLock::new(HeadingHistory::default());
+}
+
+// Either raw MAVLink, e.g. udpin:0.0.0.0:14550, or a mavlink2rest websocket, e.g. ws://blueos.local:6040/ws/mavlink
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoseSource {
+    UdpIn(String),
+    Websocket(String),
+}
+
+impl FromStr for PoseSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        if source.starts_with("ws://") {
+            return Ok(Self::Websocket(source.to_string()));
+        }
+        // Built without TLS support, a secure url would only fail once connecting
+        if source.starts_with("wss://") {
+            return Err(format!(
+                "Secure websockets are not supported as vehicle pose source, use a ws:// url: {source}"
+            ));
+        }
+
+        match source.split_once(':') {
+            Some(("udpin", host)) if host.rsplit_once(':').is_some() => {
+                Ok(Self::UdpIn(host.to_string()))
+            }
+            _ => Err(format!(
+                "Unsupported vehicle pose source, expected udpin:<ip>:<port> or a ws:// url: {source}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PoseSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UdpIn(host) => write!(f, "udpin:{host}"),
+            Self::Websocket(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+// Recent vehicle headings in degrees, clockwise from north
+#[derive(Debug, Default)]
+pub struct HeadingHistory {
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl HeadingHistory {
+    pub fn push(&mut self, time: Instant, heading: f32) {
+        if self
+            .samples
+            .back()
+            .is_some_and(|(latest, _)| time < *latest)
+        {
+            return;
+        }
+        while self
+            .samples
+            .front()
+            .is_some_and(|(oldest, _)| time.duration_since(*oldest) > HISTORY_LENGTH)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((time, heading.rem_euclid(360.0)));
+    }
+
+    pub fn heading_at(&self, time: Instant) -> Option<f32> {
+        let next = self
+            .samples
+            .iter()
+            .position(|(sample_time, _)| *sample_time >= time);
+
+        match next {
+            Some(0) => {
+                let (sample_time, heading) = self.samples[0];
+                (sample_time.duration_since(time) <= HEADING_TIMEOUT).then_some(heading)
+            }
+            Some(index) => {
+                let (before_time, before) = self.samples[index - 1];
+                let (after_time, after) = self.samples[index];
+                let span = after_time.duration_since(before_time).as_secs_f32();
+                if span <= 0.0 {
+                    return Some(after);
+                }
+                let ratio = time.duration_since(before_time).as_secs_f32() / span;
+                // Interpolate over the shortest arc, so 359 to 1 goes through 0
+                let delta = (after - before + 540.0).rem_euclid(360.0) - 180.0;
+                Some((before + delta * ratio).rem_euclid(360.0))
+            }
+            None => {
+                let (sample_time, heading) = *self.samples.back()?;
+                (time.duration_since(sample_time) <= HEADING_TIMEOUT).then_some(heading)
+            }
+        }
+    }
+}
+
+// Vehicle heading at the given time, None when no recent pose is available
+pub fn heading_at(time: Instant) -> Option<f32> {
+    HEADING.read().ok()?.heading_at(time)
+}
+
+#[derive(Debug, Default)]
+struct HeadingInput {
+    last_attitude: Option<Instant>,
+}
+
+impl HeadingInput {
+    fn attitude(&mut self, yaw: f32) {
+        let now = Instant::now();
+        self.last_attitude = Some(now);
+        Self::record(now, yaw.to_degrees());
+    }
+
+    // GLOBAL_POSITION_INT is slower and coarser, only used while ATTITUDE is not streamed
+    fn position(&mut self, hdg: u16) {
+        if hdg == u16::MAX
+            || self
+                .last_attitude
+                .is_some_and(|time| time.elapsed() <= HEADING_TIMEOUT)
+        {
+            return;
+        }
+        Self::record(Instant::now(), hdg as f32 / 100.0);
+    }
+
+    fn record(time: Instant, heading: f32) {
+        match HEADING.write() {
+            Ok(mut history) => history.push(time, heading),
+            Err(err) => warn!("Vehicle pose: Failed to store heading: {err}"),
+        }
+    }
+
+    fn handle_frame(&mut self, frame: &Frame) {
+        if frame.component_id != MAV_COMP_ID_AUTOPILOT1 {
+            return;
+        }
+        if let Some(attitude) = frame.message::<Attitude>() {
+            self.attitude(attitude.yaw);
+        } else if let Some(position) = frame.message::<GlobalPositionInt>() {
+            self.position(position.hdg);
+        }
+    }
+
+    // mavlink2rest format: {"header": {"component_id": 1, ..}, "message": {"type": "ATTITUDE", "yaw": 1.5, ..}}
+    fn handle_json(&mut self, text: &str) {
+        let Ok(value) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        if value["header"]["component_id"].as_u64() != Some(MAV_COMP_ID_AUTOPILOT1 as u64) {
+            return;
+        }
+
+        let message = &value["message"];
+        match message["type"].as_str() {
+            Some("ATTITUDE") => {
+                if let Some(yaw) = message["yaw"].as_f64() {
+                    self.attitude(yaw as f32);
+                }
+            }
+            Some("GLOBAL_POSITION_INT") => {
+                if let Some(hdg) = message["hdg"].as_u64() {
+                    self.position(hdg.min(u16::MAX as u64) as u16);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn run_udp(host: &str, input: &mut HeadingInput) -> Result<(), String> {
+    let socket = UdpSocket::bind(host)
+        .await
+        .map_err(|err| format!("Failed to bind {host}: {err}"))?;
+    info!("Vehicle pose: Listening for MAVLink on udpin:{host}");
+
+    let mut decoder = Decoder::new();
+    let mut buffer = [0u8; 2048];
+    loop {
+        let (size, _) = socket
+            .recv_from(&mut buffer)
+            .await
+            .map_err(|err| format!("Failed to receive from {host}: {err}"))?;
+        for frame in decoder.push(&buffer[..size]) {
+            input.handle_frame(&frame);
+        }
+    }
+}
+
+async fn run_websocket(url: &str, input: &mut HeadingInput) -> Result<(), String> {
+    let (mut stream, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|err| format!("Failed to connect to {url}: {err}"))?;
+    info!("Vehicle pose: Connected to {url}");
+
+    while let Some(message) = stream.next().await {
+        match message.map_err(|err| format!("Connection to {url} lost: {err}"))? {
+            tungstenite::Message::Text(text) => input.handle_json(&text),
+            tungstenite::Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    Err(format!("Connection to {url} closed"))
+}
+
+// Keep the vehicle heading updated on background, reconnecting whenever the source goes away
+pub fn spawn(source: PoseSource) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut input = HeadingInput::default();
+        loop {
+            let result = match &source {
+                PoseSource::UdpIn(host) => run_udp(host, &mut input).await,
+                PoseSource::Websocket(url) => run_websocket(url, &mut input).await,
+            };
+            if let Err(err) = result {
+                warn!("Vehicle pose: {err}");
+            }
+            trace!("Vehicle pose: Retrying {source} in {RECONNECT_INTERVAL:?}");
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_parsing() {
+        assert_eq!(
+            "udpin:0.0.0.0:14550".parse::<PoseSource>(),
+            Ok(PoseSource::UdpIn("0.0.0.0:14550".to_string()))
+        );
+        assert_eq!(
+            "ws://blueos.local:6040/ws/mavlink?filter=ATTITUDE".parse::<PoseSource>(),
+            Ok(PoseSource::Websocket(
+                "ws://blueos.local:6040/ws/mavlink?filter=ATTITUDE".to_string()
+            ))
+        );
+        assert!("wss://blueos.local/ws/mavlink"
+            .parse::<PoseSource>()
+            .unwrap_err()
+            .contains("ws://"));
+        assert!("udpout:127.0.0.1:14550".parse::<PoseSource>().is_err());
+        assert!("udpin:0.0.0.0".parse::<PoseSource>().is_err());
+    }
+
+    #[test]
+    fn test_heading_interpolation() {
+        let start = Instant::now();
+        let mut history = HeadingHistory::default();
+        assert_eq!(history.heading_at(start), None);
+
+        history.push(start, 350.0);
+        history.push(start + Duration::from_millis(100), 10.0);
+
+        let heading = history
+            .heading_at(start + Duration::from_millis(75))
+            .unwrap();
+        assert!((heading - 5.0).abs() < 0.01, "{heading}");
+        assert_eq!(
+            history.heading_at(start + Duration::from_millis(500)),
+            Some(10.0)
+        );
+        assert_eq!(history.heading_at(start + Duration::from_secs(2)), None);
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web::{Data, Query},
+    HttpMessage, HttpRequest,
+};
+use paperclip::v2::models::{DefaultApiRaw, SecurityScheme};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{
+    devices::{Ping1DRequest, Ping360Request, PingCommonRequest, PingRequest, Tsr1000Request},
+    manager::{ModifyDeviceCommand, Request},
+};
+use crate::server::protocols::v1::errors::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    /// Lists devices, reads their data and subscribes to messages
+    ReadOnly,
+    /// Everything, including creating, configuring, updating and deleting devices
+    Operator,
+}
+
+impl Role {
+    pub fn allows(&self, request: &Request) -> bool {
+        *self >= required_role(request)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Only used to identify the token on logs
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+// Without tokens the API stays open, as every client is an operator
+#[derive(Debug, Clone, Default)]
+pub struct ApiTokens {
+    tokens: Vec<ApiToken>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+// Compares every byte, so the time taken doesn't tell how much of a token was right
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+impl ApiTokens {
+    pub fn new(tokens: Vec<ApiToken>) -> Self {
+        Self { tokens }
+    }
+
+    // A JSON list as [{"name": "cockpit", "token": "...", "role": "ReadOnly"}]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read API tokens from {path}: {err}"))?;
+        let tokens: Vec<ApiToken> = serde_json::from_str(&content)
+            .map_err(|err| format!("Failed to parse API tokens from {path}: {err}"))?;
+        if let Some(token) = tokens.iter().find(|token| token.token.is_empty()) {
+            return Err(format!("API token {} is empty", token.name));
+        }
+        Ok(Self::new(tokens))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    pub fn role(&self, token: Option<&str>) -> Result<Role, Error> {
+        if !self.is_enabled() {
+            return Ok(Role::Operator);
+        }
+        let token = token.ok_or_else(|| Error::Unauthorized("Missing API token".to_string()))?;
+        self.tokens
+            .iter()
+            .find(|candidate| same_token(&candidate.token, token))
+            .map(|candidate| candidate.role)
+            .ok_or_else(|| Error::Unauthorized("Invalid API token".to_string()))
+    }
+}
+
+// Browsers can't set headers on websocket upgrades, so the token is also accepted as ?token=
+fn request_token(request: &HttpRequest) -> Option<String> {
+    if let Some(value) = request.headers().get(header::AUTHORIZATION) {
+        return value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+    }
+    Query::<TokenQuery>::from_query(request.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().token)
+}
+
+// The API lives under /v1 and /v2, and v1 routes are also at the root
+fn is_protected(path: &str) -> bool {
+    if path.starts_with("/v2/") {
+        return true;
+    }
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    path.starts_with("/device_manager/") || path == "/ws" || path == "/metrics"
+}
+
+// Authenticates API requests, leaving the caller role on the request extensions
+pub async fn authenticate(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // Routes match the decoded path, so /%64evice_manager is checked as /device_manager
+    if is_protected(request.match_info().path()) {
+        let role = match request.app_data::<Data<ApiTokens>>() {
+            Some(tokens) => tokens.role(request_token(request.request()).as_deref())?,
+            None => Role::Operator,
+        };
+        request.extensions_mut().insert(role);
+    }
+    next.call(request).await
+}
+
+// Role of an authenticated request, refused if it never went through the middleware
+pub fn role(request: &HttpRequest) -> Result<Role, Error> {
+    request
+        .extensions()
+        .get::<Role>()
+        .copied()
+        .ok_or_else(|| Error::Unauthorized("Request was not authenticated".to_string()))
+}
+
+pub fn authorize(request: &HttpRequest, manager_request: &Request) -> Result<(), Error> {
+    let role = role(request)?;
+    if role.allows(manager_request) {
+        return Ok(());
+    }
+    Err(Error::Forbidden(format!(
+        "{role:?} token is not allowed to send this request"
+    )))
+}
+
+// Anything not known to be a read is left to operators, so new requests are safe by default
+pub fn required_role(request: &Request) -> Role {
+    match request {
+        Request::List
+        | Request::Info(_)
+        | Request::Search
+        | Request::Health(_)
+        | Request::Metrics => Role::ReadOnly,
+        Request::ModifyDevice(modify) => match modify.modify {
+            ModifyDeviceCommand::GetPing360Config
+            | ModifyDeviceCommand::GetPing1DConfig
+            | ModifyDeviceCommand::GetTsr1000Config => Role::ReadOnly,
+            _ => Role::Operator,
+        },
+        Request::Ping(device_request) => match &device_request.device_request {
+            PingRequest::Ping1D(request) => match request {
+                Ping1DRequest::DeviceID
+                | Ping1DRequest::ModeAuto
+                | Ping1DRequest::Distance
+                | Ping1DRequest::Profile
+                | Ping1DRequest::SpeedOfSound
+                | Ping1DRequest::Voltage5
+                | Ping1DRequest::DeviceId
+                | Ping1DRequest::FirmwareVersion
+                | Ping1DRequest::Range
+                | Ping1DRequest::TransmitDuration
+                | Ping1DRequest::PingInterval
+                | Ping1DRequest::ProcessorTemperature
+                | Ping1DRequest::PcbTemperature
+                | Ping1DRequest::GeneralInfo
+                | Ping1DRequest::GainSetting
+                | Ping1DRequest::PingEnable
+                | Ping1DRequest::DistanceSimple => Role::ReadOnly,
+                _ => Role::Operator,
+            },
+            PingRequest::Tsr1000(request) => match request {
+                Tsr1000Request::DeviceID
+                | Tsr1000Request::ModeAuto
+                | Tsr1000Request::Distance
+                | Tsr1000Request::Profile
+                | Tsr1000Request::SpeedOfSound
+                | Tsr1000Request::Voltage5
+                | Tsr1000Request::DeviceId
+                | Tsr1000Request::FirmwareVersion
+                | Tsr1000Request::Range
+                | Tsr1000Request::TransmitDuration
+                | Tsr1000Request::PingInterval
+                | Tsr1000Request::ProcessorTemperature
+                | Tsr1000Request::PcbTemperature
+                | Tsr1000Request::GeneralInfo
+                | Tsr1000Request::GainSetting
+                | Tsr1000Request::PingEnable
+                | Tsr1000Request::DistanceSimple => Role::ReadOnly,
+                _ => Role::Operator,
+            },
+            PingRequest::Ping360(Ping360Request::DeviceData) => Role::ReadOnly,
+            PingRequest::Common(
+                PingCommonRequest::DeviceInformation | PingCommonRequest::ProtocolVersion,
+            ) => Role::ReadOnly,
+            _ => Role::Operator,
+        },
+        _ => Role::Operator,
+    }
+}
+
+// Spec served at /api/spec, with the tokens as the two ways clients can send them
+pub fn api_spec() -> DefaultApiRaw {
+    let description = "API token, required when the server is started with tokens. \
+        ReadOnly tokens can list devices and read their data, Operator tokens can also change them.";
+    let mut spec = DefaultApiRaw::default();
+    spec.security_definitions = BTreeMap::from([
+        (
+            "bearer".to_string(),
+            SecurityScheme {
+                type_: "apiKey".to_string(),
+                name: Some(header::AUTHORIZATION.to_string()),
+                in_: Some("header".to_string()),
+                description: Some(format!("{description} Sent as: Bearer <token>")),
+                ..Default::default()
+            },
+        ),
+        (
+            "token".to_string(),
+            SecurityScheme {
+                type_: "apiKey".to_string(),
+                name: Some("token".to_string()),
+                in_: Some("query".to_string()),
+                description: Some(description.to_string()),
+                ..Default::default()
+            },
+        ),
+    ]);
+    spec.security = vec![
+        BTreeMap::from([("bearer".to_string(), Vec::new())]),
+        BTreeMap::from([("token".to_string(), Vec::new())]),
+    ];
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::device::manager::{DeviceRequestStruct, ModifyDevice, UuidWrapper};
+
+    fn tokens() -> ApiTokens {
+        ApiTokens::new(vec![
+            ApiToken {
+                name: "viewer".to_string(),
+                token: "read".to_string(),
+                role: Role::ReadOnly,
+            },
+            ApiToken {
+                name: "pilot".to_string(),
+                token: "operate".to_string(),
+                role: Role::Operator,
+            },
+        ])
+    }
+
+    #[test]
+    fn test_token_roles() {
+        let tokens = tokens();
+        assert_eq!(tokens.role(Some("read")).unwrap(), Role::ReadOnly);
+        assert_eq!(tokens.role(Some("operate")).unwrap(), Role::Operator);
+        assert!(tokens.role(Some("reader")).is_err());
+        assert!(tokens.role(None).is_err());
+        assert_eq!(ApiTokens::default().role(None).unwrap(), Role::Operator);
+
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer operate"))
+            .to_http_request();
+        assert_eq!(request_token(&request).as_deref(), Some("operate"));
+        let request = TestRequest::with_uri("/ws?device_number=1&token=read").to_http_request();
+        assert_eq!(request_token(&request).as_deref(), Some("read"));
+
+        assert!(is_protected("/v1/device_manager/List"));
+        assert!(is_protected("/device_manager/request"));
+        assert!(is_protected("/ws"));
+        assert!(is_protected("/v2/devices"));
+        assert!(!is_protected("/docs"));
+        assert!(!is_protected("/register_service"));
+
+        assert!(role(&TestRequest::default().to_http_request()).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_encoded_paths_are_authenticated() {
+        use actix_web::{http::StatusCode, middleware, test, App};
+        use paperclip::actix::{web, OpenApiExt};
+
+        use crate::device::manager::ManagerActorHandler;
+        use crate::server::protocols::v1;
+
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(ManagerActorHandler { sender }))
+                .app_data(Data::new(tokens()))
+                .wrap(middleware::from_fn(authenticate))
+                .wrap_api()
+                .service(web::scope("/v1").configure(v1::rest::register_services))
+                .service(v1::websocket::websocket)
+                .service(v1::metrics::metrics)
+                .service(web::scope("").configure(v1::rest::register_services))
+                .build(),
+        )
+        .await;
+
+        for path in [
+            "/v1/%64evice_manager/List",
+            "/%64evice_manager/List",
+            "/%77s",
+            "/%6detrics",
+        ] {
+            let request = test::TestRequest::get().uri(path).to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_required_role() {
+        let uuid = Uuid::from_u128(1);
+        let ping = |device_request| {
+            Request::Ping(DeviceRequestStruct {
+                uuid,
+                device_request,
+            })
+        };
+
+        assert!(Role::ReadOnly.allows(&Request::List));
+        assert!(Role::ReadOnly.allows(&Request::Health(UuidWrapper { uuid })));
+        assert!(Role::ReadOnly.allows(&ping(PingRequest::Ping1D(Ping1DRequest::Distance))));
+        assert!(Role::ReadOnly.allows(&Request::ModifyDevice(ModifyDevice {
+            uuid,
+            modify: ModifyDeviceCommand::GetPing360Config,
+        })));
+
+        assert!(!Role::ReadOnly.allows(&Request::Delete(UuidWrapper { uuid })));
+        assert!(!Role::ReadOnly.allows(&ping(PingRequest::Ping1D(Ping1DRequest::GotoBootloader))));
+        assert!(!Role::ReadOnly.allows(&Request::ModifyDevice(ModifyDevice {
+            uuid,
+            modify: ModifyDeviceCommand::SetIp("192.168.2.10".parse().unwrap()),
+        })));
+        assert!(Role::Operator.allows(&Request::Delete(UuidWrapper { uuid })));
+    }
+
+    #[test]
+    fn test_echosounder_requests_default_to_operator() {
+        let uuid = Uuid::from_u128(1);
+        let ping = |device_request| {
+            Request::Ping(DeviceRequestStruct {
+                uuid,
+                device_request,
+            })
+        };
+
+        assert!(Role::ReadOnly.allows(&ping(PingRequest::Tsr1000(Tsr1000Request::Profile))));
+        assert!(Role::ReadOnly.allows(&ping(PingRequest::Ping1D(Ping1DRequest::GeneralInfo))));
+
+        // Only the reads are listed, anything else needs an operator
+        for request in [
+            PingRequest::Ping1D(Ping1DRequest::ContinuousStart(
+                bluerobotics_ping::ping1d::ContinuousStartStruct { id: 1300 },
+            )),
+            PingRequest::Ping1D(Ping1DRequest::SetPingEnable(
+                bluerobotics_ping::ping1d::SetPingEnableStruct { ping_enabled: 0 },
+            )),
+            PingRequest::Tsr1000(Tsr1000Request::ContinuousStop(
+                bluerobotics_ping::tsr1000::ContinuousStopStruct { id: 1300 },
+            )),
+            PingRequest::Tsr1000(Tsr1000Request::GotoBootloader),
+        ] {
+            let request = ping(request);
+            assert!(!Role::ReadOnly.allows(&request), "{request:?}");
+            assert!(Role::Operator.allows(&request), "{request:?}");
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use crate::device::manager::ManagerActorHandler;
+use crate::device::manager::{ManagerActorHandler, Request};
 
-use super::protocols;
+use super::{auth, protocols};
 use actix_cors::Cors;
 use actix_web::{middleware, web::Data, App, HttpServer};
-use tracing::info;
+use tracing::{error, info, warn};
 
 use paperclip::actix::{
     web::{self, Scope},
@@ -14,30 +14,96 @@ fn add_v1_paths(scope: Scope) -> Scope {
     scope.configure(protocols::v1::rest::register_services)
 }
 
-pub async fn run(server_address: &str, handler: ManagerActorHandler) -> std::io::Result<()> {
+// Leaves the devices idle and tells websocket clients the server is going away, before the process exits
+pub async fn shutdown(handler: &ManagerActorHandler) {
+    match handler.send(Request::Shutdown).await {
+        Ok(_) => info!("ServerManager: Devices were shut down"),
+        Err(err) => error!("ServerManager: Failed to shut down devices: {err:?}"),
+    }
+    protocols::v1::websocket::close_clients();
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => error!("ServerManager: Failed to listen for SIGTERM: {err:?}"),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        error!("ServerManager: Failed to listen for SIGINT: {err:?}");
+        std::future::pending::<()>().await;
+    }
+}
+
+pub async fn run(
+    server_address: &str,
+    handler: ManagerActorHandler,
+    tokens: auth::ApiTokens,
+    tls: Option<rustls::ServerConfig>,
+) -> std::io::Result<()> {
     let server_address = server_address.to_string();
     info!("ServerManager: Service starting");
+    if !tokens.is_enabled() {
+        warn!(
+            "ServerManager: No API tokens configured, every client is allowed to operate devices"
+        );
+    }
 
+    let shutdown_handler = handler.clone();
     let server = HttpServer::new(move || {
         let cors = Cors::permissive();
 
         let v1 = add_v1_paths(web::scope("/v1"));
+        let v2 = web::scope("/v2").configure(protocols::v2::rest::register_services);
         let default = add_v1_paths(web::scope(""));
 
         App::new()
             .app_data(Data::new(handler.clone()))
+            .app_data(Data::new(tokens.clone()))
+            .wrap(middleware::from_fn(auth::authenticate))
             .wrap(cors)
             .wrap(middleware::Logger::default())
-            .wrap_api()
+            .wrap_api_with_spec(auth::api_spec())
             .with_json_spec_at("/api/spec")
             .with_swagger_ui_at("/docs")
             .service(v1)
+            .service(v2)
             .service(protocols::v1::rest::server_metadata)
             .service(protocols::v1::websocket::websocket)
+            .service(protocols::v1::metrics::metrics)
             .service(default)
             .build()
     });
 
-    info!("ServerManager: HTTP server running at http://{server_address}");
-    server.bind(server_address)?.run().await
+    // Signals are handled here, so devices are shut down before the server stops
+    let server = server.disable_signals();
+    let server = match tls {
+        Some(tls) => {
+            info!("ServerManager: HTTPS server running at https://{server_address}");
+            server.bind_rustls_0_23(server_address, tls)?.run()
+        }
+        None => {
+            info!("ServerManager: HTTP server running at http://{server_address}");
+            server.bind(server_address)?.run()
+        }
+    };
+
+    let server_handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        info!("ServerManager: Shutdown requested");
+        shutdown(&shutdown_handler).await;
+        server_handle.stop(true).await;
+    });
+
+    server.await
 }
@@ -1,5 +1,7 @@
+pub mod auth;
 pub mod manager;
 pub mod protocols;
+pub mod tls;
 
 // The Server module consists of a manager and all available layers that provide access to internal services.
 //
@@ -8,6 +10,10 @@ pub mod protocols;
 // This allows the Manager to receive and process requests from RestAPI and WebSocket methods.
 // The requests are forwarded to the DeviceManager using the server's AppData, which holds a clone of the DeviceManager's Handler and will provide the responses.
 //
+// TLS:
+// With --tls-cert and --tls-key, or --tls-self-signed, the server is only available over HTTPS and WSS.
+// A self-signed certificate is generated on the first run when the files don't exist yet.
+//
 // Front-end:
 // The frontend provides access to REST API documentation through {address}/docs with a Swagger interface and the API specifications.
 //
@@ -15,11 +21,32 @@ pub mod protocols;
 // The REST API will have a default route and versioned routes.
 // To keep the application stable through updates, users can use {address}/v{x}/route.
 //
+// RestAPI v2:
+// Resources under {address}/v2, as /devices, /devices/{id}, /devices/{id}/config and /devices/{id}/continuous.
+// Errors are answered with their HTTP status (404, 409, 400, 503..) and a body as {"error": "DeviceNotExist", "message": "..", "details": ..}.
+//
 // WebSocket:
 // WebSocket is provided via the {address}/ws route.
 // Users can use the following queries:
-//     ?filter="some_desired_string_to_use_regex"
 //     ?device-number="00000000-0000-0000-b9c0-f5752d453eb3" // The UUID provided by the source of the device created
+//     ?kinds=DeviceMessage,Error // DeviceMessage, ManagerEvent, DeviceEvent and Error
+//     ?message_ids=1300,2300 // Ping protocol message ids, only applied to device messages
+//     ?encoding=msgpack // json (default), msgpack or cbor, binary encodings carry sonar samples as byte strings
 // Otherwise, if they are not defined, the WebSocket channel will receive all available messages.
+// The subscription can be changed later by sending {"module": "Websocket", "command": "Subscribe", "payload": {..}},
+// with Unsubscribe, SetSubscription and GetSubscription as the other commands, all answered with the current subscription.
 // All operations made through REST API and WebSocket routes will be broadcast to all clients subscribed to device-number=null (default),
 // except for errors, which are forwarded directly to the requester.
+// Requests may carry a "request_id", echoed on a direct reply {"request_id": .., "answer": ..} or {"request_id": .., "error": ..},
+// and "broadcast": false to skip sending the result to the other clients.
+// Device lifecycle changes are pushed as {"DeviceEvent": {"Registered": ..}}, with Discovered, Deleted, StatusChanged,
+// PropertiesUpdated, ConfigChanged, ContinuousModeStarted and ContinuousModeStopped as the other events.
+// Device messages streamed on continuous mode carry "timestamp": {"utc_us": .., "monotonic_us": ..}, taken when their frame was read
+// from the device, monotonic_us counts from the server start and is shared by every device.
+//
+// Authentication:
+// When started with --auth-tokens, the API routes, /ws and /metrics require a token, sent as "Authorization: Bearer <token>" or ?token=<token>.
+// ReadOnly tokens can list devices, read their data and subscribe to messages, Operator tokens can also change them.
+//
+// Metrics:
+// Prometheus text format is provided via the {address}/metrics route, covering devices, websocket clients and the manager queue.
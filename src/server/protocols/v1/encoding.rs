@@ -0,0 +1,155 @@
+use paperclip::actix::Apiv2Schema;
+use serde::{
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Serialize, Serializer,
+};
+use serde_json::Value;
+use tracing::error;
+
+// Sonar samples, as (message, field), go out as binary strings instead of arrays of integers
+const SAMPLE_FIELDS: [(&str, &str); 3] = [
+    ("DeviceData", "data"),
+    ("AutoDeviceData", "data"),
+    ("Profile", "profile_data"),
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Apiv2Schema)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    Cbor,
+}
+
+// Serializes a json value as is, except for the sample fields
+struct Encoded<'a> {
+    value: &'a Value,
+    // Key of the object holding this value, and the key of this value
+    parent: Option<&'a str>,
+    key: Option<&'a str>,
+}
+
+impl<'a> Encoded<'a> {
+    fn new(value: &'a Value) -> Self {
+        Self {
+            value,
+            parent: None,
+            key: None,
+        }
+    }
+
+    fn samples(&self, values: &[Value]) -> Option<Vec<u8>> {
+        let field = (self.parent?, self.key?);
+        if !SAMPLE_FIELDS.contains(&field) {
+            return None;
+        }
+        values
+            .iter()
+            .map(|value| value.as_u64().and_then(|value| u8::try_from(value).ok()))
+            .collect()
+    }
+}
+
+impl Serialize for Encoded<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Array(values) => {
+                if let Some(samples) = self.samples(values) {
+                    return serializer.serialize_bytes(&samples);
+                }
+                let mut sequence = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    sequence.serialize_element(&Encoded::new(value))?;
+                }
+                sequence.end()
+            }
+            Value::Object(map) => {
+                let mut entries = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map {
+                    entries.serialize_entry(
+                        key,
+                        &Encoded {
+                            value,
+                            parent: self.key,
+                            key: Some(key),
+                        },
+                    )?;
+                }
+                entries.end()
+            }
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+pub fn to_message_pack(value: &Value) -> Vec<u8> {
+    rmp_serde::to_vec(&Encoded::new(value)).unwrap_or_else(|err| {
+        error!("Failed to encode MessagePack message: {err}");
+        Vec::new()
+    })
+}
+
+pub fn to_cbor(value: &Value) -> Vec<u8> {
+    let mut output = Vec::new();
+    if let Err(err) = ciborium::into_writer(&Encoded::new(value), &mut output) {
+        error!("Failed to encode CBOR message: {err}");
+        output.clear();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_message_pack() {
+        assert_eq!(
+            to_message_pack(&json!({"a": [1, -2, 300], "b": null, "c": 1.5})),
+            [
+                0x83, 0xa1, b'a', 0x93, 0x01, 0xfe, 0xcd, 0x01, 0x2c, 0xa1, b'b', 0xc0, 0xa1, b'c',
+                0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0
+            ]
+        );
+
+        let samples: Vec<u8> = (0..200).collect();
+        let encoded = to_message_pack(&json!({ "DeviceData": { "data": samples } }));
+        assert_eq!(
+            &encoded[..20],
+            &[
+                0x81, 0xaa, b'D', b'e', b'v', b'i', b'c', b'e', b'D', b'a', b't', b'a', 0x81, 0xa4,
+                b'd', b'a', b't', b'a', 0xc4, 200
+            ]
+        );
+        assert_eq!(&encoded[20..], samples.as_slice());
+    }
+
+    #[test]
+    fn test_cbor() {
+        assert_eq!(
+            to_cbor(&json!({"a": [1, -2, 300], "b": true})),
+            [0xa2, 0x61, b'a', 0x83, 0x01, 0x21, 0x19, 0x01, 0x2c, 0x61, b'b', 0xf5]
+        );
+
+        let samples: Vec<u8> = (0..200).collect();
+        let encoded = to_cbor(&json!({ "Profile": { "profile_data": samples } }));
+        assert_eq!(
+            &encoded[..25],
+            b"\xa1\x67Profile\xa1\x6cprofile_data\x58\xc8"
+        );
+        assert_eq!(&encoded[25..], samples.as_slice());
+    }
+
+    #[test]
+    fn test_only_sample_fields_are_binary() {
+        let values: Vec<u8> = (0..20).collect();
+        let encoded = to_cbor(&json!({ "DeviceData": { "reserved": values } }));
+        // Array of 20 items, not a byte string
+        assert_eq!(encoded[22], 0x94);
+        assert_eq!(encoded.len(), 23 + 20);
+    }
+}
@@ -7,6 +7,10 @@ use validator::ValidationErrors;
 #[api_v2_errors(
     code = 400,
     description = "Bad Request: The client's request contains invalid or malformed data.",
+    code = 401,
+    description = "Unauthorized: The API token is missing or unknown.",
+    code = 403,
+    description = "Forbidden: The API token role doesn't allow this request.",
     code = 500,
     description = "Internal Server Error: An unexpected server error has occurred."
 )]
@@ -14,6 +18,10 @@ use validator::ValidationErrors;
 pub enum Error {
     #[error("Bad Request: {0}")]
     BadRequest(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
     #[error("Internal Server Error: {0}")]
     Internal(String),
 }
@@ -22,6 +30,8 @@ impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,297 @@
+use std::fmt::Write;
+
+use actix_web::HttpRequest;
+use paperclip::actix::{
+    api_v2_operation, get,
+    web::{self, HttpResponse},
+};
+
+use crate::{
+    device::manager::{
+        device_health::DeviceHealth, Answer, DeviceStatus, ManagerActorHandler, Request,
+    },
+    server::protocols::v1::{errors::Error, websocket},
+};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Prometheus text format exporter, scraped by the vehicle monitoring
+#[api_v2_operation(skip)]
+#[get("metrics")]
+pub async fn metrics(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<HttpResponse, actix_web::Error> {
+    crate::server::auth::authorize(&req, &Request::Metrics)?;
+    // Read before sending, otherwise our own request would be accounted
+    let queue_depth = manager_handler.queue_depth();
+
+    let devices = match manager_handler
+        .send(Request::Metrics)
+        .await
+        .map_err(Error::from)?
+    {
+        Answer::Metrics(devices) => devices,
+        answer => {
+            return Err(Error::Internal(format!("Unexpected answer: {answer:?}")).into());
+        }
+    };
+
+    let body = render(
+        &devices,
+        queue_depth,
+        websocket::client_count(),
+        websocket::dropped_messages(),
+    );
+    Ok(HttpResponse::Ok().content_type(CONTENT_TYPE).body(body))
+}
+
+fn family(
+    output: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, f64)>,
+) {
+    let _ = writeln!(output, "# HELP {name} {help}");
+    let _ = writeln!(output, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(output, "{name}{labels} {value}");
+    }
+}
+
+fn device_labels(device: &DeviceHealth) -> String {
+    format!(
+        "device_id=\"{}\",device_type=\"{:?}\"",
+        device.device_id, device.device_type
+    )
+}
+
+// Samples of the devices with link statistics, i.e. the running ones
+fn device_samples(
+    devices: &[DeviceHealth],
+    value: impl Fn(&crate::device::health::LinkStatistics) -> f64,
+) -> Vec<(String, f64)> {
+    devices
+        .iter()
+        .filter_map(|device| {
+            let link = device.link.as_ref()?;
+            Some((format!("{{{}}}", device_labels(device)), value(link)))
+        })
+        .collect()
+}
+
+pub fn render(
+    devices: &[DeviceHealth],
+    queue_depth: usize,
+    websocket_clients: usize,
+    websocket_dropped: u64,
+) -> String {
+    let mut output = String::new();
+
+    family(
+        &mut output,
+        "ping_viewer_devices",
+        "gauge",
+        "Number of devices per status.",
+        [
+            DeviceStatus::Available,
+            DeviceStatus::Running,
+            DeviceStatus::Stopped,
+            DeviceStatus::ContinuousMode,
+        ]
+        .into_iter()
+        .map(|status| {
+            let count = devices
+                .iter()
+                .filter(|device| device.status == status)
+                .count();
+            (format!("{{status=\"{status:?}\"}}"), count as f64)
+        }),
+    );
+
+    let mut rates = Vec::new();
+    for device in devices {
+        let labels = device_labels(device);
+        for message in device.link.iter().flat_map(|link| link.messages.iter()) {
+            rates.push((
+                format!("{{{labels},message_id=\"{}\"}}", message.message_id),
+                message.rate_hz as f64,
+            ));
+        }
+    }
+    family(
+        &mut output,
+        "ping_viewer_device_message_rate_hz",
+        "gauge",
+        "Incoming messages per second, per message id.",
+        rates,
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_device_messages_total",
+        "counter",
+        "Incoming messages since the device was started.",
+        device_samples(devices, |link| link.total_messages as f64),
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_device_parser_errors_total",
+        "counter",
+        "Incoming bytes that could not be parsed.",
+        device_samples(devices, |link| link.parser_errors as f64),
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_device_checksum_errors_total",
+        "counter",
+        "Incoming messages with a wrong checksum.",
+        device_samples(devices, |link| link.checksum_errors as f64),
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_device_requests_total",
+        "counter",
+        "Requests sent to the device.",
+        device_samples(devices, |link| link.requests as f64),
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_device_request_errors_total",
+        "counter",
+        "Requests answered with an error.",
+        device_samples(devices, |link| link.request_errors as f64),
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_device_request_timeouts_total",
+        "counter",
+        "Requests left without answer.",
+        device_samples(devices, |link| link.request_timeouts as f64),
+    );
+
+    let mut latencies = Vec::new();
+    for device in devices {
+        let Some(latency) = device.link.as_ref().and_then(|link| link.latency.as_ref()) else {
+            continue;
+        };
+        let labels = device_labels(device);
+        for (stat, milliseconds) in [
+            ("last", latency.last_ms),
+            ("average", latency.average_ms),
+            ("max", latency.max_ms),
+        ] {
+            latencies.push((
+                format!("{{{labels},stat=\"{stat}\"}}"),
+                milliseconds as f64 / 1000.0,
+            ));
+        }
+    }
+    family(
+        &mut output,
+        "ping_viewer_device_request_latency_seconds",
+        "gauge",
+        "Latency of the answered requests, last, smoothed average and max.",
+        latencies,
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_manager_queue_depth",
+        "gauge",
+        "Requests waiting for the device manager.",
+        [(String::new(), queue_depth as f64)],
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_websocket_clients",
+        "gauge",
+        "Connected websocket clients.",
+        [(String::new(), websocket_clients as f64)],
+    );
+
+    family(
+        &mut output,
+        "ping_viewer_websocket_dropped_messages_total",
+        "counter",
+        "Messages not delivered because the client mailbox was full or closed.",
+        [(String::new(), websocket_dropped as f64)],
+    );
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::device::{
+        health::{LatencyStatistics, LinkStatistics, MessageStatistics, SensorReadings},
+        manager::DeviceSelection,
+    };
+
+    #[test]
+    fn test_render() {
+        let devices = vec![
+            DeviceHealth {
+                device_id: Uuid::from_u128(1),
+                device_type: DeviceSelection::Ping1D,
+                status: DeviceStatus::ContinuousMode,
+                link: Some(LinkStatistics {
+                    messages: vec![MessageStatistics {
+                        message_id: 1300,
+                        count: 120,
+                        rate_hz: 10.0,
+                    }],
+                    total_messages: 120,
+                    parser_errors: 0,
+                    checksum_errors: 2,
+                    requests: 4,
+                    request_errors: 0,
+                    request_timeouts: 1,
+                    latency: Some(LatencyStatistics {
+                        last_ms: 20.0,
+                        average_ms: 25.0,
+                        max_ms: 50.0,
+                    }),
+                    last_seen: None,
+                    seconds_since_last_seen: None,
+                    sensors: SensorReadings::default(),
+                }),
+            },
+            DeviceHealth {
+                device_id: Uuid::from_u128(2),
+                device_type: DeviceSelection::Ping360,
+                status: DeviceStatus::Stopped,
+                link: None,
+            },
+        ];
+
+        let output = render(&devices, 3, 2, 7);
+        let labels = "device_id=\"00000000-0000-0000-0000-000000000001\",device_type=\"Ping1D\"";
+
+        for line in [
+            "ping_viewer_devices{status=\"ContinuousMode\"} 1".to_string(),
+            "ping_viewer_devices{status=\"Stopped\"} 1".to_string(),
+            "ping_viewer_devices{status=\"Running\"} 0".to_string(),
+            format!("ping_viewer_device_message_rate_hz{{{labels},message_id=\"1300\"}} 10"),
+            format!("ping_viewer_device_checksum_errors_total{{{labels}}} 2"),
+            format!("ping_viewer_device_request_latency_seconds{{{labels},stat=\"max\"}} 0.05"),
+            "ping_viewer_manager_queue_depth 3".to_string(),
+            "ping_viewer_websocket_clients 2".to_string(),
+            "ping_viewer_websocket_dropped_messages_total 7".to_string(),
+            "# TYPE ping_viewer_device_requests_total counter".to_string(),
+        ] {
+            assert!(output.lines().any(|candidate| candidate == line), "{line}");
+        }
+        assert!(!output.contains("00000000-0000-0000-0000-000000000002"));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod encoding;
 pub mod errors;
+pub mod metrics;
 pub mod rest;
+pub mod subscription;
 pub mod websocket;
@@ -1,6 +1,6 @@
 use crate::device::manager::{ManagerActorHandler, Request, UuidWrapper};
 use crate::server::protocols::v1::errors::Error;
-use actix_web::Responder;
+use actix_web::{HttpRequest, Responder};
 use mime_guess::from_path;
 use paperclip::actix::{
     api_v2_operation, get, post,
@@ -61,8 +61,18 @@ pub fn register_services(cfg: &mut web::ServiceConfig) {
     cfg.service(index)
         .service(post_request)
         .service(device_manager_get)
+        // Registered before device_manager/{device}/{selection}, which would match it otherwise
+        .service(
+            web::resource("device_manager/{device}/firmware")
+                .app_data(actix_web::web::PayloadConfig::new(
+                    crate::device::manager::firmware::FIRMWARE_MAX_SIZE,
+                ))
+                .route(web::post().to(device_manager_firmware_post)),
+        )
         .service(device_manager_post)
         .service(post_create)
+        .service(post_replay)
+        .service(device_manager_health_get)
         .service(device_manager_device_get)
         .service(device_manager_device_ping1d_get)
         .service(device_manager_device_ping360_get)
@@ -74,9 +84,12 @@ pub fn register_services(cfg: &mut web::ServiceConfig) {
 }
 
 async fn send_request_and_broadcast(
+    req: &HttpRequest,
     manager_handler: &web::Data<ManagerActorHandler>,
     request: Request,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
+    crate::server::auth::authorize(req, &request)?;
+
     let request_has_id = match &request {
         Request::ModifyDevice(modify) => Some(modify.uuid),
         Request::Ping(device_request) => Some(device_request.uuid),
@@ -84,23 +97,32 @@ async fn send_request_and_broadcast(
         Request::Info(uuid_wrapper) => Some(uuid_wrapper.uuid),
         Request::EnableContinuousMode(uuid_wrapper) => Some(uuid_wrapper.uuid),
         Request::DisableContinuousMode(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::StartRecording(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::StopRecording(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::Replay(replay_control) => Some(replay_control.uuid),
+        Request::Health(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::FirmwareUpdate(firmware) => Some(firmware.uuid),
         _ => None,
     };
 
     let answer = manager_handler.send(request).await?;
-    crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), request_has_id);
+    crate::server::protocols::v1::websocket::send_to_websockets(
+        json!(answer),
+        crate::server::protocols::v1::subscription::Topic::from_answer(&answer, request_has_id),
+    );
     Ok(Json(answer))
 }
 
 #[api_v2_operation(tags("Device Manager"))]
 #[post("device_manager/request")]
 async fn post_request(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     json: web::Json<crate::device::manager::Request>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
     let request = json.into_inner();
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
@@ -116,11 +138,14 @@ pub enum DeviceManagerPostOptionsV1 {
     Info,
     EnableContinuousMode,
     DisableContinuousMode,
+    StartRecording,
+    StopRecording,
 }
 
 #[api_v2_operation(tags("Device Manager"))]
 #[get("device_manager/{selection}")]
 async fn device_manager_get(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     selection: web::Path<DeviceManagerGetOptionsV1>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -130,12 +155,13 @@ async fn device_manager_get(
         DeviceManagerGetOptionsV1::Search => crate::device::manager::Request::Search,
     };
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[api_v2_operation(tags("Device Manager"))]
 #[post("device_manager/create")]
 async fn post_create(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Json<crate::device::manager::CreateStruct>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -143,12 +169,27 @@ async fn post_create(
 
     let request = crate::device::manager::Request::Create(create_struct);
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
+}
+
+#[api_v2_operation(tags("Device Manager"))]
+#[post("device_manager/replay")]
+async fn post_replay(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    info: web::Json<crate::device::manager::replay::ReplayControl>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let replay_control = info.into_inner();
+
+    let request = crate::device::manager::Request::Replay(replay_control);
+
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[api_v2_operation(tags("Device Manager : Device"))]
 #[post("device_manager/{device}/{selection}")]
 async fn device_manager_post(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Path<(Uuid, DeviceManagerPostOptionsV1)>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -169,14 +210,57 @@ async fn device_manager_post(
         DeviceManagerPostOptionsV1::DisableContinuousMode => {
             crate::device::manager::Request::DisableContinuousMode(UuidWrapper { uuid })
         }
+        DeviceManagerPostOptionsV1::StartRecording => {
+            crate::device::manager::Request::StartRecording(UuidWrapper { uuid })
+        }
+        DeviceManagerPostOptionsV1::StopRecording => {
+            crate::device::manager::Request::StopRecording(UuidWrapper { uuid })
+        }
     };
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
+}
+
+#[api_v2_operation(tags("Device Manager : Device"))]
+#[get("device_manager/{device}/health")]
+async fn device_manager_health_get(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    device: web::Path<Uuid>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let uuid = device.into_inner();
+
+    let request = crate::device::manager::Request::Health(UuidWrapper { uuid });
+    crate::server::auth::authorize(&req, &request)?;
+
+    Ok(Json(manager_handler.send(request).await?))
+}
+
+/// Upload an Intel HEX or binary image, the progress is reported over the websocket.
+/// Only Ping1D devices can be updated.
+#[api_v2_operation(tags("Device Manager : Device"))]
+async fn device_manager_firmware_post(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    device: web::Path<Uuid>,
+    image: web::Bytes,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let uuid = device.into_inner();
+
+    let request = crate::device::manager::Request::FirmwareUpdate(
+        crate::device::manager::firmware::FirmwareUpdateStruct {
+            uuid,
+            image: crate::device::manager::firmware::FirmwareUpload::new(image.to_vec()),
+        },
+    );
+
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[api_v2_operation(tags("Device Manager : Device"))]
 #[get("device_manager/{device}/{request}")]
 async fn device_manager_device_get(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Path<(Uuid, crate::device::devices::PingRequest)>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -190,12 +274,13 @@ async fn device_manager_device_get(
             device_request: request,
         });
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[api_v2_operation(tags("Device Manager : Device"))]
 #[get("device_manager/{device}/ping1d/{request}")]
 async fn device_manager_device_ping1d_get(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Path<(Uuid, crate::device::devices::Ping1DRequest)>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -211,12 +296,13 @@ async fn device_manager_device_ping1d_get(
             device_request: request,
         });
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[api_v2_operation(tags("Device Manager : Device"))]
 #[get("device_manager/{device}/ping360/{request}")]
 async fn device_manager_device_ping360_get(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Path<(Uuid, crate::device::devices::Ping360Request)>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -232,12 +318,13 @@ async fn device_manager_device_ping360_get(
             device_request: request,
         });
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[api_v2_operation(tags("Device Manager : Device"))]
-#[get("device_manager/{device}/tsr1000{request}")]
+#[get("device_manager/{device}/tsr1000/{request}")]
 async fn device_manager_device_tsr1000_get(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Path<(Uuid, crate::device::devices::Tsr1000Request)>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -253,13 +340,13 @@ async fn device_manager_device_tsr1000_get(
             device_request: request,
         });
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
-
 #[api_v2_operation(tags("Device Manager : Device"))]
 #[get("device_manager/{device}/common/{request}")]
 async fn device_manager_device_common_get(
+    req: HttpRequest,
     manager_handler: web::Data<ManagerActorHandler>,
     info: web::Path<(Uuid, crate::device::devices::PingCommonRequest)>,
 ) -> Result<Json<crate::device::manager::Answer>, Error> {
@@ -275,7 +362,7 @@ async fn device_manager_device_common_get(
             device_request: request,
         });
 
-    send_request_and_broadcast(&manager_handler, request).await
+    send_request_and_broadcast(&req, &manager_handler, request).await
 }
 
 #[derive(Debug, Serialize, Deserialize, Apiv2Schema)]
@@ -377,3 +464,73 @@ async fn cockpit_extras(
         widgets,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware, test, web::Data, App};
+    use paperclip::actix::OpenApiExt;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::device::manager::{
+        firmware::{FirmwareStage, FirmwareUpdateStatus, FIRMWARE_MAX_SIZE},
+        Answer, ManagerActorRequest,
+    };
+
+    #[actix_web::test]
+    async fn test_firmware_upload_route() {
+        let (sender, mut receiver) = mpsc::channel::<ManagerActorRequest>(4);
+        let uploads = tokio::spawn(async move {
+            let mut uploads = Vec::new();
+            while let Some(actor_request) = receiver.recv().await {
+                let Request::FirmwareUpdate(firmware) = actor_request.request else {
+                    panic!("Unexpected request: {:?}", actor_request.request);
+                };
+                let answer = Answer::FirmwareUpdate(FirmwareUpdateStatus {
+                    device_id: firmware.uuid,
+                    stage: FirmwareStage::Preparing,
+                    progress: 0.0,
+                });
+                actor_request.respond_to.send(Ok(answer)).unwrap();
+                uploads.push(firmware);
+            }
+            uploads
+        });
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(ManagerActorHandler { sender }))
+                .wrap(middleware::from_fn(crate::server::auth::authenticate))
+                .wrap_api()
+                .service(web::scope("/v1").configure(register_services))
+                .build(),
+        )
+        .await;
+
+        // Larger than the default payload limit, which only applies to the other routes
+        let uuid = Uuid::from_u128(1);
+        let image: Vec<u8> = (0..1024 * 1024u32).map(|value| value as u8).collect();
+        let request = test::TestRequest::post()
+            .uri(&format!("/v1/device_manager/{uuid}/firmware"))
+            .set_payload(image.clone())
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(response.status().is_success(), "{:?}", response.status());
+
+        let request = test::TestRequest::post()
+            .uri(&format!("/v1/device_manager/{uuid}/firmware"))
+            .set_payload(vec![0u8; FIRMWARE_MAX_SIZE + 1])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.status(),
+            actix_web::http::StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        drop(app);
+        let uploads = uploads.await.unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].uuid, uuid);
+        assert_eq!(uploads[0].image.as_slice(), image.as_slice());
+    }
+}
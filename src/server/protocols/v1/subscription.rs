@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::manager::Answer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MessageKind {
+    /// Messages coming from the devices, like profiles and scans
+    DeviceMessage,
+    /// Answers and notifications from the device manager, like device lists, health and firmware progress
+    ManagerEvent,
+    /// Device lifecycle events, like a device being registered, deleted or changing status
+    DeviceEvent,
+    Error,
+}
+
+// What a published message is about, so clients are selected without looking into its content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Topic {
+    pub kind: MessageKind,
+    pub device_id: Option<Uuid>,
+    /// Ping protocol message id, only for device messages, None when it is not known
+    pub message_id: Option<u16>,
+}
+
+impl Topic {
+    pub fn device_message(device_id: Uuid, message_id: u16) -> Self {
+        Self {
+            kind: MessageKind::DeviceMessage,
+            device_id: Some(device_id),
+            message_id: Some(message_id),
+        }
+    }
+
+    pub fn event(device_id: Option<Uuid>) -> Self {
+        Self {
+            kind: MessageKind::ManagerEvent,
+            device_id,
+            message_id: None,
+        }
+    }
+
+    pub fn device_event(device_id: Uuid) -> Self {
+        Self {
+            kind: MessageKind::DeviceEvent,
+            device_id: Some(device_id),
+            message_id: None,
+        }
+    }
+
+    pub fn error(device_id: Option<Uuid>) -> Self {
+        Self {
+            kind: MessageKind::Error,
+            device_id,
+            message_id: None,
+        }
+    }
+
+    // Answers of requests, a Ping request answer is still a device message, even without its id at hand,
+    // so it is not filtered out by the subscribed message ids
+    pub fn from_answer(answer: &Answer, device_id: Option<Uuid>) -> Self {
+        match answer {
+            Answer::DeviceMessage(_) => Self {
+                kind: MessageKind::DeviceMessage,
+                device_id,
+                message_id: None,
+            },
+            _ => Self::event(device_id),
+        }
+    }
+}
+
+/// Messages delivered to a websocket client, a missing field selects everything
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    #[serde(default)]
+    pub device_ids: Option<BTreeSet<Uuid>>,
+    #[serde(default)]
+    pub kinds: Option<BTreeSet<MessageKind>>,
+    /// Only applies to device messages with a known id, the other messages are not filtered by it
+    #[serde(default)]
+    pub message_ids: Option<BTreeSet<u16>>,
+}
+
+fn selected<T: Ord>(selection: &Option<BTreeSet<T>>, value: Option<&T>) -> bool {
+    match (selection, value) {
+        (None, _) => true,
+        (Some(selection), Some(value)) => selection.contains(value),
+        (Some(_), None) => false,
+    }
+}
+
+// Subscribing narrows a field that selected everything down to the given values
+fn subscribe<T: Ord + Clone>(selection: &mut Option<BTreeSet<T>>, values: &Option<BTreeSet<T>>) {
+    if let Some(values) = values {
+        selection
+            .get_or_insert_with(BTreeSet::new)
+            .extend(values.iter().cloned());
+    }
+}
+
+fn unsubscribe<T: Ord>(
+    selection: &mut Option<BTreeSet<T>>,
+    values: &Option<BTreeSet<T>>,
+    everything: impl FnOnce() -> BTreeSet<T>,
+) {
+    if let Some(values) = values {
+        selection
+            .get_or_insert_with(everything)
+            .retain(|value| !values.contains(value));
+    }
+}
+
+impl Subscription {
+    pub fn matches(&self, topic: &Topic) -> bool {
+        selected(&self.device_ids, topic.device_id.as_ref())
+            && selected(&self.kinds, Some(&topic.kind))
+            && (topic.kind != MessageKind::DeviceMessage
+                || topic.message_id.is_none()
+                || selected(&self.message_ids, topic.message_id.as_ref()))
+    }
+
+    pub fn subscribe(&mut self, update: &Subscription) {
+        subscribe(&mut self.device_ids, &update.device_ids);
+        subscribe(&mut self.kinds, &update.kinds);
+        subscribe(&mut self.message_ids, &update.message_ids);
+    }
+
+    pub fn unsubscribe(&mut self, update: &Subscription) -> Result<(), String> {
+        // Every kind is known, while device and message ids can only be removed once subscribed to
+        for (everything, values, field) in [
+            (
+                self.device_ids.is_none(),
+                update.device_ids.is_some(),
+                "device id",
+            ),
+            (
+                self.message_ids.is_none(),
+                update.message_ids.is_some(),
+                "message id",
+            ),
+        ] {
+            if everything && values {
+                return Err(format!(
+                    "Subscribed to every {field}, subscribe to specific ones before unsubscribing"
+                ));
+            }
+        }
+
+        unsubscribe(&mut self.device_ids, &update.device_ids, BTreeSet::new);
+        unsubscribe(&mut self.kinds, &update.kinds, || {
+            BTreeSet::from([
+                MessageKind::DeviceMessage,
+                MessageKind::ManagerEvent,
+                MessageKind::DeviceEvent,
+                MessageKind::Error,
+            ])
+        });
+        unsubscribe(&mut self.message_ids, &update.message_ids, BTreeSet::new);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_matches() {
+        let device = Uuid::from_u128(1);
+        let other = Uuid::from_u128(2);
+
+        let everything = Subscription::default();
+        assert!(everything.matches(&Topic::device_message(device, 2300)));
+        assert!(everything.matches(&Topic::event(None)));
+
+        let mut subscription = Subscription::default();
+        subscription.subscribe(&Subscription {
+            device_ids: Some(BTreeSet::from([device])),
+            message_ids: Some(BTreeSet::from([2300])),
+            ..Default::default()
+        });
+        assert!(subscription.matches(&Topic::device_message(device, 2300)));
+        assert!(!subscription.matches(&Topic::device_message(device, 1211)));
+        assert!(!subscription.matches(&Topic::device_message(other, 2300)));
+        assert!(subscription.matches(&Topic::error(Some(device))));
+        assert!(!subscription.matches(&Topic::event(None)));
+
+        // Request answers carry no message id, they are still delivered to the device subscribers
+        let answer = Answer::DeviceMessage(crate::device::manager::DeviceAnswer {
+            answer: crate::device::devices::PingAnswer::NotSupported(
+                crate::device::devices::PingRequest::GetSubscriber,
+            ),
+            device_id: device,
+            heading: None,
+            timestamp: None,
+        });
+        assert!(subscription.matches(&Topic::from_answer(&answer, Some(device))));
+        assert!(!subscription.matches(&Topic::from_answer(&answer, Some(other))));
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let device = Uuid::from_u128(1);
+        let mut subscription = Subscription::default();
+
+        subscription
+            .unsubscribe(&Subscription {
+                kinds: Some(BTreeSet::from([MessageKind::DeviceMessage])),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!subscription.matches(&Topic::device_message(device, 1300)));
+        assert!(subscription.matches(&Topic::event(Some(device))));
+
+        let unknown_universe = Subscription {
+            device_ids: Some(BTreeSet::from([device])),
+            kinds: Some(BTreeSet::from([MessageKind::Error])),
+            ..Default::default()
+        };
+        let before = subscription.clone();
+        assert!(subscription.unsubscribe(&unknown_universe).is_err());
+        assert_eq!(subscription, before);
+    }
+}
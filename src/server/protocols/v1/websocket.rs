@@ -2,7 +2,7 @@ use actix::{
     dev::ContextFutureSpawner, fut, Actor, ActorFutureExt, Addr, AsyncContext, Handler, Message,
     StreamHandler, WrapFuture,
 };
-use actix_web::HttpRequest;
+use actix_web::{web::Bytes, HttpRequest};
 use actix_web_actors::ws;
 use lazy_static::lazy_static;
 use paperclip::actix::{
@@ -10,14 +10,27 @@ use paperclip::actix::{
     web::{self, HttpResponse},
     Apiv2Schema,
 };
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::sync::{Arc, Mutex};
+use std::{
+    cell::OnceCell,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 use tracing::info;
 use uuid::Uuid;
 
 use crate::device::manager::{ManagerActorHandler, Request};
+use crate::server::auth::{self, Role};
+use crate::server::protocols::v1::{
+    encoding::{self, Encoding},
+    subscription::{MessageKind, Subscription, Topic},
+};
+
+// Messages queued for a client beyond this are dropped, so a stalled client can't exhaust memory
+const CLIENT_MAILBOX_CAPACITY: usize = 1024;
 
 pub struct StringMessage(String);
 
@@ -25,16 +38,85 @@ impl Message for StringMessage {
     type Result = ();
 }
 
+pub struct BinaryMessage(Bytes);
+
+impl Message for BinaryMessage {
+    type Result = ();
+}
+
+// Asks a client to leave, as the server is going down
+pub struct CloseMessage;
+
+impl Message for CloseMessage {
+    type Result = ();
+}
+
 #[derive(Serialize, Debug)]
 pub struct WebsocketError {
     pub error: String,
 }
 
+// Envelope of the requests sent by clients, request_id and broadcast are optional
+// e.g. {"request_id": 7, "broadcast": false, "module": "DeviceManager", "command": "List"}
+#[derive(Debug, Deserialize)]
+pub struct WebsocketRequest {
+    /// Any JSON value chosen by the client, echoed back on the direct reply
+    #[serde(default)]
+    pub request_id: Option<Value>,
+    /// Whether the result is also sent to every subscribed client, as when no request_id is given
+    #[serde(default = "default_broadcast")]
+    pub broadcast: bool,
+    #[serde(flatten)]
+    pub module: crate::ModuleType,
+}
+
+fn default_broadcast() -> bool {
+    true
+}
+
+// Direct reply to the client that sent a request with a request_id
+#[derive(Serialize, Debug)]
+pub struct WebsocketReply {
+    pub request_id: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+impl WebsocketReply {
+    pub fn new(request_id: Option<Value>, result: Result<Value, Value>) -> Self {
+        match result {
+            Ok(answer) => Self {
+                request_id,
+                answer: Some(answer),
+                error: None,
+            },
+            Err(error) => Self {
+                request_id,
+                answer: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct WebsocketActorContent {
     pub actor: Addr<WebsocketActor>,
-    pub re: Option<Regex>,
-    pub device_number: Option<Uuid>,
+    pub subscription: Subscription,
+    pub encoding: Encoding,
+}
+
+// Sent by clients as {"module": "Websocket", "command": "Subscribe", "payload": {"kinds": ["DeviceMessage"]}}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", content = "payload")]
+pub enum WebsocketControl {
+    Subscribe(Subscription),
+    Unsubscribe(Subscription),
+    /// Replaces the whole subscription, an empty one selects everything
+    SetSubscription(Subscription),
+    GetSubscription,
 }
 
 #[derive(Debug, Default)]
@@ -43,18 +125,34 @@ pub struct WebsocketManager {
 }
 
 impl WebsocketManager {
-    pub fn send(&self, value: &serde_json::Value, name: &str, device_number: Option<Uuid>) {
+    pub fn send(&self, value: &serde_json::Value, topic: &Topic) {
         if self.clients.is_empty() {
             return;
         }
 
-        let string = serde_json::to_string(value).unwrap();
+        // Each encoding is serialized once, on the first client asking for it
+        let json = OnceCell::new();
+        let message_pack = OnceCell::new();
+        let cbor = OnceCell::new();
         for client in &self.clients {
-            // check client list was subscribed or subscribed to all
-            if client.device_number.is_none() || client.device_number == device_number {
-                let is_match = client.re.as_ref().map_or(false, |regx| regx.is_match(name));
-                if is_match {
-                    client.actor.do_send(StringMessage(string.clone()));
+            if client.subscription.matches(topic) {
+                let result = match client.encoding {
+                    Encoding::Json => client.actor.try_send(StringMessage(
+                        json.get_or_init(|| serde_json::to_string(value).unwrap())
+                            .clone(),
+                    )),
+                    Encoding::MessagePack => client.actor.try_send(BinaryMessage(
+                        message_pack
+                            .get_or_init(|| Bytes::from(encoding::to_message_pack(value)))
+                            .clone(),
+                    )),
+                    Encoding::Cbor => client.actor.try_send(BinaryMessage(
+                        cbor.get_or_init(|| Bytes::from(encoding::to_cbor(value)))
+                            .clone(),
+                    )),
+                };
+                if result.is_err() {
+                    DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
                 }
             }
         }
@@ -66,35 +164,109 @@ lazy_static! {
         Arc::new(Mutex::new(WebsocketManager::default()));
 }
 
-pub fn send_to_websockets(message: Value, device: Option<Uuid>) {
-    MANAGER
-        .lock()
-        .unwrap()
-        .send(&message, &message.to_string(), device);
+static DROPPED_MESSAGES: AtomicU64 = AtomicU64::new(0);
+
+pub fn client_count() -> usize {
+    MANAGER.lock().map_or(0, |manager| manager.clients.len())
+}
+
+pub fn dropped_messages() -> u64 {
+    DROPPED_MESSAGES.load(Ordering::Relaxed)
+}
+
+pub fn send_to_websockets(message: Value, topic: Topic) {
+    MANAGER.lock().unwrap().send(&message, &topic);
+}
+
+// Sends a close frame to every client, used on shutdown so clients know the server went away on purpose
+pub fn close_clients() {
+    let manager = MANAGER.lock().unwrap();
+    info!(
+        "ServerManager: Closing {} websocket clients",
+        manager.clients.len()
+    );
+    for client in &manager.clients {
+        client.actor.do_send(CloseMessage);
+    }
 }
 
 pub struct WebsocketActor {
     server: Arc<Mutex<WebsocketManager>>,
-    pub filter: String,
+    pub subscription: Subscription,
     pub device_number: Option<Uuid>,
+    pub encoding: Encoding,
+    pub role: Role,
     pub manager_handler: web::Data<ManagerActorHandler>,
 }
 
 impl WebsocketActor {
     pub fn new(
-        message_filter: String,
+        subscription: Subscription,
         device_number: Option<Uuid>,
+        encoding: Encoding,
+        role: Role,
         manager_handler: web::Data<ManagerActorHandler>,
     ) -> Self {
         Self {
             server: MANAGER.clone(),
-            filter: message_filter,
+            subscription,
             device_number,
+            encoding,
+            role,
             manager_handler,
         }
     }
 }
 
+impl WebsocketActor {
+    // Answered only to the requester, with the resulting subscription
+    fn handle_control(
+        &mut self,
+        control: WebsocketControl,
+        request_id: Option<Value>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let result = match control {
+            WebsocketControl::Subscribe(update) => {
+                self.subscription.subscribe(&update);
+                Ok(())
+            }
+            WebsocketControl::Unsubscribe(update) => self.subscription.unsubscribe(&update),
+            WebsocketControl::SetSubscription(subscription) => {
+                self.subscription = subscription;
+                Ok(())
+            }
+            WebsocketControl::GetSubscription => Ok(()),
+        };
+
+        if let Err(error) = result {
+            match request_id {
+                Some(_) => {
+                    ctx.text(json!(WebsocketReply::new(request_id, Err(json!(error)))).to_string())
+                }
+                None => ctx.text(json!(WebsocketError { error }).to_string()),
+            }
+            return;
+        }
+
+        if let Some(client) = self
+            .server
+            .lock()
+            .unwrap()
+            .clients
+            .iter_mut()
+            .find(|client| client.actor == ctx.address())
+        {
+            client.subscription = self.subscription.clone();
+        }
+        let answer = json!({ "Subscription": self.subscription });
+        match request_id {
+            Some(_) => ctx.text(json!(WebsocketReply::new(request_id, Ok(answer))).to_string()),
+            None => ctx.text(answer.to_string()),
+        }
+    }
+}
+
 impl Handler<StringMessage> for WebsocketActor {
     type Result = ();
 
@@ -103,6 +275,26 @@ impl Handler<StringMessage> for WebsocketActor {
     }
 }
 
+impl Handler<BinaryMessage> for WebsocketActor {
+    type Result = ();
+
+    fn handle(&mut self, message: BinaryMessage, context: &mut Self::Context) {
+        context.binary(message.0);
+    }
+}
+
+impl Handler<CloseMessage> for WebsocketActor {
+    type Result = ();
+
+    fn handle(&mut self, _message: CloseMessage, context: &mut Self::Context) {
+        context.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Away,
+            description: Some("Server is shutting down".to_string()),
+        }));
+        context.stop();
+    }
+}
+
 impl Actor for WebsocketActor {
     type Context = ws::WebsocketContext<Self>;
 }
@@ -110,14 +302,15 @@ impl Actor for WebsocketActor {
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
     fn started(&mut self, ctx: &mut Self::Context) {
         info!("ServerManager: Starting websocket client, add itself in manager.");
+        ctx.set_mailbox_capacity(CLIENT_MAILBOX_CAPACITY);
         self.server
             .lock()
             .unwrap()
             .clients
             .push(WebsocketActorContent {
                 actor: ctx.address(),
-                re: Regex::new(&self.filter).ok(),
-                device_number: (self.device_number),
+                subscription: self.subscription.clone(),
+                encoding: self.encoding,
             });
     }
 
@@ -134,7 +327,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                let manager_requests: Vec<crate::ModuleType> = match serde_json::from_str(&text) {
+                let manager_requests: Vec<WebsocketRequest> = match serde_json::from_str(&text) {
                     Ok(requests) => requests,
                     Err(err) => match serde_json::from_str(&text) {
                         Ok(request) => vec![request],
@@ -146,9 +339,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
                     },
                 };
 
-                for request in manager_requests {
-                    match request {
+                for WebsocketRequest {
+                    request_id,
+                    broadcast,
+                    module,
+                } in manager_requests
+                {
+                    match module {
                         crate::ModuleType::DeviceManager(request) => {
+                            if !self.role.allows(&request) {
+                                let error = format!(
+                                    "{:?} token is not allowed to send this request",
+                                    self.role
+                                );
+                                match request_id {
+                                    Some(_) => ctx.text(
+                                        json!(WebsocketReply::new(request_id, Err(json!(error))))
+                                            .to_string(),
+                                    ),
+                                    None => ctx.text(json!(WebsocketError { error }).to_string()),
+                                }
+                                continue;
+                            }
                             let manager_handler = self.manager_handler.clone();
 
                             let request_has_id = match &request {
@@ -162,6 +374,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
                                 Request::DisableContinuousMode(uuid_wrapper) => {
                                     Some(uuid_wrapper.uuid)
                                 }
+                                Request::StartRecording(uuid_wrapper) => Some(uuid_wrapper.uuid),
+                                Request::StopRecording(uuid_wrapper) => Some(uuid_wrapper.uuid),
+                                Request::Replay(replay_control) => Some(replay_control.uuid),
+                                Request::Health(uuid_wrapper) => Some(uuid_wrapper.uuid),
+                                Request::FirmwareUpdate(firmware) => Some(firmware.uuid),
                                 _ => None,
                             };
 
@@ -176,19 +393,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
                                                 Some(device_number) => Some(device_number),
                                                 None => actor.device_number,
                                             };
-                                            crate::server::protocols::v1::websocket::send_to_websockets(
-                                                json!(result),
-                                                device_number,
-                                            );
-                                        }
-                                        Err(err) => {
-                                            ctx.text(serde_json::to_string_pretty(err).unwrap());
+                                            if request_id.is_some() || !broadcast {
+                                                ctx.text(json!(WebsocketReply::new(request_id, Ok(json!(result)))).to_string());
+                                            }
+                                            if broadcast {
+                                                crate::server::protocols::v1::websocket::send_to_websockets(
+                                                    json!(result),
+                                                    Topic::from_answer(result, device_number),
+                                                );
+                                            }
                                         }
+                                        Err(err) => match request_id {
+                                            Some(_) => ctx.text(json!(WebsocketReply::new(request_id, Err(json!(err)))).to_string()),
+                                            None => ctx.text(serde_json::to_string_pretty(err).unwrap()),
+                                        },
                                     }
                                     fut::ready(())
                                 })
                                 .wait(ctx);
                         }
+                        crate::ModuleType::Websocket(control) => {
+                            self.handle_control(control, request_id, ctx);
+                        }
                     }
                 }
             }
@@ -206,13 +432,14 @@ pub async fn websocket(
     stream: web::Payload,
     manager_handler: web::Data<ManagerActorHandler>,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let role = auth::role(&req)?;
     let query_inner = query.into_inner();
 
-    let filter = match query_inner.filter {
-        Some(filter) => filter,
-        _ => ".*".to_owned(),
-    };
     let device_number = query_inner.device_number;
+    let subscription = match query_inner.subscription() {
+        Ok(subscription) => subscription,
+        Err(error) => return Ok(HttpResponse::BadRequest().json(WebsocketError { error })),
+    };
 
     if let Some(device_number) = device_number {
         let request = crate::device::manager::Request::Info(crate::device::manager::UuidWrapper {
@@ -231,7 +458,13 @@ pub async fn websocket(
     }
 
     ws::start(
-        WebsocketActor::new(filter, device_number, manager_handler.clone()),
+        WebsocketActor::new(
+            subscription,
+            device_number,
+            query_inner.encoding.unwrap_or_default(),
+            role,
+            manager_handler.clone(),
+        ),
         &req,
         stream,
     )
@@ -239,7 +472,107 @@ pub async fn websocket(
 
 #[derive(Deserialize, Apiv2Schema, Clone)]
 pub struct WebsocketQuery {
-    /// Regex filter to select the desired incoming messages
-    filter: Option<String>,
+    /// Only messages of this device, and messages not related to any device are left out
     device_number: Option<Uuid>,
+    /// Comma separated message kinds: DeviceMessage, ManagerEvent, DeviceEvent and Error
+    kinds: Option<String>,
+    /// Comma separated ping protocol message ids, only applied to device messages
+    message_ids: Option<String>,
+    /// Encoding of the messages sent to the client: json (default), msgpack or cbor, binary ones go out as binary frames
+    encoding: Option<Encoding>,
+    /// Removed regex filter, requests using it are refused in favor of kinds and message_ids
+    filter: Option<String>,
+}
+
+impl WebsocketQuery {
+    // Initial subscription, clients can change it later with WebsocketControl messages
+    fn subscription(&self) -> Result<Subscription, String> {
+        if self.filter.is_some() {
+            return Err(
+                "The filter parameter was removed, select messages with kinds and message_ids instead"
+                    .to_string(),
+            );
+        }
+        let kinds = self
+            .kinds
+            .as_ref()
+            .map(|kinds| {
+                kinds
+                    .split(',')
+                    .map(|kind| {
+                        serde_json::from_value::<MessageKind>(json!(kind.trim()))
+                            .map_err(|_| format!("Unknown message kind: {kind}"))
+                    })
+                    .collect()
+            })
+            .transpose()?;
+        let message_ids = self
+            .message_ids
+            .as_ref()
+            .map(|ids| {
+                ids.split(',')
+                    .map(|id| {
+                        id.trim()
+                            .parse::<u16>()
+                            .map_err(|_| format!("Invalid message id: {id}"))
+                    })
+                    .collect()
+            })
+            .transpose()?;
+
+        Ok(Subscription {
+            device_ids: self.device_number.map(|id| [id].into()),
+            kinds,
+            message_ids,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_envelope() {
+        let request: WebsocketRequest = serde_json::from_str(
+            r#"{"request_id": "scan-1", "broadcast": false, "module": "DeviceManager", "command": "List"}"#,
+        )
+        .unwrap();
+        assert_eq!(request.request_id, Some(json!("scan-1")));
+        assert!(!request.broadcast);
+        assert!(matches!(
+            request.module,
+            crate::ModuleType::DeviceManager(Request::List)
+        ));
+
+        let request: WebsocketRequest =
+            serde_json::from_str(r#"{"module": "DeviceManager", "command": "List"}"#).unwrap();
+        assert_eq!(request.request_id, None);
+        assert!(request.broadcast);
+
+        assert_eq!(
+            json!(WebsocketReply::new(Some(json!(7)), Err(json!("NoDevices")))),
+            json!({"request_id": 7, "error": "NoDevices"})
+        );
+    }
+    #[test]
+    fn test_query_subscription() {
+        let query = |query: &str| {
+            web::Query::<WebsocketQuery>::from_query(query)
+                .unwrap()
+                .subscription()
+        };
+
+        let subscription = query("kinds=DeviceMessage,Error&message_ids=1211,1300").unwrap();
+        assert_eq!(
+            subscription.kinds,
+            Some([MessageKind::DeviceMessage, MessageKind::Error].into())
+        );
+        assert_eq!(subscription.message_ids, Some([1211, 1300].into()));
+
+        assert!(query("kinds=Unknown").is_err());
+        assert!(query("filter=.*")
+            .unwrap_err()
+            .contains("kinds and message_ids"));
+    }
 }
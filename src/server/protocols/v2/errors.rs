@@ -0,0 +1,189 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use paperclip::actix::api_v2_errors;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::device::manager::ManagerError;
+
+// Body of every error answer, error is a stable identifier clients can match on
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+#[allow(dead_code)]
+#[api_v2_errors(
+    code = 400,
+    description = "Bad Request: The request is invalid for the device or contains malformed data.",
+    code = 401,
+    description = "Unauthorized: The API token is missing or unknown.",
+    code = 403,
+    description = "Forbidden: The API token role doesn't allow this request.",
+    code = 404,
+    description = "Not Found: The device doesn't exist.",
+    code = 409,
+    description = "Conflict: The device already exists or its current status doesn't allow this request.",
+    code = 500,
+    description = "Internal Server Error: An unexpected server error has occurred.",
+    code = 503,
+    description = "Service Unavailable: The device or its source is not responding."
+)]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Internal(String),
+    #[error("{}", describe(.0))]
+    Manager(ManagerError),
+}
+
+fn describe(error: &ManagerError) -> String {
+    match error {
+        ManagerError::DeviceNotExist(id) => format!("Device {id} doesn't exist"),
+        ManagerError::DeviceAlreadyExist(id) => format!("Device {id} already exists"),
+        ManagerError::DeviceStatus(status, id) => {
+            format!("Device {id} is {status:?}, which doesn't allow this request")
+        }
+        ManagerError::DeviceError(err) => format!("Device error: {err:?}"),
+        ManagerError::DeviceSourceError(err) => format!("Device source error: {err}"),
+        ManagerError::NoDevices => "No devices available".to_string(),
+        ManagerError::TokioMpsc(err) => format!("Device manager is not responding: {err}"),
+        ManagerError::NotImplemented(request) => {
+            format!("Request not available: {request:?}")
+        }
+        ManagerError::InvalidRequest(err)
+        | ManagerError::Conflict(err)
+        | ManagerError::Other(err) => err.clone(),
+    }
+}
+
+impl Error {
+    // Manager errors are named after their variant, as serialized on v1 and the websocket
+    fn name(&self) -> String {
+        match self {
+            Self::BadRequest(_) => "BadRequest".to_string(),
+            Self::Unauthorized(_) => "Unauthorized".to_string(),
+            Self::Forbidden(_) => "Forbidden".to_string(),
+            Self::Internal(_) => "Internal".to_string(),
+            Self::Manager(error) => match json!(error) {
+                Value::String(name) => name,
+                Value::Object(map) => map.keys().next().cloned().unwrap_or_default(),
+                _ => "Manager".to_string(),
+            },
+        }
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Manager(error) => match error {
+                ManagerError::DeviceNotExist(_) | ManagerError::NoDevices => StatusCode::NOT_FOUND,
+                ManagerError::DeviceAlreadyExist(_)
+                | ManagerError::DeviceStatus(..)
+                | ManagerError::Conflict(_) => StatusCode::CONFLICT,
+                ManagerError::DeviceError(_)
+                | ManagerError::DeviceSourceError(_)
+                | ManagerError::TokioMpsc(_) => StatusCode::SERVICE_UNAVAILABLE,
+                ManagerError::NotImplemented(_) | ManagerError::InvalidRequest(_) => {
+                    StatusCode::BAD_REQUEST
+                }
+                ManagerError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.name(),
+            message: self.to_string(),
+            details: match self {
+                Self::Manager(error) => Some(json!(error)),
+                _ => None,
+            },
+        })
+    }
+}
+
+impl From<ManagerError> for Error {
+    fn from(error: ManagerError) -> Self {
+        Self::Manager(error)
+    }
+}
+
+// Authorization is shared with v1
+impl From<crate::server::protocols::v1::errors::Error> for Error {
+    fn from(error: crate::server::protocols::v1::errors::Error) -> Self {
+        use crate::server::protocols::v1::errors::Error as V1;
+        match error {
+            V1::BadRequest(message) => Self::BadRequest(message),
+            V1::Unauthorized(message) => Self::Unauthorized(message),
+            V1::Forbidden(message) => Self::Forbidden(message),
+            V1::Internal(message) => Self::Internal(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::device::manager::DeviceStatus;
+
+    #[test]
+    fn test_status_mapping() {
+        let id = Uuid::from_u128(1);
+        for (error, status) in [
+            (ManagerError::DeviceNotExist(id), StatusCode::NOT_FOUND),
+            (ManagerError::DeviceAlreadyExist(id), StatusCode::CONFLICT),
+            (
+                ManagerError::DeviceStatus(DeviceStatus::Stopped, id),
+                StatusCode::CONFLICT,
+            ),
+            (
+                ManagerError::DeviceSourceError("timeout".to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ManagerError::InvalidRequest("empty image".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ManagerError::Conflict("already recording".to_string()),
+                StatusCode::CONFLICT,
+            ),
+            (
+                ManagerError::Other("unexpected".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ] {
+            assert_eq!(Error::from(error).status_code(), status);
+        }
+
+        let body = Error::from(ManagerError::DeviceNotExist(id))
+            .error_response()
+            .into_body()
+            .try_into_bytes()
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "DeviceNotExist");
+        assert_eq!(body["message"], format!("Device {id} doesn't exist"));
+        assert_eq!(body["details"], json!({ "DeviceNotExist": id }));
+
+        assert_eq!(Error::from(ManagerError::NoDevices).name(), "NoDevices");
+    }
+}
@@ -0,0 +1,374 @@
+use actix_web::HttpRequest;
+use paperclip::actix::{
+    api_v2_operation, delete, get, post, put,
+    web::{self, HttpResponse, Json},
+    Apiv2Schema,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::device::manager::{
+    Answer, CreateStruct, DeviceInfo, DeviceSelection, ManagerActorHandler, ManagerError,
+    ModifyDevice, ModifyDeviceCommand, ModifyDeviceResult, Ping1DConfig, Ping360Config, Request,
+    Tsr1000Config, UuidWrapper,
+};
+use crate::server::protocols::v1::subscription::Topic;
+use crate::server::protocols::v2::errors::Error;
+
+// Settings of a device, named after its type
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub enum DeviceConfig {
+    Ping360(Ping360Config),
+    Ping1D(Ping1DConfig),
+    Tsr1000(Tsr1000Config),
+}
+
+pub fn register_services(cfg: &mut web::ServiceConfig) {
+    cfg.service(devices_get)
+        .service(devices_post)
+        .service(device_get)
+        .service(device_delete)
+        .service(device_config_get)
+        .service(device_config_put)
+        .service(device_continuous_put)
+        .service(device_continuous_delete);
+}
+
+// Results are also sent to the websocket clients, as v1 does
+async fn send(
+    req: &HttpRequest,
+    manager_handler: &web::Data<ManagerActorHandler>,
+    request: Request,
+    device_id: Option<Uuid>,
+) -> Result<Answer, Error> {
+    crate::server::auth::authorize(req, &request)?;
+
+    let answer = manager_handler.send(request).await?;
+    crate::server::protocols::v1::websocket::send_to_websockets(
+        json!(answer),
+        Topic::from_answer(&answer, device_id),
+    );
+    Ok(answer)
+}
+
+fn into_devices(answer: Answer) -> Result<Vec<DeviceInfo>, Error> {
+    match answer {
+        Answer::DeviceInfo(devices) => Ok(devices),
+        unexpected => Err(Error::Internal(format!(
+            "Unexpected answer from device manager: {unexpected:?}"
+        ))),
+    }
+}
+
+fn into_device(answer: Answer) -> Result<DeviceInfo, Error> {
+    into_devices(answer)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Internal("Device manager answered without devices".to_string()))
+}
+
+async fn device_info(
+    req: &HttpRequest,
+    manager_handler: &web::Data<ManagerActorHandler>,
+    uuid: Uuid,
+) -> Result<DeviceInfo, Error> {
+    crate::server::auth::authorize(req, &Request::Info(UuidWrapper { uuid }))?;
+    into_device(
+        manager_handler
+            .send(Request::Info(UuidWrapper { uuid }))
+            .await?,
+    )
+}
+
+/// List all devices, empty when there are none
+#[api_v2_operation(tags("Devices"))]
+#[get("devices")]
+async fn devices_get(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<HttpResponse, Error> {
+    let devices = match send(&req, &manager_handler, Request::List, None).await {
+        Ok(answer) => into_devices(answer)?,
+        Err(Error::Manager(ManagerError::NoDevices)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    Ok(HttpResponse::Ok().json(devices))
+}
+
+/// Create a device, answered with 201 and the new device
+#[api_v2_operation(tags("Devices"))]
+#[post("devices")]
+async fn devices_post(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    create: Json<CreateStruct>,
+) -> Result<HttpResponse, Error> {
+    let answer = send(
+        &req,
+        &manager_handler,
+        Request::Create(create.into_inner()),
+        None,
+    )
+    .await?;
+    let device = into_device(answer)?;
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", format!("/v2/devices/{}", device.id)))
+        .json(device))
+}
+
+#[api_v2_operation(tags("Devices"))]
+#[get("devices/{id}")]
+async fn device_get(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let device = device_info(&req, &manager_handler, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(device))
+}
+
+#[api_v2_operation(tags("Devices"))]
+#[delete("devices/{id}")]
+async fn device_delete(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let uuid = id.into_inner();
+    send(
+        &req,
+        &manager_handler,
+        Request::Delete(UuidWrapper { uuid }),
+        Some(uuid),
+    )
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Current settings, as {"Ping360": {..}}, {"Ping1D": {..}} or {"Tsr1000": {..}}
+#[api_v2_operation(tags("Devices"))]
+#[get("devices/{id}/config")]
+async fn device_config_get(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let uuid = id.into_inner();
+    let modify = match device_info(&req, &manager_handler, uuid).await?.device_type {
+        DeviceSelection::Ping360 => ModifyDeviceCommand::GetPing360Config,
+        DeviceSelection::Ping1D => ModifyDeviceCommand::GetPing1DConfig,
+        DeviceSelection::Tsr1000 => ModifyDeviceCommand::GetTsr1000Config,
+        device_type => {
+            return Err(Error::BadRequest(format!(
+                "{device_type:?} devices have no settings"
+            )))
+        }
+    };
+
+    let request = Request::ModifyDevice(ModifyDevice { uuid, modify });
+    let config = match send(&req, &manager_handler, request, Some(uuid)).await? {
+        Answer::DeviceConfig(ModifyDeviceResult::Ping360Config(config)) => {
+            DeviceConfig::Ping360(config)
+        }
+        Answer::DeviceConfig(ModifyDeviceResult::Ping1DConfig(config)) => {
+            DeviceConfig::Ping1D(config)
+        }
+        Answer::DeviceConfig(ModifyDeviceResult::Tsr1000Config(config)) => {
+            DeviceConfig::Tsr1000(config)
+        }
+        unexpected => {
+            return Err(Error::Internal(format!(
+                "Unexpected answer from device manager: {unexpected:?}"
+            )))
+        }
+    };
+    Ok(HttpResponse::Ok().json(config))
+}
+
+/// Replace the settings, the configuration type must match the device type
+#[api_v2_operation(tags("Devices"))]
+#[put("devices/{id}/config")]
+async fn device_config_put(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    id: web::Path<Uuid>,
+    config: Json<DeviceConfig>,
+) -> Result<HttpResponse, Error> {
+    let uuid = id.into_inner();
+    let config = config.into_inner();
+    let device_type = device_info(&req, &manager_handler, uuid).await?.device_type;
+    let modify = match (&device_type, config.clone()) {
+        (DeviceSelection::Ping360, DeviceConfig::Ping360(config)) => {
+            ModifyDeviceCommand::SetPing360Config(config)
+        }
+        (DeviceSelection::Ping1D, DeviceConfig::Ping1D(config)) => {
+            ModifyDeviceCommand::SetPing1DConfig(config)
+        }
+        (DeviceSelection::Tsr1000, DeviceConfig::Tsr1000(config)) => {
+            ModifyDeviceCommand::SetTsr1000Config(config)
+        }
+        _ => {
+            return Err(Error::BadRequest(format!(
+                "Configuration doesn't match the {device_type:?} device: {config:?}"
+            )))
+        }
+    };
+
+    let request = Request::ModifyDevice(ModifyDevice { uuid, modify });
+    send(&req, &manager_handler, request, Some(uuid)).await?;
+    Ok(HttpResponse::Ok().json(config))
+}
+
+/// Start streaming the device data to the websocket clients
+#[api_v2_operation(tags("Devices"))]
+#[put("devices/{id}/continuous")]
+async fn device_continuous_put(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let uuid = id.into_inner();
+    let request = Request::EnableContinuousMode(UuidWrapper { uuid });
+    send(&req, &manager_handler, request, Some(uuid)).await?;
+    let device = device_info(&req, &manager_handler, uuid).await?;
+    Ok(HttpResponse::Ok().json(device))
+}
+
+#[api_v2_operation(tags("Devices"))]
+#[delete("devices/{id}/continuous")]
+async fn device_continuous_delete(
+    req: HttpRequest,
+    manager_handler: web::Data<ManagerActorHandler>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let uuid = id.into_inner();
+    let request = Request::DisableContinuousMode(UuidWrapper { uuid });
+    send(&req, &manager_handler, request, Some(uuid)).await?;
+    let device = device_info(&req, &manager_handler, uuid).await?;
+    Ok(HttpResponse::Ok().json(device))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use actix_web::{http::StatusCode, middleware, test, web::Data, App};
+    use paperclip::actix::OpenApiExt;
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::device::manager::{
+        DeviceStatus, ManagerActorRequest, SourceSelection, SourceUdpStruct,
+    };
+
+    fn ping360(id: Uuid) -> DeviceInfo {
+        DeviceInfo {
+            id,
+            name: None,
+            source: SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::LOCALHOST,
+                port: 12345,
+            }),
+            status: DeviceStatus::Running,
+            device_type: DeviceSelection::Ping360,
+            properties: None,
+            recording: None,
+            mavlink: None,
+            nmea: None,
+        }
+    }
+
+    fn known(device: &Option<DeviceInfo>, uuid: Uuid) -> Result<DeviceInfo, ManagerError> {
+        device
+            .clone()
+            .filter(|device| device.id == uuid)
+            .ok_or(ManagerError::DeviceNotExist(uuid))
+    }
+
+    // Answers as a device manager without devices, until one is created
+    fn manager() -> ManagerActorHandler {
+        let (sender, mut receiver) = mpsc::channel::<ManagerActorRequest>(4);
+        tokio::spawn(async move {
+            let mut device: Option<DeviceInfo> = None;
+            while let Some(actor_request) = receiver.recv().await {
+                let result = match actor_request.request {
+                    Request::List => device
+                        .clone()
+                        .map(|device| Answer::DeviceInfo(vec![device]))
+                        .ok_or(ManagerError::NoDevices),
+                    Request::Create(create) => {
+                        let created = ping360(create.source.device_id());
+                        device = Some(created.clone());
+                        Ok(Answer::DeviceInfo(vec![created]))
+                    }
+                    Request::Info(UuidWrapper { uuid }) => {
+                        known(&device, uuid).map(|device| Answer::DeviceInfo(vec![device]))
+                    }
+                    Request::Delete(UuidWrapper { uuid }) => known(&device, uuid).map(|deleted| {
+                        device = None;
+                        Answer::DeviceInfo(vec![deleted])
+                    }),
+                    request => panic!("Unexpected request: {request:?}"),
+                };
+                actor_request.respond_to.send(result).unwrap();
+            }
+        });
+        ManagerActorHandler { sender }
+    }
+
+    #[actix_web::test]
+    async fn test_devices_routes() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(manager()))
+                .wrap(middleware::from_fn(crate::server::auth::authenticate))
+                .wrap_api()
+                .service(web::scope("/v2").configure(register_services))
+                .build(),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/v2/devices").to_request();
+        let devices: Vec<DeviceInfo> = test::call_and_read_body_json(&app, request).await;
+        assert!(devices.is_empty());
+
+        let request = test::TestRequest::post()
+            .uri("/v2/devices")
+            .set_json(json!({
+                "source": { "UdpStream": { "ip": "127.0.0.1", "port": 12345 } },
+                "device_selection": "Ping360",
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()["Location"].to_str().unwrap().to_string();
+        let device: DeviceInfo = test::read_body_json(response).await;
+        assert_eq!(location, format!("/v2/devices/{}", device.id));
+
+        // Settings of another device type
+        let request = test::TestRequest::put()
+            .uri(&format!("/v2/devices/{}/config", device.id))
+            .set_json(json!({ "Ping1D": Ping1DConfig::default() }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "BadRequest");
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/v2/devices/{}", device.id))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/v2/devices/{}", device.id))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "DeviceNotExist");
+    }
+}
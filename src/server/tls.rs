@@ -0,0 +1,134 @@
+use std::{fs, io::BufReader, path::Path, sync::Arc};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tracing::info;
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    /// Generate a self-signed certificate when the files are missing
+    pub self_signed: bool,
+}
+
+fn generate_self_signed(config: &TlsConfig, hosts: Vec<String>) -> Result<(), String> {
+    let certified = rcgen::generate_simple_self_signed(hosts.clone())
+        .map_err(|err| format!("TLS: Failed to generate self-signed certificate: {err}"))?;
+
+    for path in [&config.cert_path, &config.key_path] {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("TLS: Failed to create {}: {err}", parent.display()))?;
+        }
+    }
+    fs::write(&config.cert_path, certified.cert.pem())
+        .map_err(|err| format!("TLS: Failed to write {}: {err}", config.cert_path))?;
+    write_private(&config.key_path, &certified.key_pair.serialize_pem())?;
+
+    info!(
+        "TLS: Generated self-signed certificate for {hosts:?} at {}",
+        config.cert_path
+    );
+    Ok(())
+}
+
+// The key is only readable by its owner where the platform allows it
+fn write_private(path: &str, content: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .map_err(|err| format!("TLS: Failed to write {path}: {err}"))?;
+    std::io::Write::write_all(&mut file, content.as_bytes())
+        .map_err(|err| format!("TLS: Failed to write {path}: {err}"))
+}
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = fs::File::open(path).map_err(|err| format!("TLS: Failed to open {path}: {err}"))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("TLS: Failed to parse certificates from {path}: {err}"))?;
+    if certificates.is_empty() {
+        return Err(format!("TLS: No certificate found in {path}"));
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = fs::File::open(path).map_err(|err| format!("TLS: Failed to open {path}: {err}"))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|err| format!("TLS: Failed to parse private key from {path}: {err}"))?
+        .ok_or_else(|| format!("TLS: No private key found in {path}"))
+}
+
+// Certificate names for the generated certificate, the bind address is included when it is a specific one
+pub fn self_signed_hosts(server_address: &str) -> Vec<String> {
+    let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if let Some((host, _port)) = server_address.rsplit_once(':') {
+        if !host.is_empty() && host != "0.0.0.0" && !hosts.iter().any(|known| known == host) {
+            hosts.push(host.to_string());
+        }
+    }
+    hosts
+}
+
+pub fn server_config(
+    config: &TlsConfig,
+    server_address: &str,
+) -> Result<rustls::ServerConfig, String> {
+    let missing = !Path::new(&config.cert_path).exists() || !Path::new(&config.key_path).exists();
+    if missing && config.self_signed {
+        generate_self_signed(config, self_signed_hosts(server_address))?;
+    }
+
+    let certificates = load_certificates(&config.cert_path)?;
+    let key = load_private_key(&config.key_path)?;
+
+    // Explicit provider, so it doesn't depend on which one other crates enable as process default
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| format!("TLS: {err}"))?
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|err| format!("TLS: Invalid certificate or key: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_signed_config() {
+        let directory = std::env::temp_dir().join(format!("tls-{}", uuid::Uuid::new_v4()));
+        let config = TlsConfig {
+            cert_path: directory.join("cert.pem").display().to_string(),
+            key_path: directory.join("key.pem").display().to_string(),
+            self_signed: false,
+        };
+        assert!(server_config(&config, "0.0.0.0:8080").is_err());
+
+        let config = TlsConfig {
+            self_signed: true,
+            ..config
+        };
+        assert!(server_config(&config, "192.168.2.2:8080").is_ok());
+        let certificate = fs::read_to_string(&config.cert_path).unwrap();
+        // The existing certificate is kept on the next start
+        assert!(server_config(&config, "192.168.2.2:8080").is_ok());
+        assert_eq!(fs::read_to_string(&config.cert_path).unwrap(), certificate);
+
+        assert_eq!(
+            self_signed_hosts("192.168.2.2:8080"),
+            ["localhost", "127.0.0.1", "192.168.2.2"]
+        );
+        assert_eq!(
+            self_signed_hosts("0.0.0.0:8080"),
+            ["localhost", "127.0.0.1"]
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}